@@ -0,0 +1,354 @@
+use crate::git::{FileChange, Hunk};
+
+/// Files are never trimmed below this many tokens. When the budget cannot give
+/// every file at least this much, the diff is summarized file by file instead.
+const MIN_FILE_TOKENS: usize = 400;
+
+/// Rough token estimate (about four bytes per token for code and English).
+/// Good enough to keep prompts under a limit without a tokenizer per provider.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// List prices in US dollars per million input and output tokens, matched
+/// by model name prefix. They only feed the `--dry-run` estimate and go out
+/// of date; the provider's price page is authoritative.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-35-turbo", 0.5, 1.5),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4-1106", 10.0, 30.0),
+    ("gpt-4-0125", 10.0, 30.0),
+    ("gpt-4", 30.0, 60.0),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-opus", 15.0, 75.0),
+];
+
+/// Estimated price in US dollars of sending `input_tokens` to `model` and
+/// getting `output_tokens` back, or `None` for a model without a known price.
+pub fn estimate_cost(model: &str, input_tokens: usize, output_tokens: usize) -> Option<f64> {
+    let (_, input, output) = PRICES
+        .iter()
+        .filter(|(prefix, _, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())?;
+    Some((input_tokens as f64 * input + output_tokens as f64 * output) / 1_000_000.0)
+}
+
+/// A file whose diff had to be shortened to fit the budget.
+#[derive(Debug, Clone)]
+pub struct TrimNote {
+    pub path: String,
+    pub kept_lines: usize,
+    pub total_lines: usize,
+}
+
+impl std::fmt::Display for TrimNote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "trimmed {} ({} of {} diff lines kept)",
+            self.path, self.kept_lines, self.total_lines
+        )
+    }
+}
+
+/// How the staged diff will be sent to the model.
+pub enum DiffPlan {
+    /// The (possibly trimmed) diff fits in one prompt.
+    Single {
+        diff: String,
+        trimmed: Vec<TrimNote>,
+    },
+    /// Too many files for one prompt: each chunk is summarized separately and
+    /// the commit message is written from the summaries.
+    MapReduce {
+        chunks: Vec<String>,
+        trimmed: Vec<TrimNote>,
+    },
+}
+
+/// Fits `changes` into `max_tokens`, trimming the largest files first.
+pub fn plan_diff(changes: &[FileChange], max_tokens: usize) -> DiffPlan {
+    let rendered: Vec<String> = changes.iter().map(FileChange::to_prompt_text).collect();
+    let total: usize = rendered.iter().map(|text| estimate_tokens(text)).sum();
+    if total <= max_tokens {
+        return DiffPlan::Single {
+            diff: rendered.concat(),
+            trimmed: Vec::new(),
+        };
+    }
+
+    // Share the budget out smallest file first, so small files stay whole and
+    // what they leave unused goes to the larger ones.
+    let mut order: Vec<usize> = (0..changes.len()).collect();
+    order.sort_by_key(|&idx| estimate_tokens(&rendered[idx]));
+    let mut remaining = max_tokens;
+    let mut sections = vec![String::new(); changes.len()];
+    let mut trimmed = Vec::new();
+    for (position, &idx) in order.iter().enumerate() {
+        let share = remaining / (order.len() - position);
+        if estimate_tokens(&rendered[idx]) <= share {
+            sections[idx] = rendered[idx].clone();
+        } else if share >= MIN_FILE_TOKENS {
+            let (text, note) = render_within(&changes[idx], share);
+            trimmed.extend(note);
+            sections[idx] = text;
+        } else {
+            return map_reduce_plan(changes, max_tokens);
+        }
+        remaining = remaining.saturating_sub(estimate_tokens(&sections[idx]));
+    }
+
+    DiffPlan::Single {
+        diff: sections.concat(),
+        trimmed,
+    }
+}
+
+/// Gives each file up to a whole prompt, then packs files into chunks.
+fn map_reduce_plan(changes: &[FileChange], max_tokens: usize) -> DiffPlan {
+    let mut trimmed = Vec::new();
+    let mut texts = Vec::with_capacity(changes.len());
+    for change in changes {
+        let (text, note) = render_within(change, max_tokens);
+        trimmed.extend(note);
+        texts.push(text);
+    }
+    DiffPlan::MapReduce {
+        chunks: pack_chunks(&texts, "", max_tokens),
+        trimmed,
+    }
+}
+
+/// Joins consecutive `texts` with `separator` into as few chunks of at most
+/// `max_tokens` as their order allows. A text over the budget on its own
+/// gets a chunk of its own.
+pub fn pack_chunks(texts: &[String], separator: &str, max_tokens: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for text in texts {
+        if !current.is_empty() {
+            let joined = estimate_tokens(&current) + estimate_tokens(separator);
+            if joined + estimate_tokens(text) > max_tokens {
+                chunks.push(std::mem::take(&mut current));
+            } else {
+                current.push_str(separator);
+            }
+        }
+        current.push_str(text);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Cuts `text` after the last whole line that fits in `max_tokens`, noting
+/// how many lines were dropped. Returns `text` unchanged when it fits.
+pub fn truncate_to(text: &str, max_tokens: usize) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+    let lines: Vec<&str> = text.lines().collect();
+    let mut budget = max_tokens.saturating_sub(estimate_tokens("[... 0000 more lines cut ...]"));
+    let mut kept = 0;
+    for line in &lines {
+        let cost = estimate_tokens(line) + 1;
+        if cost > budget {
+            break;
+        }
+        budget -= cost;
+        kept += 1;
+    }
+    let mut output = lines[..kept].join("\n");
+    output.push_str(&format!(
+        "\n[... {} more lines cut ...]",
+        lines.len() - kept
+    ));
+    output
+}
+
+/// Renders `change` keeping as many leading hunk lines as fit in `max_tokens`.
+fn render_within(change: &FileChange, max_tokens: usize) -> (String, Option<TrimNote>) {
+    let full = change.to_prompt_text();
+    if estimate_tokens(&full) <= max_tokens {
+        return (full, None);
+    }
+
+    let total_lines: usize = change.hunks.iter().map(|hunk| hunk.lines.len()).sum();
+    let marker_cost = estimate_tokens(&trim_marker(total_lines));
+    let mut budget = max_tokens.saturating_sub(estimate_tokens(&change.render(&[])) + marker_cost);
+    let mut kept_lines = 0;
+    let mut hunks = Vec::new();
+    'hunks: for hunk in &change.hunks {
+        let header_cost = estimate_tokens(&hunk.header) + 1;
+        if header_cost > budget {
+            break;
+        }
+        budget -= header_cost;
+        let mut kept = Hunk {
+            header: hunk.header.clone(),
+            lines: Vec::new(),
+        };
+        for line in &hunk.lines {
+            let cost = estimate_tokens(line) + 1;
+            if cost > budget {
+                hunks.push(kept);
+                break 'hunks;
+            }
+            budget -= cost;
+            kept.lines.push(line.clone());
+            kept_lines += 1;
+        }
+        hunks.push(kept);
+    }
+
+    let mut text = change.render(&hunks);
+    text.push_str(&trim_marker(total_lines - kept_lines));
+    let note = TrimNote {
+        path: change.path.to_string_lossy().into_owned(),
+        kept_lines,
+        total_lines,
+    };
+    (text, Some(note))
+}
+
+fn trim_marker(lines: usize) -> String {
+    format!("[... {} more diff lines trimmed ...]\n", lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::git::ChangeStatus;
+
+    /// A modified file with one hunk of `lines` lines of `width` characters.
+    fn change(path: &str, lines: usize, width: usize) -> FileChange {
+        FileChange {
+            path: PathBuf::from(path),
+            old_path: None,
+            status: ChangeStatus::Modified,
+            hunks: vec![Hunk {
+                header: "@@ -1,1 +1,1 @@".to_string(),
+                lines: (0..lines)
+                    .map(|_| format!("+{}", "x".repeat(width)))
+                    .collect(),
+            }],
+            binary: false,
+            additions: lines,
+            deletions: 0,
+            mode_change: None,
+        }
+    }
+
+    #[test]
+    fn estimates_four_bytes_per_token() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+    }
+
+    #[test]
+    fn small_diffs_are_sent_whole() {
+        let changes = vec![change("a.rs", 5, 20), change("b.rs", 5, 20)];
+        match plan_diff(&changes, 1_000) {
+            DiffPlan::Single { diff, trimmed } => {
+                assert!(trimmed.is_empty());
+                assert_eq!(
+                    diff,
+                    changes[0].to_prompt_text() + &changes[1].to_prompt_text()
+                );
+            }
+            DiffPlan::MapReduce { .. } => panic!("expected a single prompt"),
+        }
+    }
+
+    #[test]
+    fn the_largest_file_is_trimmed_first() {
+        let small = change("small.rs", 5, 20);
+        let large = change("large.rs", 400, 40);
+        let changes = vec![large, small.clone()];
+        match plan_diff(&changes, 1_000) {
+            DiffPlan::Single { diff, trimmed } => {
+                assert_eq!(trimmed.len(), 1);
+                assert_eq!(trimmed[0].path, "large.rs");
+                assert_eq!(trimmed[0].total_lines, 400);
+                assert!(trimmed[0].kept_lines > 0 && trimmed[0].kept_lines < 400);
+                assert!(diff.contains(&small.to_prompt_text()));
+                assert!(diff.contains("more diff lines trimmed"));
+                assert!(estimate_tokens(&diff) <= 1_000);
+            }
+            DiffPlan::MapReduce { .. } => panic!("expected a trimmed single prompt"),
+        }
+    }
+
+    #[test]
+    fn too_many_large_files_are_summarized_in_chunks() {
+        let changes: Vec<FileChange> = (0..10)
+            .map(|idx| change(&format!("file{}.rs", idx), 80, 40))
+            .collect();
+        match plan_diff(&changes, 1_000) {
+            DiffPlan::MapReduce { chunks, trimmed } => {
+                assert!(chunks.len() > 1);
+                assert!(trimmed.is_empty());
+                assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 1_000));
+                let joined = chunks.concat();
+                for change in &changes {
+                    assert!(joined.contains(&change.to_prompt_text()));
+                }
+            }
+            DiffPlan::Single { .. } => panic!("expected map-reduce"),
+        }
+    }
+
+    #[test]
+    fn packs_in_order_and_keeps_oversized_texts_alone() {
+        let texts: Vec<String> = [
+            "a".repeat(40),
+            "b".repeat(40),
+            "c".repeat(200),
+            "d".repeat(4),
+        ]
+        .into_iter()
+        .collect();
+        let chunks = pack_chunks(&texts, "\n", 25);
+        assert_eq!(
+            chunks,
+            vec![
+                format!("{}\n{}", "a".repeat(40), "b".repeat(40)),
+                "c".repeat(200),
+                "d".repeat(4),
+            ]
+        );
+        assert!(pack_chunks(&[], "\n", 25).is_empty());
+    }
+
+    #[test]
+    fn truncates_at_whole_lines() {
+        assert_eq!(truncate_to("short", 10), "short");
+        let text: Vec<String> = (0..50)
+            .map(|idx| format!("- summary line {}", idx))
+            .collect();
+        let cut = truncate_to(&text.join("\n"), 60);
+        assert!(estimate_tokens(&cut) <= 60);
+        assert!(cut.starts_with("- summary line 0\n"));
+        let kept = cut.lines().count() - 1;
+        assert!(cut.ends_with(&format!("[... {} more lines cut ...]", 50 - kept)));
+    }
+
+    #[test]
+    fn prices_match_the_longest_model_prefix() {
+        let mini = estimate_cost("gpt-4o-mini-2024-07-18", 1_000_000, 0).unwrap();
+        assert!((mini - 0.15).abs() < 1e-9);
+        let full = estimate_cost("gpt-4o", 0, 1_000_000).unwrap();
+        assert!((full - 10.0).abs() < 1e-9);
+        assert_eq!(estimate_cost("mistral-large", 1_000, 1_000), None);
+    }
+}
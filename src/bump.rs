@@ -0,0 +1,579 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::conventional;
+
+/// How much a release has to raise the version, in increasing order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bump {
+    None,
+    Patch,
+    Minor,
+    Major,
+}
+
+impl Bump {
+    /// What one commit message requires: major for `!` or a `BREAKING
+    /// CHANGE` footer, minor for `feat`, patch for `fix` and `perf`.
+    pub fn of_message(message: &str) -> Bump {
+        match conventional::parse(message) {
+            Ok(commit) if commit.is_breaking() => Bump::Major,
+            Ok(commit) => match commit.commit_type.as_str() {
+                "feat" => Bump::Minor,
+                "fix" | "perf" => Bump::Patch,
+                _ => Bump::None,
+            },
+            Err(_) => Bump::None,
+        }
+    }
+}
+
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bump::None => write!(f, "none"),
+            Bump::Patch => write!(f, "patch"),
+            Bump::Minor => write!(f, "minor"),
+            Bump::Major => write!(f, "major"),
+        }
+    }
+}
+
+impl FromStr for Bump {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "major" => Ok(Bump::Major),
+            "minor" => Ok(Bump::Minor),
+            "patch" => Ok(Bump::Patch),
+            other => Err(format!(
+                "unknown bump `{}`, expected major, minor or patch",
+                other
+            )),
+        }
+    }
+}
+
+/// A semantic version. Build metadata is dropped when parsing, as it does
+/// not take part in precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Dot-separated pre-release identifiers, e.g. `["rc", "1"]`.
+    pub pre: Vec<String>,
+}
+
+impl Version {
+    pub const ZERO: Version = Version {
+        major: 0,
+        minor: 0,
+        patch: 0,
+        pre: Vec::new(),
+    };
+
+    /// Parses `1.2.3`, `v1.2.3` or `1.2.3-rc.1+build`.
+    pub fn parse(text: &str) -> Option<Version> {
+        let text = text.strip_prefix('v').unwrap_or(text);
+        let text = text.split_once('+').map_or(text, |(version, _)| version);
+        let (core, pre) = match text.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (text, None),
+        };
+        let mut numbers = core.split('.').map(|part| {
+            let leading_zero = part.len() > 1 && part.starts_with('0');
+            (!leading_zero).then(|| part.parse::<u64>().ok()).flatten()
+        });
+        let version = Version {
+            major: numbers.next()??,
+            minor: numbers.next()??,
+            patch: numbers.next()??,
+            pre: match pre {
+                Some(pre) => pre.split('.').map(str::to_string).collect(),
+                None => Vec::new(),
+            },
+        };
+        let valid_pre = version.pre.iter().all(|identifier| {
+            !identifier.is_empty()
+                && identifier
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+        (numbers.next().is_none() && valid_pre).then_some(version)
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+
+    /// The version without its pre-release part.
+    pub fn release(&self) -> Version {
+        Version {
+            pre: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    fn bumped(&self, bump: Bump) -> Version {
+        let (major, minor, patch) = (self.major, self.minor, self.patch);
+        let (major, minor, patch) = match bump {
+            Bump::Major => (major + 1, 0, 0),
+            Bump::Minor => (major, minor + 1, 0),
+            Bump::Patch => (major, minor, patch + 1),
+            Bump::None => (major, minor, patch),
+        };
+        Version {
+            major,
+            minor,
+            patch,
+            pre: Vec::new(),
+        }
+    }
+
+    /// `N` of a `<channel>.N` pre-release.
+    fn pre_number(&self, channel: &str) -> Option<u64> {
+        match self.pre.as_slice() {
+            [name, number] if name == channel => number.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if self.is_prerelease() {
+            write!(f, "-{}", self.pre.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    /// Semantic Versioning 2.0 precedence: a pre-release sorts before its
+    /// release, numeric identifiers before alphanumeric ones.
+    fn cmp(&self, other: &Self) -> Ordering {
+        let core =
+            (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch));
+        core.then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => {
+                for (a, b) in self.pre.iter().zip(&other.pre) {
+                    let order = match (a.parse::<u64>(), b.parse::<u64>()) {
+                        (Ok(a), Ok(b)) => a.cmp(&b),
+                        (Ok(_), Err(_)) => Ordering::Less,
+                        (Err(_), Ok(_)) => Ordering::Greater,
+                        (Err(_), Err(_)) => a.cmp(b),
+                    };
+                    if order != Ordering::Equal {
+                        return order;
+                    }
+                }
+                self.pre.len().cmp(&other.pre.len())
+            }
+        })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The version after `bump` from the last release `stable`. `latest` is the
+/// newest version tagged since, possibly a pre-release: a pending
+/// pre-release that already covers the bump keeps its number, so
+/// `1.3.0-rc.1` becomes `1.3.0-rc.2` with `channel` `rc` and `1.3.0`
+/// without a channel.
+pub fn next_version(
+    stable: &Version,
+    latest: &Version,
+    bump: Bump,
+    channel: Option<&str>,
+) -> Version {
+    let target = stable.bumped(bump);
+    let release = if latest.is_prerelease() && latest.release() >= target {
+        latest.release()
+    } else {
+        target
+    };
+    let Some(channel) = channel else {
+        return release;
+    };
+    let number = match latest.pre_number(channel) {
+        Some(number) if latest.release() == release => number + 1,
+        _ => 1,
+    };
+    Version {
+        pre: vec![channel.to_string(), number.to_string()],
+        ..release
+    }
+}
+
+/// Checks a `--pre` channel: one pre-release identifier of letters, digits
+/// and hyphens, not all digits, as `.N` is added to it.
+pub fn validate_channel(channel: &str) -> Result<(), String> {
+    let valid = !channel.is_empty()
+        && channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !channel.chars().all(|c| c.is_ascii_digit());
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` is not a pre-release channel, use letters, digits and hyphens such as rc or beta",
+            channel
+        ))
+    }
+}
+
+/// The version of `[package]`, or else of `[workspace.package]`, in a
+/// `Cargo.toml`.
+pub fn cargo_version(manifest: &str) -> Option<Version> {
+    let manifest: toml::Table = manifest.parse().ok()?;
+    let version = match manifest.get("package").and_then(|p| p.get("version")) {
+        Some(version) => version,
+        None => manifest.get("workspace")?.get("package")?.get("version")?,
+    };
+    Version::parse(version.as_str()?)
+}
+
+/// Sets the version of `[package]` (or `[workspace.package]`) in a
+/// `Cargo.toml`, keeping the rest of the file as it is. `None` when the
+/// file sets no version there, e.g. with `version.workspace = true`.
+pub fn set_cargo_version(manifest: &str, version: &Version) -> Option<String> {
+    let pattern = Regex::new(r#"^(\s*version\s*=\s*)"[^"]*"(.*)$"#).expect("valid regex");
+    let mut section = String::new();
+    let mut found = false;
+    let mut lines = Vec::new();
+    for line in manifest.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            section = trimmed.to_string();
+        }
+        let in_package = section == "[package]" || section == "[workspace.package]";
+        match pattern.captures(line) {
+            Some(captures) if in_package && !found => {
+                found = true;
+                lines.push(format!("{}\"{}\"{}", &captures[1], version, &captures[2]));
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    found.then(|| join_lines(manifest, &lines))
+}
+
+/// Sets the top-level `version` of a `package.json`, keeping the rest of
+/// the file as it is. `None` when it has none.
+pub fn set_package_json_version(manifest: &str, version: &Version) -> Option<String> {
+    let parsed: serde_json::Value = serde_json::from_str(manifest).ok()?;
+    let current = parsed.get("version")?.as_str()?;
+    let pattern = Regex::new(&format!(
+        r#""version"(\s*):(\s*)"{}""#,
+        regex::escape(current)
+    ))
+    .expect("valid regex");
+    pattern.is_match(manifest).then(|| {
+        pattern
+            .replace(manifest, |captures: &regex::Captures| {
+                format!(
+                    "\"version\"{}:{}\"{}\"",
+                    &captures[1], &captures[2], version
+                )
+            })
+            .into_owned()
+    })
+}
+
+/// Sets the version of the repository's own packages in a `Cargo.lock`:
+/// those without a `source` that are still at the version `manifest`, the
+/// `Cargo.toml` before the change, had. `None` when there are none.
+pub fn set_cargo_lock_version(lock: &str, manifest: &str, version: &Version) -> Option<String> {
+    let old = format!("version = \"{}\"", cargo_version(manifest)?);
+    let mut lines: Vec<String> = lock.lines().map(str::to_string).collect();
+    let starts: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.trim() == "[[package]]")
+        .map(|(idx, _)| idx)
+        .collect();
+    let mut found = false;
+    for (nth, &start) in starts.iter().enumerate() {
+        let end = starts.get(nth + 1).copied().unwrap_or(lines.len());
+        let block = start..end;
+        if lines[block.clone()]
+            .iter()
+            .any(|line| line.starts_with("source = "))
+        {
+            continue;
+        }
+        if let Some(idx) = block.into_iter().find(|&idx| lines[idx].trim() == old) {
+            lines[idx] = format!("version = \"{}\"", version);
+            found = true;
+        }
+    }
+    found.then(|| join_lines(lock, &lines))
+}
+
+/// Sets the project's version in a `package-lock.json`: the top-level
+/// `version` and the one of the root entry of `packages`, where they still
+/// match `manifest`, the `package.json` before the change.
+pub fn set_package_lock_version(lock: &str, manifest: &str, version: &Version) -> Option<String> {
+    let parsed: serde_json::Value = serde_json::from_str(manifest).ok()?;
+    let current = parsed.get("version")?.as_str()?;
+    let lock_version = serde_json::from_str::<serde_json::Value>(lock)
+        .ok()?
+        .get("version")?
+        .as_str()?
+        .to_string();
+    if lock_version != current {
+        return None;
+    }
+    let lock = set_package_json_version(lock, version)?;
+    // The root package: `"": { "name": ..., "version": ... }`.
+    let root = Regex::new(&format!(
+        r#"("":\s*\{{\s*(?:"[^"]*":\s*"[^"]*",\s*)*"version"\s*:\s*)"{}""#,
+        regex::escape(current)
+    ))
+    .expect("valid regex");
+    Some(
+        root.replace(&lock, |captures: &regex::Captures| {
+            format!("{}\"{}\"", &captures[1], version)
+        })
+        .into_owned(),
+    )
+}
+
+fn join_lines(original: &str, lines: &[String]) -> String {
+    let mut text = lines.join("\n");
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!(
+            version("v1.2.3"),
+            Version {
+                major: 1,
+                minor: 2,
+                patch: 3,
+                pre: Vec::new()
+            }
+        );
+        assert_eq!(version("1.3.0-rc.1+build.5").pre, ["rc", "1"]);
+        assert_eq!(version("1.3.0-rc.1").to_string(), "1.3.0-rc.1");
+        for invalid in [
+            "1.2",
+            "1.2.3.4",
+            "01.2.3",
+            "1.2.x",
+            "1.2.3-",
+            "1.2.3-rc..1",
+            "deploy-7",
+        ] {
+            assert_eq!(Version::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn orders_by_semver_precedence() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(
+                version(pair[0]) < version(pair[1]),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn bumps_from_the_last_stable_release() {
+        let stable = version("1.2.3");
+        assert_eq!(
+            next_version(&stable, &stable, Bump::Patch, None),
+            version("1.2.4")
+        );
+        assert_eq!(
+            next_version(&stable, &stable, Bump::Minor, None),
+            version("1.3.0")
+        );
+        assert_eq!(
+            next_version(&stable, &stable, Bump::Major, None),
+            version("2.0.0")
+        );
+        assert_eq!(
+            next_version(&stable, &stable, Bump::Minor, Some("rc")),
+            version("1.3.0-rc.1")
+        );
+    }
+
+    #[test]
+    fn continues_a_pending_prerelease() {
+        let stable = version("1.2.3");
+        let rc = version("1.3.0-rc.1");
+        assert_eq!(
+            next_version(&stable, &rc, Bump::Minor, Some("rc")),
+            version("1.3.0-rc.2")
+        );
+        // A fix alone does not move a pending minor release back.
+        assert_eq!(
+            next_version(&stable, &rc, Bump::Patch, Some("rc")),
+            version("1.3.0-rc.2")
+        );
+        assert_eq!(
+            next_version(&stable, &rc, Bump::Minor, None),
+            version("1.3.0")
+        );
+        assert_eq!(
+            next_version(&stable, &version("1.3.0-rc.11"), Bump::Minor, Some("rc")),
+            version("1.3.0-rc.12")
+        );
+    }
+
+    #[test]
+    fn switching_channel_or_bumping_past_an_rc_starts_over() {
+        let stable = version("1.2.3");
+        let rc = version("1.3.0-rc.2");
+        assert_eq!(
+            next_version(&stable, &rc, Bump::Minor, Some("beta")),
+            version("1.3.0-beta.1")
+        );
+        assert_eq!(
+            next_version(&stable, &rc, Bump::Major, Some("rc")),
+            version("2.0.0-rc.1")
+        );
+        assert_eq!(
+            next_version(&stable, &rc, Bump::Major, None),
+            version("2.0.0")
+        );
+    }
+
+    #[test]
+    fn validates_channels() {
+        assert!(validate_channel("rc").is_ok());
+        assert!(validate_channel("pre-release2").is_ok());
+        for invalid in ["", "rc 1", "rc.1", "7", "beta+1"] {
+            assert!(validate_channel(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn classifies_commits() {
+        assert_eq!(Bump::of_message("feat: a"), Bump::Minor);
+        assert_eq!(Bump::of_message("perf: a"), Bump::Patch);
+        assert_eq!(Bump::of_message("docs: a"), Bump::None);
+        assert_eq!(Bump::of_message("fix!: a"), Bump::Major);
+        assert_eq!(
+            Bump::of_message("fix: a\n\nBREAKING CHANGE: b"),
+            Bump::Major
+        );
+        assert_eq!(Bump::of_message("Update readme"), Bump::None);
+    }
+
+    #[test]
+    fn sets_the_package_version_in_cargo_toml() {
+        let manifest = "[package]\nname = \"demo\"\nversion = \"0.1.0\" # keep\n\n\
+                        [dependencies]\nserde = { version = \"1\" }\nversion = \"9\"\n";
+        assert_eq!(
+            set_cargo_version(manifest, &version("0.2.0")).unwrap(),
+            manifest.replacen("\"0.1.0\"", "\"0.2.0\"", 1)
+        );
+        assert_eq!(cargo_version(manifest), Some(version("0.1.0")));
+    }
+
+    #[test]
+    fn sets_the_workspace_version_and_skips_inherited_ones() {
+        let workspace =
+            "[workspace]\nmembers = [\"a\"]\n\n[workspace.package]\nversion = \"1.0.0\"\n";
+        let updated = set_cargo_version(workspace, &version("1.1.0")).unwrap();
+        assert!(updated.ends_with("[workspace.package]\nversion = \"1.1.0\"\n"));
+        assert_eq!(cargo_version(workspace), Some(version("1.0.0")));
+
+        let member = "[package]\nname = \"a\"\nversion.workspace = true\n";
+        assert_eq!(set_cargo_version(member, &version("1.1.0")), None);
+    }
+
+    #[test]
+    fn sets_local_packages_in_cargo_lock() {
+        let manifest = "[workspace.package]\nversion = \"1.0.0\"\n";
+        let lock = "version = 3\n\n[[package]]\nname = \"a\"\nversion = \"1.0.0\"\n\n\
+                    [[package]]\nname = \"b\"\nversion = \"1.0.0\"\n\n\
+                    [[package]]\nname = \"dep\"\nversion = \"1.0.0\"\n\
+                    source = \"registry+https://github.com/rust-lang/crates.io-index\"\n\n\
+                    [[package]]\nname = \"old\"\nversion = \"0.9.0\"\n";
+        let updated = set_cargo_lock_version(lock, manifest, &version("1.1.0")).unwrap();
+        assert_eq!(updated.matches("version = \"1.1.0\"").count(), 2);
+        assert!(updated.contains("name = \"dep\"\nversion = \"1.0.0\""));
+        assert!(updated.contains("name = \"old\"\nversion = \"0.9.0\""));
+        assert_eq!(
+            set_cargo_lock_version(lock, "[package]\nversion = \"5.0.0\"\n", &version("5.1.0")),
+            None
+        );
+    }
+
+    #[test]
+    fn sets_the_top_level_version_in_package_json() {
+        let manifest = "{\n  \"name\": \"demo\",\n  \"version\" : \"0.1.0\",\n  \
+                        \"dependencies\": { \"x\": { \"version\": \"0.1.0\" } }\n}\n";
+        let updated = set_package_json_version(manifest, &version("0.2.0")).unwrap();
+        assert!(updated.contains("\"version\" : \"0.2.0\""));
+        assert!(updated.contains("{ \"version\": \"0.1.0\" }"));
+        assert_eq!(
+            set_package_json_version("{\"name\": \"x\"}", &version("1.0.0")),
+            None
+        );
+    }
+
+    #[test]
+    fn sets_the_root_package_in_package_lock() {
+        let manifest = "{\"name\": \"demo\", \"version\": \"0.1.0\"}";
+        let lock = "{\n  \"name\": \"demo\",\n  \"version\": \"0.1.0\",\n  \"packages\": {\n    \
+                    \"\": {\n      \"name\": \"demo\",\n      \"version\": \"0.1.0\"\n    },\n    \
+                    \"node_modules/x\": {\n      \"version\": \"0.1.0\"\n    }\n  }\n}\n";
+        let updated = set_package_lock_version(lock, manifest, &version("0.2.0")).unwrap();
+        assert_eq!(updated.matches("\"0.2.0\"").count(), 2);
+        assert!(updated.contains("\"node_modules/x\": {\n      \"version\": \"0.1.0\""));
+        let stale = lock.replace(
+            "\"version\": \"0.1.0\",\n  \"packages\"",
+            "\"version\": \"0.0.9\",\n  \"packages\"",
+        );
+        assert_eq!(
+            set_package_lock_version(&stale, manifest, &version("0.2.0")),
+            None
+        );
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::conventional;
+
+/// Section titles by commit type, in the order they are printed.
+const SECTIONS: &[(&str, &str)] = &[
+    ("feat", "Features"),
+    ("fix", "Bug Fixes"),
+    ("perf", "Performance Improvements"),
+    ("revert", "Reverts"),
+    ("docs", "Documentation"),
+    ("refactor", "Code Refactoring"),
+    ("style", "Styles"),
+    ("test", "Tests"),
+    ("build", "Build System"),
+    ("ci", "Continuous Integration"),
+    ("chore", "Chores"),
+];
+
+/// Types that rarely matter to users, left out unless `--all` is given.
+const INTERNAL_TYPES: &[&str] = &["style", "test", "build", "ci", "chore"];
+
+const BREAKING_SECTION: &str = "Breaking Changes";
+/// Where commits that are not Conventional Commits go with `--all`.
+const OTHER_SECTION: &str = "Other Changes";
+
+const KEEP_A_CHANGELOG_HEADER: &str = "# Changelog
+
+All notable changes to this project will be documented in this file.
+
+The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/).
+";
+
+/// The instructions sent with the numbered changes for `--rewrite`.
+const REWRITE_PROMPT: &str = r#"Rewrite each change below as a release note for the users of the project, in {language}.
+Each note is one sentence in the present tense that says what changed for them, without commit types, scopes, hashes or trailing period.
+Reply with only a JSON array of {count} strings, one per change and in the same order, and nothing else.
+
+Changes:
+{changes}"#;
+
+/// One commit as it appears in the changelog.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    /// Abbreviated commit id.
+    pub commit: String,
+    /// `None` for commits that are not Conventional Commits.
+    #[serde(rename = "type")]
+    pub commit_type: Option<String>,
+    pub scope: Option<String>,
+    pub subject: String,
+    /// The release note written by the model with `--rewrite`.
+    pub note: Option<String>,
+    /// What breaks: the `BREAKING CHANGE` footer, or the subject for a
+    /// header marked with `!`.
+    pub breaking: Option<String>,
+    #[serde(skip)]
+    body: Option<String>,
+}
+
+impl Entry {
+    fn text(&self) -> &str {
+        self.note.as_deref().unwrap_or(&self.subject)
+    }
+}
+
+/// The changes of one release, grouped for printing.
+#[derive(Debug, Clone)]
+pub struct Changelog {
+    /// `None` for the unreleased changes.
+    pub version: Option<String>,
+    /// `YYYY-MM-DD`, set along with `version`.
+    pub date: Option<String>,
+    pub entries: Vec<Entry>,
+    /// Commits left out: not conventional, or of an internal type.
+    pub skipped: usize,
+}
+
+/// A titled group of entries.
+#[derive(Debug, Clone, Serialize)]
+pub struct Section<'a> {
+    pub title: String,
+    pub entries: Vec<&'a Entry>,
+}
+
+/// Splits `<from>..<to>` into its ends. `<from>..` and a single revision
+/// mean up to `HEAD`, and `..<to>` the whole history up to `<to>`.
+pub fn parse_range(spec: &str) -> (Option<String>, String) {
+    let (from, to) = spec.split_once("..").unwrap_or((spec, ""));
+    let from = (!from.is_empty()).then(|| from.to_string());
+    let to = if to.is_empty() { "HEAD" } else { to };
+    (from, to.to_string())
+}
+
+impl Changelog {
+    /// Parses `commits`, given as id and message, newest first. Commits of
+    /// an internal type, and those that are not Conventional Commits, are
+    /// only kept with `include_all`.
+    pub fn new(
+        version: Option<String>,
+        date: Option<String>,
+        commits: &[(String, String)],
+        include_all: bool,
+    ) -> Self {
+        let mut entries = Vec::new();
+        let mut skipped = 0;
+        for (commit, message) in commits {
+            let entry = match conventional::parse(message) {
+                Ok(parsed) => {
+                    let breaking = parsed.is_breaking().then(|| {
+                        parsed
+                            .footers
+                            .iter()
+                            .find(|footer| footer.token.starts_with("BREAKING"))
+                            .map_or_else(|| parsed.subject.clone(), |footer| footer.value.clone())
+                    });
+                    let internal = INTERNAL_TYPES.contains(&parsed.commit_type.as_str());
+                    if internal && breaking.is_none() && !include_all {
+                        skipped += 1;
+                        continue;
+                    }
+                    Entry {
+                        commit: commit.clone(),
+                        commit_type: Some(parsed.commit_type),
+                        scope: parsed.scope,
+                        subject: parsed.subject,
+                        note: None,
+                        breaking,
+                        body: parsed.body,
+                    }
+                }
+                Err(_) if include_all => Entry {
+                    commit: commit.clone(),
+                    commit_type: None,
+                    scope: None,
+                    subject: message.lines().next().unwrap_or_default().to_string(),
+                    note: None,
+                    breaking: None,
+                    body: None,
+                },
+                Err(_) => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            entries.push(entry);
+        }
+        Changelog {
+            version,
+            date,
+            entries,
+            skipped,
+        }
+    }
+
+    /// Breaking changes first, then one section per type in the order of
+    /// [`SECTIONS`], then types of the configuration that have no title of
+    /// their own, then commits that are not Conventional Commits.
+    pub fn sections(&self) -> Vec<Section<'_>> {
+        let mut sections = Vec::new();
+        let breaking: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.breaking.is_some())
+            .collect();
+        if !breaking.is_empty() {
+            sections.push(Section {
+                title: BREAKING_SECTION.to_string(),
+                entries: breaking,
+            });
+        }
+        let mut by_type: BTreeMap<Option<&str>, Vec<&Entry>> = BTreeMap::new();
+        for entry in &self.entries {
+            by_type
+                .entry(entry.commit_type.as_deref())
+                .or_default()
+                .push(entry);
+        }
+        for (commit_type, title) in SECTIONS {
+            if let Some(entries) = by_type.remove(&Some(*commit_type)) {
+                sections.push(Section {
+                    title: title.to_string(),
+                    entries,
+                });
+            }
+        }
+        let other = by_type.remove(&None);
+        for (commit_type, entries) in by_type {
+            sections.push(Section {
+                title: capitalize(commit_type.unwrap_or_default()),
+                entries,
+            });
+        }
+        if let Some(entries) = other {
+            sections.push(Section {
+                title: OTHER_SECTION.to_string(),
+                entries,
+            });
+        }
+        sections
+    }
+
+    /// The release as a Keep a Changelog block: a `## [version] - date`
+    /// heading and a `###` heading per section.
+    pub fn to_markdown(&self) -> String {
+        let mut output = match (&self.version, &self.date) {
+            (Some(version), Some(date)) => format!("## [{}] - {}\n", version, date),
+            (Some(version), None) => format!("## [{}]\n", version),
+            (None, _) => "## [Unreleased]\n".to_string(),
+        };
+        for section in self.sections() {
+            output.push_str(&format!("\n### {}\n\n", section.title));
+            let breaking = section.title == BREAKING_SECTION;
+            for entry in section.entries {
+                let text = match (&entry.breaking, breaking) {
+                    (Some(note), true) => note.as_str(),
+                    _ => entry.text(),
+                };
+                match &entry.scope {
+                    Some(scope) => output.push_str(&format!("- **{}:** {}", scope, text)),
+                    None => output.push_str(&format!("- {}", text)),
+                }
+                output.push_str(&format!(" ({})\n", entry.commit));
+            }
+        }
+        output
+    }
+
+    /// Puts this release at the top of `existing`, a Keep a Changelog file,
+    /// below its title and introduction. An `[Unreleased]` section there is
+    /// replaced, as its changes are the ones being written.
+    pub fn prepend_to(&self, existing: &str) -> String {
+        let block = self.to_markdown();
+        if existing.trim().is_empty() {
+            return format!("{}\n{}", KEEP_A_CHANGELOG_HEADER, block);
+        }
+        let lines: Vec<&str> = existing.lines().collect();
+        let is_release = |line: &&str| line.starts_with("## ");
+        let insert_at = lines.iter().position(is_release).unwrap_or(lines.len());
+        let unreleased = lines
+            .get(insert_at)
+            .is_some_and(|line| line.to_lowercase().starts_with("## [unreleased]"));
+        let replace_to = if unreleased {
+            lines[insert_at + 1..]
+                .iter()
+                .position(is_release)
+                .map_or(lines.len(), |idx| insert_at + 1 + idx)
+        } else {
+            insert_at
+        };
+
+        let mut output = lines[..insert_at].join("\n").trim_end().to_string();
+        if !output.is_empty() {
+            output.push_str("\n\n");
+        }
+        output.push_str(&block);
+        if replace_to < lines.len() {
+            output.push('\n');
+            output.push_str(&lines[replace_to..].join("\n"));
+            output.push('\n');
+        }
+        output
+    }
+
+    /// The prompt asking the model to turn the subjects into release notes.
+    pub fn rewrite_prompt(&self, language: &str) -> String {
+        let changes: Vec<String> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| {
+                let mut change = format!("[{}] ", idx + 1);
+                if let Some(commit_type) = &entry.commit_type {
+                    change.push_str(commit_type);
+                    if let Some(scope) = &entry.scope {
+                        change.push_str(&format!("({})", scope));
+                    }
+                    change.push_str(": ");
+                }
+                change.push_str(&entry.subject);
+                if let Some(body) = &entry.body {
+                    change.push_str(&format!("\n    {}", body.replace('\n', "\n    ")));
+                }
+                change
+            })
+            .collect();
+        REWRITE_PROMPT
+            .replace("{language}", language)
+            .replace("{count}", &self.entries.len().to_string())
+            .replace("{changes}", &changes.join("\n"))
+    }
+
+    /// Takes the notes from the model's reply to [`Changelog::rewrite_prompt`].
+    pub fn apply_rewrite(&mut self, answer: &str) -> Result<(), String> {
+        let notes: Vec<String> = serde_json::from_str(answer)
+            .map_err(|err| format!("the reply is not a JSON array of strings: {}", err))?;
+        if notes.len() != self.entries.len() {
+            return Err(format!(
+                "expected {} notes, one per change, but got {}",
+                self.entries.len(),
+                notes.len()
+            ));
+        }
+        if let Some(position) = notes.iter().position(|note| note.trim().is_empty()) {
+            return Err(format!("note {} is empty", position + 1));
+        }
+        for (entry, note) in self.entries.iter_mut().zip(notes) {
+            entry.note = Some(note.trim().to_string());
+        }
+        Ok(())
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// The calendar date, `YYYY-MM-DD`, of a git timestamp in its own time zone.
+pub fn format_date(seconds: i64, offset_minutes: i32) -> String {
+    // Howard Hinnant's days-to-civil algorithm.
+    let days = (seconds + i64::from(offset_minutes) * 60).div_euclid(86_400);
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commits(messages: &[&str]) -> Vec<(String, String)> {
+        messages
+            .iter()
+            .enumerate()
+            .map(|(idx, message)| (format!("c{}", idx), message.to_string()))
+            .collect()
+    }
+
+    fn release(messages: &[&str]) -> Changelog {
+        Changelog::new(
+            Some("1.2.0".to_string()),
+            Some("2024-05-01".to_string()),
+            &commits(messages),
+            false,
+        )
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let range = |from: Option<&str>, to: &str| (from.map(str::to_string), to.to_string());
+        assert_eq!(
+            parse_range("v1.0.0..v1.1.0"),
+            range(Some("v1.0.0"), "v1.1.0")
+        );
+        assert_eq!(parse_range("v1.0.0.."), range(Some("v1.0.0"), "HEAD"));
+        assert_eq!(parse_range("v1.0.0"), range(Some("v1.0.0"), "HEAD"));
+        assert_eq!(parse_range("..v1.1.0"), range(None, "v1.1.0"));
+    }
+
+    #[test]
+    fn leaves_out_internal_and_unconventional_commits() {
+        let changelog = release(&[
+            "feat: add export",
+            "chore: bump deps",
+            "ci!: drop the old runner\n\nBREAKING CHANGE: builds need Node 20",
+            "Merge remote-tracking branch",
+        ]);
+        let subjects: Vec<&str> = changelog
+            .entries
+            .iter()
+            .map(|e| e.subject.as_str())
+            .collect();
+        assert_eq!(subjects, ["add export", "drop the old runner"]);
+        assert_eq!(changelog.skipped, 2);
+        assert_eq!(
+            changelog.entries[1].breaking.as_deref(),
+            Some("builds need Node 20")
+        );
+
+        let all = Changelog::new(None, None, &commits(&["chore: x", "Update things"]), true);
+        assert_eq!(all.skipped, 0);
+        assert_eq!(all.entries[1].commit_type, None);
+        assert_eq!(all.entries[1].subject, "Update things");
+    }
+
+    #[test]
+    fn breaking_header_without_footer_uses_the_subject() {
+        let changelog = release(&["feat(api)!: remove v1 routes"]);
+        assert_eq!(
+            changelog.entries[0].breaking.as_deref(),
+            Some("remove v1 routes")
+        );
+    }
+
+    #[test]
+    fn orders_sections() {
+        let changelog = Changelog::new(
+            None,
+            None,
+            &commits(&[
+                "docs: explain setup",
+                "security: rotate keys",
+                "Tidy up",
+                "fix: handle empty input",
+                "feat!: new config format",
+                "perf: cache lookups",
+                "feat: add export",
+            ]),
+            true,
+        );
+        let titles: Vec<String> = changelog.sections().into_iter().map(|s| s.title).collect();
+        assert_eq!(
+            titles,
+            [
+                "Breaking Changes",
+                "Features",
+                "Bug Fixes",
+                "Performance Improvements",
+                "Documentation",
+                "Security",
+                "Other Changes"
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_markdown() {
+        let changelog = release(&[
+            "feat(cli): add --json\n\nBREAKING CHANGE: output is JSON by default",
+            "fix: handle empty input",
+        ]);
+        assert_eq!(
+            changelog.to_markdown(),
+            "## [1.2.0] - 2024-05-01\n\n\
+             ### Breaking Changes\n\n\
+             - **cli:** output is JSON by default (c0)\n\n\
+             ### Features\n\n\
+             - **cli:** add --json (c0)\n\n\
+             ### Bug Fixes\n\n\
+             - handle empty input (c1)\n"
+        );
+        let unreleased = Changelog::new(None, None, &commits(&["fix: a"]), false);
+        assert!(unreleased.to_markdown().starts_with("## [Unreleased]\n"));
+    }
+
+    #[test]
+    fn prepends_to_an_empty_file_with_a_header() {
+        let output = release(&["fix: a"]).prepend_to("");
+        assert!(output.starts_with(KEEP_A_CHANGELOG_HEADER));
+        assert!(output.ends_with("## [1.2.0] - 2024-05-01\n\n### Bug Fixes\n\n- a (c0)\n"));
+    }
+
+    #[test]
+    fn prepends_below_the_header_and_replaces_unreleased() {
+        let existing = "# Changelog\n\nIntro.\n\n## [Unreleased]\n\n### Features\n\n- old (x)\n\n\
+                        ## [1.1.0] - 2024-01-01\n\n- older (y)\n";
+        assert_eq!(
+            release(&["fix: a"]).prepend_to(existing),
+            "# Changelog\n\nIntro.\n\n## [1.2.0] - 2024-05-01\n\n### Bug Fixes\n\n- a (c0)\n\n\
+             ## [1.1.0] - 2024-01-01\n\n- older (y)\n"
+        );
+    }
+
+    #[test]
+    fn prepends_above_the_latest_release() {
+        let existing = "# Changelog\n\n## [1.1.0] - 2024-01-01\n\n- older (y)\n";
+        assert_eq!(
+            release(&["fix: a"]).prepend_to(existing),
+            "# Changelog\n\n## [1.2.0] - 2024-05-01\n\n### Bug Fixes\n\n- a (c0)\n\n\
+             ## [1.1.0] - 2024-01-01\n\n- older (y)\n"
+        );
+        assert_eq!(
+            release(&["fix: a"]).prepend_to("# Changelog\n"),
+            "# Changelog\n\n## [1.2.0] - 2024-05-01\n\n### Bug Fixes\n\n- a (c0)\n"
+        );
+    }
+
+    #[test]
+    fn applies_rewritten_notes() {
+        let mut changelog = release(&["feat: add export", "fix: handle empty input"]);
+        assert!(changelog.apply_rewrite("not json").is_err());
+        assert!(changelog.apply_rewrite(r#"["only one"]"#).is_err());
+        assert_eq!(
+            changelog.apply_rewrite(r#"["You can export", "  "]"#),
+            Err("note 2 is empty".to_string())
+        );
+        assert!(changelog.entries.iter().all(|entry| entry.note.is_none()));
+
+        changelog
+            .apply_rewrite(r#"["You can export reports", " Empty input no longer fails "]"#)
+            .unwrap();
+        let markdown = changelog.to_markdown();
+        assert!(markdown.contains("- You can export reports (c0)"));
+        assert!(markdown.contains("- Empty input no longer fails (c1)"));
+    }
+
+    #[test]
+    fn formats_dates_in_the_committer_time_zone() {
+        assert_eq!(format_date(0, 0), "1970-01-01");
+        assert_eq!(format_date(1_700_000_000, 0), "2023-11-14");
+        assert_eq!(format_date(1_700_000_000, 120), "2023-11-15");
+        assert_eq!(format_date(951_825_600, 0), "2000-02-29");
+    }
+}
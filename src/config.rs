@@ -0,0 +1,851 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::conventional;
+use crate::provider;
+use crate::secrets::SecretPolicy;
+
+/// Name of the repository-level configuration file.
+pub const REPO_CONFIG_NAME: &str = ".rcommit.toml";
+const DEFAULT_LANGUAGE: &str = "English";
+const DEFAULT_MAX_TOKENS: usize = 12_000;
+/// Branches that shared history usually lives on.
+const DEFAULT_PROTECTED_BRANCHES: &[&str] = &["main", "master", "develop", "release/*"];
+
+/// Where an effective setting came from, lowest precedence first.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            source: Source::Default,
+        }
+    }
+
+    /// Replaces the value when a higher-precedence layer provides one.
+    fn layer(&mut self, value: Option<T>, source: Source) {
+        if let Some(value) = value {
+            self.value = value;
+            self.source = source;
+        }
+    }
+}
+
+/// What happens to the accepted message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputMode {
+    Clipboard,
+    Git,
+    Commit,
+    Stdout,
+    File(PathBuf),
+    Json,
+}
+
+impl FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "clipboard" => Ok(OutputMode::Clipboard),
+            "git" => Ok(OutputMode::Git),
+            "commit" => Ok(OutputMode::Commit),
+            "stdout" => Ok(OutputMode::Stdout),
+            "json" => Ok(OutputMode::Json),
+            other => match other.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(OutputMode::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "unknown output mode `{}` (expected stdout, clipboard, file:<path>, json, \
+                     git or commit)",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for OutputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputMode::Clipboard => write!(f, "clipboard"),
+            OutputMode::Git => write!(f, "git"),
+            OutputMode::Commit => write!(f, "commit"),
+            OutputMode::Stdout => write!(f, "stdout"),
+            OutputMode::File(path) => write!(f, "file:{}", path.display()),
+            OutputMode::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// The keys accepted in `config.toml` and `.rcommit.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    provider: Option<String>,
+    model: Option<String>,
+    exclude: Option<Vec<String>>,
+    types: Option<Vec<String>>,
+    scopes: Option<Vec<String>>,
+    language: Option<String>,
+    output: Option<String>,
+    template: Option<PathBuf>,
+    max_tokens: Option<usize>,
+    style_from_history: Option<usize>,
+    style_author_only: Option<bool>,
+    style_main_branch: Option<bool>,
+    scope_rules: Option<BTreeMap<String, String>>,
+    workspace_scopes: Option<bool>,
+    secrets: Option<String>,
+    offline: Option<bool>,
+    pr_base: Option<String>,
+    protected_branches: Option<Vec<String>>,
+}
+
+/// Effective settings after merging, in increasing precedence: built-in
+/// defaults, the user config file, `.rcommit.toml` at the repository root,
+/// `RCOMMIT_*` environment variables and command-line flags. Each layer
+/// replaces a value wholesale; lists are not concatenated.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub provider: Setting<String>,
+    pub model: Setting<Option<String>>,
+    pub exclude: Setting<Vec<String>>,
+    pub types: Setting<Vec<String>>,
+    pub scopes: Setting<Vec<String>>,
+    pub language: Setting<String>,
+    pub output: Setting<OutputMode>,
+    pub template: Setting<Option<PathBuf>>,
+    pub max_tokens: Setting<usize>,
+    /// How many past commits to learn the message style from; 0 turns it off.
+    pub style_from_history: Setting<usize>,
+    pub style_author_only: Setting<bool>,
+    pub style_main_branch: Setting<bool>,
+    /// Path prefix to scope, e.g. `services/api` -> `api`.
+    pub scope_rules: Setting<Vec<(String, String)>>,
+    /// Whether workspace members found in manifests become scopes too.
+    pub workspace_scopes: Setting<bool>,
+    /// What happens when the staged diff seems to contain secrets.
+    pub secrets: Setting<SecretPolicy>,
+    /// Writes messages with the built-in heuristics instead of a model.
+    pub offline: Setting<bool>,
+    /// Branch `rcommit pr` compares against; detected when unset.
+    pub pr_base: Setting<Option<String>>,
+    /// Branches whose commits `rcommit reword` leaves alone once pushed;
+    /// a trailing `*` matches any suffix.
+    pub protected_branches: Setting<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            provider: Setting::new(provider::DEFAULT_PROVIDER.to_string()),
+            model: Setting::new(None),
+            exclude: Setting::new(Vec::new()),
+            types: Setting::new(
+                conventional::DEFAULT_TYPES
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect(),
+            ),
+            scopes: Setting::new(Vec::new()),
+            language: Setting::new(DEFAULT_LANGUAGE.to_string()),
+            output: Setting::new(OutputMode::Clipboard),
+            template: Setting::new(None),
+            max_tokens: Setting::new(DEFAULT_MAX_TOKENS),
+            style_from_history: Setting::new(0),
+            style_author_only: Setting::new(false),
+            style_main_branch: Setting::new(false),
+            scope_rules: Setting::new(Vec::new()),
+            workspace_scopes: Setting::new(true),
+            secrets: Setting::new(SecretPolicy::Redact),
+            offline: Setting::new(false),
+            pr_base: Setting::new(None),
+            protected_branches: Setting::new(
+                DEFAULT_PROTECTED_BRANCHES
+                    .iter()
+                    .map(|branch| branch.to_string())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Config {
+    /// Loads every layer. `repo_root` is `None` outside a repository, in
+    /// which case `.rcommit.toml` is skipped.
+    pub fn load(repo_root: Option<&Path>, matches: &ArgMatches) -> io::Result<Self> {
+        let mut config = Config::default();
+        if let Some(path) = user_config_path() {
+            config.apply_file(&path)?;
+        }
+        if let Some(root) = repo_root {
+            config.apply_file(&root.join(REPO_CONFIG_NAME))?;
+        }
+        config.apply_env()?;
+        config.apply_cli(matches);
+        config.check_provider()?;
+        Ok(config)
+    }
+
+    pub fn type_names(&self) -> Vec<&str> {
+        self.types.value.iter().map(String::as_str).collect()
+    }
+
+    pub fn scope_names(&self) -> Vec<&str> {
+        self.scopes.value.iter().map(String::as_str).collect()
+    }
+
+    pub fn exclude_patterns(&self) -> Vec<&str> {
+        self.exclude.value.iter().map(String::as_str).collect()
+    }
+
+    /// Whether `branch`, without its remote, is one of `protected-branches`.
+    pub fn is_protected_branch(&self, branch: &str) -> bool {
+        self.protected_branches
+            .value
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => branch.starts_with(prefix),
+                None => branch == pattern,
+            })
+    }
+
+    /// `--provider` is checked by clap; the files and `RCOMMIT_PROVIDER` are
+    /// checked here, naming the layer that set the value.
+    fn check_provider(&self) -> io::Result<()> {
+        if provider::find_provider(&self.provider.value).is_some() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: unknown provider `{}`, expected one of {}",
+                self.provider.source,
+                self.provider.value,
+                provider::provider_names().join(", ")
+            ),
+        ))
+    }
+
+    fn apply_file(&mut self, path: &Path) -> io::Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let text = fs::read_to_string(path)?;
+        let file: ConfigFile = toml::from_str(&text).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })?;
+        let source = || Source::File(path.to_path_buf());
+        // Relative template paths are resolved next to the file naming them.
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        self.provider.layer(file.provider, source());
+        self.model.layer(file.model.map(Some), source());
+        self.exclude.layer(file.exclude, source());
+        self.types.layer(file.types, source());
+        self.scopes.layer(file.scopes, source());
+        self.language.layer(file.language, source());
+        self.output.layer(
+            file.output
+                .map(|value| parse_value(&value, &path.display().to_string()))
+                .transpose()?,
+            source(),
+        );
+        self.template
+            .layer(file.template.map(|path| Some(base.join(path))), source());
+        self.max_tokens.layer(file.max_tokens, source());
+        self.style_from_history
+            .layer(file.style_from_history, source());
+        self.style_author_only
+            .layer(file.style_author_only, source());
+        self.style_main_branch
+            .layer(file.style_main_branch, source());
+        self.scope_rules.layer(
+            file.scope_rules.map(|rules| rules.into_iter().collect()),
+            source(),
+        );
+        self.workspace_scopes.layer(file.workspace_scopes, source());
+        self.secrets.layer(
+            file.secrets
+                .map(|value| parse_value(&value, &path.display().to_string()))
+                .transpose()?,
+            source(),
+        );
+        self.offline.layer(file.offline, source());
+        self.pr_base.layer(file.pr_base.map(Some), source());
+        self.protected_branches
+            .layer(file.protected_branches, source());
+        Ok(())
+    }
+
+    fn apply_env(&mut self) -> io::Result<()> {
+        self.provider
+            .layer(env_var("RCOMMIT_PROVIDER"), Source::Env("RCOMMIT_PROVIDER"));
+        self.model.layer(
+            env_var("RCOMMIT_MODEL").map(Some),
+            Source::Env("RCOMMIT_MODEL"),
+        );
+        self.exclude
+            .layer(env_list("RCOMMIT_EXCLUDE"), Source::Env("RCOMMIT_EXCLUDE"));
+        self.types
+            .layer(env_list("RCOMMIT_TYPES"), Source::Env("RCOMMIT_TYPES"));
+        self.scopes
+            .layer(env_list("RCOMMIT_SCOPES"), Source::Env("RCOMMIT_SCOPES"));
+        self.language
+            .layer(env_var("RCOMMIT_LANGUAGE"), Source::Env("RCOMMIT_LANGUAGE"));
+        self.output.layer(
+            env_var("RCOMMIT_OUTPUT")
+                .map(|value| parse_value(&value, "RCOMMIT_OUTPUT"))
+                .transpose()?,
+            Source::Env("RCOMMIT_OUTPUT"),
+        );
+        self.template.layer(
+            env_var("RCOMMIT_TEMPLATE").map(|path| Some(PathBuf::from(path))),
+            Source::Env("RCOMMIT_TEMPLATE"),
+        );
+        self.max_tokens.layer(
+            env_var("RCOMMIT_MAX_TOKENS")
+                .map(|value| parse_value(&value, "RCOMMIT_MAX_TOKENS"))
+                .transpose()?,
+            Source::Env("RCOMMIT_MAX_TOKENS"),
+        );
+        self.style_from_history.layer(
+            env_var("RCOMMIT_STYLE_FROM_HISTORY")
+                .map(|value| parse_value(&value, "RCOMMIT_STYLE_FROM_HISTORY"))
+                .transpose()?,
+            Source::Env("RCOMMIT_STYLE_FROM_HISTORY"),
+        );
+        for (setting, name) in [
+            (&mut self.style_author_only, "RCOMMIT_STYLE_AUTHOR_ONLY"),
+            (&mut self.style_main_branch, "RCOMMIT_STYLE_MAIN_BRANCH"),
+        ] {
+            setting.layer(
+                env_var(name)
+                    .map(|value| parse_value(&value, name))
+                    .transpose()?,
+                Source::Env(name),
+            );
+        }
+        self.scope_rules.layer(
+            env_list("RCOMMIT_SCOPE_RULES")
+                .map(|rules| {
+                    rules
+                        .iter()
+                        .map(|rule| parse_scope_rule(rule))
+                        .collect::<io::Result<_>>()
+                })
+                .transpose()?,
+            Source::Env("RCOMMIT_SCOPE_RULES"),
+        );
+        self.workspace_scopes.layer(
+            env_var("RCOMMIT_WORKSPACE_SCOPES")
+                .map(|value| parse_value(&value, "RCOMMIT_WORKSPACE_SCOPES"))
+                .transpose()?,
+            Source::Env("RCOMMIT_WORKSPACE_SCOPES"),
+        );
+        self.secrets.layer(
+            env_var("RCOMMIT_SECRETS")
+                .map(|value| parse_value(&value, "RCOMMIT_SECRETS"))
+                .transpose()?,
+            Source::Env("RCOMMIT_SECRETS"),
+        );
+        self.offline.layer(
+            env_var("RCOMMIT_OFFLINE")
+                .map(|value| parse_value(&value, "RCOMMIT_OFFLINE"))
+                .transpose()?,
+            Source::Env("RCOMMIT_OFFLINE"),
+        );
+        self.pr_base.layer(
+            env_var("RCOMMIT_PR_BASE").map(Some),
+            Source::Env("RCOMMIT_PR_BASE"),
+        );
+        self.protected_branches.layer(
+            env_list("RCOMMIT_PROTECTED_BRANCHES"),
+            Source::Env("RCOMMIT_PROTECTED_BRANCHES"),
+        );
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, matches: &ArgMatches) {
+        let value = |name: &str| matches.value_of(name).map(str::to_string);
+        self.provider.layer(value("provider"), Source::Cli);
+        self.model.layer(value("model").map(Some), Source::Cli);
+        self.exclude.layer(
+            matches
+                .values_of("exclude")
+                .map(|values| values.map(str::to_string).collect()),
+            Source::Cli,
+        );
+        self.language.layer(value("language"), Source::Cli);
+        self.secrets.layer(
+            matches
+                .is_present("secrets")
+                .then(|| matches.value_of_t_or_exit("secrets")),
+            Source::Cli,
+        );
+        self.max_tokens.layer(
+            matches
+                .is_present("max-tokens")
+                .then(|| matches.value_of_t_or_exit("max-tokens")),
+            Source::Cli,
+        );
+        self.style_from_history.layer(
+            matches
+                .is_present("style-from-history")
+                .then(|| matches.value_of_t_or_exit("style-from-history")),
+            Source::Cli,
+        );
+        self.style_author_only.layer(
+            matches.is_present("style-author-only").then_some(true),
+            Source::Cli,
+        );
+        self.style_main_branch.layer(
+            matches.is_present("style-main-branch").then_some(true),
+            Source::Cli,
+        );
+        self.offline
+            .layer(matches.is_present("offline").then_some(true), Source::Cli);
+        // Subcommands do not define the output flags, so probe for them.
+        let flag = |name: &str| matches.try_contains_id(name).unwrap_or(false);
+        let output = if flag("output") {
+            Some(matches.value_of_t_or_exit("output"))
+        } else if flag("commit") {
+            Some(OutputMode::Commit)
+        } else if flag("git") {
+            Some(OutputMode::Git)
+        } else {
+            None
+        };
+        self.output.layer(output, Source::Cli);
+        if flag("base") {
+            self.pr_base.layer(value("base").map(Some), Source::Cli);
+        }
+    }
+}
+
+impl fmt::Display for Config {
+    /// Prints one `key = value  # source` line per setting, as TOML.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn line(
+            f: &mut fmt::Formatter<'_>,
+            key: &str,
+            value: String,
+            source: &Source,
+        ) -> fmt::Result {
+            writeln!(f, "{:<18} = {:<40} # {}", key, value, source)
+        }
+        fn list(values: &[String]) -> String {
+            let quoted: Vec<String> = values.iter().map(|v| format!("{:?}", v)).collect();
+            format!("[{}]", quoted.join(", "))
+        }
+
+        line(
+            f,
+            "provider",
+            format!("{:?}", self.provider.value),
+            &self.provider.source,
+        )?;
+        let model = match &self.model.value {
+            Some(model) => format!("{:?}", model),
+            None => "\"\" (provider default)".to_string(),
+        };
+        line(f, "model", model, &self.model.source)?;
+        line(
+            f,
+            "exclude",
+            list(&self.exclude.value),
+            &self.exclude.source,
+        )?;
+        line(f, "types", list(&self.types.value), &self.types.source)?;
+        line(f, "scopes", list(&self.scopes.value), &self.scopes.source)?;
+        line(
+            f,
+            "language",
+            format!("{:?}", self.language.value),
+            &self.language.source,
+        )?;
+        line(
+            f,
+            "output",
+            format!("\"{}\"", self.output.value),
+            &self.output.source,
+        )?;
+        let template = match &self.template.value {
+            Some(path) => format!("{:?}", path.display().to_string()),
+            None => "\"\" (built-in)".to_string(),
+        };
+        line(f, "template", template, &self.template.source)?;
+        line(
+            f,
+            "max-tokens",
+            self.max_tokens.value.to_string(),
+            &self.max_tokens.source,
+        )?;
+        line(
+            f,
+            "style-from-history",
+            self.style_from_history.value.to_string(),
+            &self.style_from_history.source,
+        )?;
+        line(
+            f,
+            "style-author-only",
+            self.style_author_only.value.to_string(),
+            &self.style_author_only.source,
+        )?;
+        line(
+            f,
+            "style-main-branch",
+            self.style_main_branch.value.to_string(),
+            &self.style_main_branch.source,
+        )?;
+        let rules: Vec<String> = self
+            .scope_rules
+            .value
+            .iter()
+            .map(|(prefix, scope)| format!("{:?} = {:?}", prefix, scope))
+            .collect();
+        line(
+            f,
+            "scope-rules",
+            if rules.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", rules.join(", "))
+            },
+            &self.scope_rules.source,
+        )?;
+        line(
+            f,
+            "workspace-scopes",
+            self.workspace_scopes.value.to_string(),
+            &self.workspace_scopes.source,
+        )?;
+        line(
+            f,
+            "secrets",
+            format!("\"{}\"", self.secrets.value),
+            &self.secrets.source,
+        )?;
+        line(
+            f,
+            "offline",
+            self.offline.value.to_string(),
+            &self.offline.source,
+        )?;
+        let pr_base = match &self.pr_base.value {
+            Some(base) => format!("{:?}", base),
+            None => "\"\" (detected)".to_string(),
+        };
+        line(f, "pr-base", pr_base, &self.pr_base.source)?;
+        line(
+            f,
+            "protected-branches",
+            list(&self.protected_branches.value),
+            &self.protected_branches.source,
+        )
+    }
+}
+
+/// `$XDG_CONFIG_HOME/rcommit/config.toml`, falling back to `~/.config`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("rcommit").join("config.toml"))
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Comma-separated list, e.g. `RCOMMIT_TYPES=feat,fix,docs`.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    env_var(name).map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+/// One `prefix=scope` item of `RCOMMIT_SCOPE_RULES`.
+fn parse_scope_rule(rule: &str) -> io::Result<(String, String)> {
+    match rule.split_once('=') {
+        Some((prefix, scope)) if !prefix.trim().is_empty() && !scope.trim().is_empty() => {
+            Ok((prefix.trim().to_string(), scope.trim().to_string()))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "RCOMMIT_SCOPE_RULES: expected `prefix=scope`, found `{}`",
+                rule
+            ),
+        )),
+    }
+}
+
+fn parse_value<T>(value: &str, origin: &str) -> io::Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|err: T::Err| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", origin, err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Tests reading `RCOMMIT_*` or `XDG_CONFIG_HOME` hold this, since the
+    /// environment is shared by every test thread.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// An empty directory named after the test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rcommit-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn matches(args: &[&str]) -> ArgMatches {
+        crate::command_line_interface()
+            .try_get_matches_from(std::iter::once("rcommit").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = scratch_dir("precedence");
+        let user = dir.join("xdg").join("rcommit").join("config.toml");
+        fs::create_dir_all(user.parent().unwrap()).unwrap();
+        fs::write(
+            &user,
+            "language = \"German\"\nmodel = \"user-model\"\nmax-tokens = 100\noffline = true\n",
+        )
+        .unwrap();
+        let repo = dir.join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        fs::write(
+            repo.join(REPO_CONFIG_NAME),
+            "model = \"repo-model\"\nmax-tokens = 200\nscopes = [\"api\"]\n",
+        )
+        .unwrap();
+
+        env::set_var("XDG_CONFIG_HOME", dir.join("xdg"));
+        env::set_var("RCOMMIT_MAX_TOKENS", "300");
+        env::set_var("RCOMMIT_SCOPES", "web");
+        let config = Config::load(Some(&repo), &matches(&["--max-tokens", "400"]));
+        env::remove_var("XDG_CONFIG_HOME");
+        env::remove_var("RCOMMIT_MAX_TOKENS");
+        env::remove_var("RCOMMIT_SCOPES");
+        let config = config.unwrap();
+
+        assert_eq!(config.provider.value, provider::DEFAULT_PROVIDER);
+        assert!(matches!(config.provider.source, Source::Default));
+        assert_eq!(config.language.value, "German");
+        assert!(matches!(&config.language.source, Source::File(path) if *path == user));
+        assert_eq!(config.model.value.as_deref(), Some("repo-model"));
+        assert!(
+            matches!(&config.model.source, Source::File(path) if *path == repo.join(REPO_CONFIG_NAME))
+        );
+        assert_eq!(config.scopes.value, ["web"]);
+        assert!(matches!(
+            config.scopes.source,
+            Source::Env("RCOMMIT_SCOPES")
+        ));
+        assert_eq!(config.max_tokens.value, 400);
+        assert!(matches!(config.max_tokens.source, Source::Cli));
+        assert!(config.offline.value);
+    }
+
+    #[test]
+    fn rejects_unknown_providers() {
+        let dir = scratch_dir("provider");
+        let path = dir.join(REPO_CONFIG_NAME);
+        fs::write(&path, "provider = \"openia\"\n").unwrap();
+        let mut config = Config::default();
+        config.apply_file(&path).unwrap();
+        let err = config.check_provider().unwrap_err();
+        assert!(err.to_string().starts_with(&path.display().to_string()));
+        assert!(err.to_string().contains("`openia`"));
+
+        config
+            .provider
+            .layer(Some("anthropic".to_string()), Source::Cli);
+        assert!(config.check_provider().is_ok());
+    }
+
+    #[test]
+    fn show_reports_where_each_value_came_from() {
+        let dir = scratch_dir("show");
+        let path = dir.join(REPO_CONFIG_NAME);
+        fs::write(&path, "language = \"German\"\n").unwrap();
+        let mut config = Config::default();
+        config.apply_file(&path).unwrap();
+        config.max_tokens.layer(Some(400), Source::Cli);
+        config.pr_base.layer(
+            Some(Some("main".to_string())),
+            Source::Env("RCOMMIT_PR_BASE"),
+        );
+
+        let shown = config.to_string();
+        let source_of = |key: &str| {
+            let line = shown
+                .lines()
+                .find(|line| line.split_whitespace().next() == Some(key))
+                .unwrap();
+            line.rsplit_once("# ").unwrap().1.to_string()
+        };
+        assert_eq!(source_of("provider"), "default");
+        assert_eq!(source_of("language"), path.display().to_string());
+        assert_eq!(source_of("max-tokens"), "command line");
+        assert_eq!(source_of("pr-base"), "env RCOMMIT_PR_BASE");
+    }
+
+    #[test]
+    fn resolves_template_paths_next_to_the_file_naming_them() {
+        let dir = scratch_dir("template");
+        let user = dir.join("user").join("config.toml");
+        fs::create_dir_all(user.parent().unwrap()).unwrap();
+        fs::write(&user, "template = \"templates/commit.txt\"\n").unwrap();
+        let mut config = Config::default();
+        config.apply_file(&user).unwrap();
+        assert_eq!(
+            config.template.value,
+            Some(dir.join("user").join("templates").join("commit.txt"))
+        );
+
+        let absolute = dir.join("elsewhere.txt");
+        let repo = dir.join(REPO_CONFIG_NAME);
+        fs::write(&repo, format!("template = {:?}\n", absolute)).unwrap();
+        config.apply_file(&repo).unwrap();
+        assert_eq!(config.template.value, Some(absolute));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_in_config_files() {
+        let dir = scratch_dir("unknown-key");
+        let path = dir.join(REPO_CONFIG_NAME);
+        fs::write(&path, "langauge = \"German\"\n").unwrap();
+        let err = Config::default().apply_file(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&path.display().to_string()));
+        assert!(err.to_string().contains("langauge"));
+
+        fs::write(&path, "output = \"printer\"\n").unwrap();
+        assert!(Config::default().apply_file(&path).is_err());
+    }
+
+    #[test]
+    fn splits_env_lists_on_commas() {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        env::set_var("RCOMMIT_TYPES", " feat, fix,,docs ,");
+        env::set_var("RCOMMIT_SCOPE_RULES", "services/api=api, web = frontend");
+        let mut config = Config::default();
+        let applied = config.apply_env();
+        env::remove_var("RCOMMIT_TYPES");
+        env::remove_var("RCOMMIT_SCOPE_RULES");
+        applied.unwrap();
+
+        assert_eq!(config.types.value, ["feat", "fix", "docs"]);
+        assert_eq!(
+            config.scope_rules.value,
+            [
+                ("services/api".to_string(), "api".to_string()),
+                ("web".to_string(), "frontend".to_string()),
+            ]
+        );
+        assert!(matches!(config.types.source, Source::Env("RCOMMIT_TYPES")));
+    }
+
+    #[test]
+    fn rejects_malformed_scope_rules() {
+        for rule in ["api", "=api", "services/api=", " = "] {
+            let err = parse_scope_rule(rule).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(
+                err.to_string().starts_with("RCOMMIT_SCOPE_RULES:"),
+                "{}",
+                err
+            );
+        }
+
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        env::set_var("RCOMMIT_SCOPE_RULES", "services/api=api,web");
+        let applied = Config::default().apply_env();
+        env::remove_var("RCOMMIT_SCOPE_RULES");
+        assert!(applied.unwrap_err().to_string().contains("found `web`"));
+    }
+
+    #[test]
+    fn parses_output_modes() {
+        assert_eq!("stdout".parse(), Ok(OutputMode::Stdout));
+        assert_eq!("clipboard".parse(), Ok(OutputMode::Clipboard));
+        assert_eq!("git".parse(), Ok(OutputMode::Git));
+        assert_eq!("commit".parse(), Ok(OutputMode::Commit));
+        assert_eq!(
+            "file:out/msg.txt".parse(),
+            Ok(OutputMode::File(PathBuf::from("out/msg.txt")))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_modes_and_empty_file_paths() {
+        assert!("file:".parse::<OutputMode>().is_err());
+        assert!("file".parse::<OutputMode>().is_err());
+        assert!("printer".parse::<OutputMode>().is_err());
+    }
+
+    #[test]
+    fn output_modes_round_trip() {
+        for mode in ["stdout", "clipboard", "git", "commit", "file:msg.txt"] {
+            let parsed: OutputMode = mode.parse().unwrap();
+            assert_eq!(parsed.to_string(), mode);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt;
+
+/// Commit types accepted when no other list is configured.
+pub const DEFAULT_TYPES: &[&str] = &[
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+const BREAKING_CHANGE: &str = "BREAKING CHANGE";
+
+/// A commit message following Conventional Commits 1.0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionalCommit {
+    pub commit_type: String,
+    pub scope: Option<String>,
+    /// Set by `!` in the header; see [`ConventionalCommit::is_breaking`].
+    pub breaking: bool,
+    pub subject: String,
+    pub body: Option<String>,
+    pub footers: Vec<Footer>,
+}
+
+/// A `token: value` or `token #value` trailer. For the `#` form the value
+/// keeps its leading `#`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Footer {
+    pub token: String,
+    pub value: String,
+}
+
+impl ConventionalCommit {
+    /// Whether the commit breaks compatibility, marked either by `!` in the
+    /// header or by a `BREAKING CHANGE` footer.
+    pub fn is_breaking(&self) -> bool {
+        self.breaking
+            || self
+                .footers
+                .iter()
+                .any(|footer| footer.token == BREAKING_CHANGE || footer.token == "BREAKING-CHANGE")
+    }
+}
+
+impl fmt::Display for ConventionalCommit {
+    /// Writes the message back in canonical form: header, blank line, body,
+    /// blank line, footers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.commit_type)?;
+        if let Some(scope) = &self.scope {
+            write!(f, "({})", scope)?;
+        }
+        if self.breaking {
+            write!(f, "!")?;
+        }
+        write!(f, ": {}", self.subject)?;
+        if let Some(body) = &self.body {
+            write!(f, "\n\n{}", body)?;
+        }
+        if !self.footers.is_empty() {
+            writeln!(f)?;
+            for footer in &self.footers {
+                let separator = if footer.value.starts_with('#') {
+                    " "
+                } else {
+                    ": "
+                };
+                write!(f, "\n{}{}{}", footer.token, separator, footer.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    MissingSeparator,
+    InvalidType(String),
+    UnknownType(String),
+    InvalidScope,
+    UnexpectedScope {
+        found: Option<String>,
+        expected: String,
+    },
+    EmptySubject,
+    MissingBlankLine,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "the message is empty"),
+            ParseError::MissingSeparator => {
+                write!(f, "the first line must look like `type(scope): subject`")
+            }
+            ParseError::InvalidType(commit_type) => {
+                write!(f, "`{}` is not a valid commit type", commit_type)
+            }
+            ParseError::UnknownType(commit_type) => {
+                write!(
+                    f,
+                    "`{}` is not one of the allowed commit types",
+                    commit_type
+                )
+            }
+            ParseError::InvalidScope => write!(f, "the scope must be a non-empty `(scope)`"),
+            ParseError::UnexpectedScope { found, expected } => match found {
+                Some(found) => write!(
+                    f,
+                    "the scope is `{}` but the changed files belong to `{}`",
+                    found, expected
+                ),
+                None => write!(
+                    f,
+                    "the changed files belong to `{}`, so the header must start with `type({})`",
+                    expected, expected
+                ),
+            },
+            ParseError::EmptySubject => write!(f, "the subject after `: ` is empty"),
+            ParseError::MissingBlankLine => {
+                write!(
+                    f,
+                    "the body must be separated from the header by a blank line"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses `message` according to the Conventional Commits 1.0 grammar.
+pub fn parse(message: &str) -> Result<ConventionalCommit, ParseError> {
+    let message = message.trim();
+    let mut lines = message.lines();
+    let header = lines.next().ok_or(ParseError::Empty)?;
+    if header.trim().is_empty() {
+        return Err(ParseError::Empty);
+    }
+
+    let (prefix, subject) = header
+        .split_once(": ")
+        .ok_or(ParseError::MissingSeparator)?;
+    let subject = subject.trim();
+    if subject.is_empty() {
+        return Err(ParseError::EmptySubject);
+    }
+    let (prefix, breaking) = match prefix.strip_suffix('!') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, false),
+    };
+    let (commit_type, scope) = match prefix.split_once('(') {
+        Some((commit_type, rest)) => {
+            let scope = rest.strip_suffix(')').ok_or(ParseError::InvalidScope)?;
+            if scope.trim().is_empty() || scope.contains(['(', ')']) {
+                return Err(ParseError::InvalidScope);
+            }
+            (commit_type, Some(scope.trim().to_string()))
+        }
+        None => (prefix, None),
+    };
+    if commit_type.is_empty()
+        || !commit_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(ParseError::InvalidType(commit_type.to_string()));
+    }
+
+    let rest: Vec<&str> = lines.collect();
+    if rest.first().is_some_and(|line| !line.trim().is_empty()) {
+        return Err(ParseError::MissingBlankLine);
+    }
+    let (body, footers) = split_body_and_footers(&rest);
+
+    Ok(ConventionalCommit {
+        commit_type: commit_type.to_string(),
+        scope,
+        breaking,
+        subject: subject.to_string(),
+        body,
+        footers,
+    })
+}
+
+/// Parses `message` and also checks its type against `allowed_types`.
+pub fn validate(message: &str, allowed_types: &[&str]) -> Result<ConventionalCommit, ParseError> {
+    let commit = parse(message)?;
+    if !allowed_types
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(&commit.commit_type))
+    {
+        return Err(ParseError::UnknownType(commit.commit_type));
+    }
+    Ok(commit)
+}
+
+/// Checks that `commit` uses `expected`, the scope inferred from the paths
+/// it touches.
+pub fn check_scope(commit: &ConventionalCommit, expected: &str) -> Result<(), ParseError> {
+    match &commit.scope {
+        Some(scope) if scope == expected => Ok(()),
+        found => Err(ParseError::UnexpectedScope {
+            found: found.clone(),
+            expected: expected.to_string(),
+        }),
+    }
+}
+
+/// Strips what models like to wrap commit messages in: markdown code fences,
+/// surrounding quotes or backticks, and a leading "Commit message:" label.
+pub fn clean_message(raw: &str) -> String {
+    let mut text = raw.trim();
+
+    if let Some(inner) = text.strip_prefix("```") {
+        // Drop the info string (e.g. ```text) and the closing fence.
+        let inner = inner.split_once('\n').map_or("", |(_, rest)| rest);
+        text = inner.trim_end().strip_suffix("```").unwrap_or(inner).trim();
+    }
+    for label in ["Commit message:", "commit message:", "Commit Message:"] {
+        if let Some(rest) = text.strip_prefix(label) {
+            text = rest.trim();
+        }
+    }
+    for quote in ['"', '\'', '`'] {
+        if text.len() >= 2 && text.starts_with(quote) && text.ends_with(quote) {
+            text = text[1..text.len() - 1].trim();
+        }
+    }
+
+    text.to_string()
+}
+
+/// Splits everything after the header's blank line into body and footers.
+/// Footers are the last paragraph when its first line is a trailer.
+fn split_body_and_footers(lines: &[&str]) -> (Option<String>, Vec<Footer>) {
+    let text = lines.join("\n");
+    let text = text.trim();
+    if text.is_empty() {
+        return (None, Vec::new());
+    }
+
+    let (body, footer_block) = match text.rfind("\n\n") {
+        Some(idx) if footer_token(text[idx + 2..].trim_start()).is_some() => {
+            (text[..idx].trim(), &text[idx + 2..])
+        }
+        _ if footer_token(text).is_some() => ("", text),
+        _ => (text, ""),
+    };
+
+    let mut footers: Vec<Footer> = Vec::new();
+    for line in footer_block.lines() {
+        match footer_token(line) {
+            Some((token, value)) => footers.push(Footer {
+                token: token.to_string(),
+                value: value.to_string(),
+            }),
+            None => {
+                if let Some(last) = footers.last_mut() {
+                    last.value.push('\n');
+                    last.value.push_str(line);
+                }
+            }
+        }
+    }
+
+    let body = (!body.is_empty()).then(|| body.to_string());
+    (body, footers)
+}
+
+/// Recognizes `Token: value`, `Token #value` and `BREAKING CHANGE: value`.
+fn footer_token(line: &str) -> Option<(&str, &str)> {
+    if let Some(value) = line.strip_prefix("BREAKING CHANGE: ") {
+        return Some((BREAKING_CHANGE, value));
+    }
+    let (token, value) = match line.split_once(": ") {
+        Some(split) => split,
+        None => {
+            let (token, _) = line.split_once(" #")?;
+            (token, &line[token.len() + 1..])
+        }
+    };
+    let valid = !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then_some((token, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_type_scope_and_subject() {
+        let commit = parse("feat(parser): accept trailing commas").unwrap();
+        assert_eq!(commit.commit_type, "feat");
+        assert_eq!(commit.scope.as_deref(), Some("parser"));
+        assert_eq!(commit.subject, "accept trailing commas");
+        assert!(!commit.breaking);
+        assert_eq!(commit.body, None);
+        assert!(commit.footers.is_empty());
+
+        let commit = parse("fix: handle empty input").unwrap();
+        assert_eq!(commit.scope, None);
+    }
+
+    #[test]
+    fn bang_and_breaking_change_footer_mark_breaking_commits() {
+        let bang = parse("feat(api)!: drop the v1 endpoints").unwrap();
+        assert!(bang.breaking);
+        assert!(bang.is_breaking());
+
+        let footer =
+            parse("feat: new config format\n\nBREAKING CHANGE: old files are ignored").unwrap();
+        assert!(!footer.breaking);
+        assert!(footer.is_breaking());
+        assert_eq!(footer.footers[0].token, "BREAKING CHANGE");
+        assert_eq!(footer.footers[0].value, "old files are ignored");
+
+        let hyphen = parse("fix: x\n\nBREAKING-CHANGE: y").unwrap();
+        assert!(hyphen.is_breaking());
+    }
+
+    #[test]
+    fn splits_body_and_footers() {
+        let message = "fix(db): retry on deadlock\n\nThe pool now retries once.\n\nSecond paragraph.\n\nRefs: ABC-12\nCloses #34\nReviewed-by: Sam";
+        let commit = parse(message).unwrap();
+        assert_eq!(
+            commit.body.as_deref(),
+            Some("The pool now retries once.\n\nSecond paragraph.")
+        );
+        let footers: Vec<(&str, &str)> = commit
+            .footers
+            .iter()
+            .map(|footer| (footer.token.as_str(), footer.value.as_str()))
+            .collect();
+        assert_eq!(
+            footers,
+            vec![
+                ("Refs", "ABC-12"),
+                ("Closes", "#34"),
+                ("Reviewed-by", "Sam")
+            ]
+        );
+    }
+
+    #[test]
+    fn footer_values_continue_on_following_lines() {
+        let commit = parse("feat: x\n\nBREAKING CHANGE: first line\nsecond line").unwrap();
+        assert_eq!(commit.body, None);
+        assert_eq!(commit.footers[0].value, "first line\nsecond line");
+    }
+
+    #[test]
+    fn a_last_paragraph_without_a_trailer_is_body() {
+        let commit = parse("docs: readme\n\nExplain the: colon usage here").unwrap();
+        assert_eq!(
+            commit.body.as_deref(),
+            Some("Explain the: colon usage here")
+        );
+        assert!(commit.footers.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("   \n"), Err(ParseError::Empty));
+        assert_eq!(parse("update stuff"), Err(ParseError::MissingSeparator));
+        assert_eq!(parse("feat: "), Err(ParseError::MissingSeparator));
+        assert_eq!(parse("feat:  "), Err(ParseError::MissingSeparator));
+        assert_eq!(parse("feat:x"), Err(ParseError::MissingSeparator));
+        assert_eq!(
+            parse("new feature: x"),
+            Err(ParseError::InvalidType("new feature".to_string()))
+        );
+        assert_eq!(parse("feat(): x"), Err(ParseError::InvalidScope));
+        assert_eq!(parse("feat(api: x"), Err(ParseError::InvalidScope));
+        assert_eq!(parse("feat(a(b)): x"), Err(ParseError::InvalidScope));
+    }
+
+    #[test]
+    fn requires_a_blank_line_after_the_header() {
+        assert_eq!(
+            parse("feat: x\nbody right away"),
+            Err(ParseError::MissingBlankLine)
+        );
+    }
+
+    #[test]
+    fn validate_checks_the_allowed_types() {
+        assert!(validate("Feat: x", DEFAULT_TYPES).is_ok());
+        assert_eq!(
+            validate("feature: x", DEFAULT_TYPES),
+            Err(ParseError::UnknownType("feature".to_string()))
+        );
+        assert!(validate("wip: x", &["wip"]).is_ok());
+    }
+
+    #[test]
+    fn check_scope_compares_with_the_inferred_scope() {
+        let commit = parse("fix(api): x").unwrap();
+        assert!(check_scope(&commit, "api").is_ok());
+        assert_eq!(
+            check_scope(&commit, "web"),
+            Err(ParseError::UnexpectedScope {
+                found: Some("api".to_string()),
+                expected: "web".to_string(),
+            })
+        );
+        let unscoped = parse("fix: x").unwrap();
+        assert!(check_scope(&unscoped, "api").is_err());
+    }
+
+    #[test]
+    fn cleans_fences_labels_and_quotes() {
+        assert_eq!(clean_message("```\nfix: x\n```"), "fix: x");
+        assert_eq!(
+            clean_message("```text\nfix: x\n\nbody\n```\n"),
+            "fix: x\n\nbody"
+        );
+        assert_eq!(clean_message("Commit message: fix: x"), "fix: x");
+        assert_eq!(clean_message("\"fix: x\""), "fix: x");
+        assert_eq!(clean_message("`fix: x`"), "fix: x");
+        assert_eq!(clean_message("  fix: x  "), "fix: x");
+        assert_eq!(clean_message("'"), "'");
+    }
+
+    #[test]
+    fn display_round_trips_canonical_messages() {
+        for message in [
+            "feat: add x",
+            "fix(api)!: handle x",
+            "fix(api): handle x\n\nBody text.",
+            "fix(api)!: handle x\n\nBody text.\n\nBREAKING CHANGE: the api changed\nRefs #12",
+            "chore: bump deps\n\nReviewed-by: Sam",
+        ] {
+            let commit = parse(message).unwrap();
+            assert_eq!(commit.to_string(), message);
+            assert_eq!(parse(&commit.to_string()).unwrap(), commit);
+        }
+    }
+
+    #[test]
+    fn display_normalizes_spacing() {
+        let commit = parse("fix( api ):   trailing space  \n\n\n  Body.  \n").unwrap();
+        assert_eq!(commit.to_string(), "fix(api): trailing space\n\nBody.");
+    }
+}
@@ -0,0 +1,338 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::provider::ProviderError;
+use crate::template::TemplateError;
+
+/// Everything that can stop rcommit, each with its own exit code so scripts
+/// can tell the cases apart. Exit code 2 is left to clap for usage errors.
+#[derive(Debug)]
+pub enum AppError {
+    NotARepository(git2::Error),
+    NothingStaged,
+    /// `variable` (an API key or base URL) is unset for `provider`.
+    MissingCredentials {
+        provider: &'static str,
+        variable: &'static str,
+    },
+    /// The provider could not be reached at all.
+    ProviderUnreachable(String),
+    /// The provider answered with an error, `status` being its HTTP status.
+    Provider {
+        message: String,
+        status: Option<u16>,
+    },
+    RateLimited {
+        provider: &'static str,
+        retry_after: Option<u64>,
+    },
+    /// The prompt is larger than the model's context window.
+    ContextOverflow {
+        provider: &'static str,
+        message: String,
+    },
+    /// An `--exclude` pattern or a line of `.rcommitignore` does not parse.
+    InvalidExclude(ignore::Error),
+    /// The prompt template could not be read or is invalid.
+    Template(TemplateError),
+    /// What would be sent holds this many possible secrets and the policy
+    /// is to block.
+    SecretsBlocked(usize),
+    /// `--amend` or `--signoff`, named here, with an output mode other than
+    /// `commit`.
+    NeedsCommitOutput(&'static str),
+    Git(git2::Error),
+    Io(io::Error),
+    Other(String),
+}
+
+impl AppError {
+    /// Classifies an error returned by a provider or the chain driving it.
+    pub fn from_provider(err: Box<dyn Error>) -> Self {
+        let mut source: Option<&(dyn Error + 'static)> = Some(err.as_ref());
+        while let Some(inner) = source {
+            if let Some(err) = inner.downcast_ref::<ProviderError>() {
+                return Self::from_provider_error(err);
+            }
+            if let Some(err) = inner.downcast_ref::<reqwest::Error>() {
+                if err.is_connect() || err.is_timeout() {
+                    return AppError::ProviderUnreachable(err.to_string());
+                }
+                return AppError::Provider {
+                    message: err.to_string(),
+                    status: err.status().map(|status| status.as_u16()),
+                };
+            }
+            source = inner.source();
+        }
+        AppError::Provider {
+            message: err.to_string(),
+            status: None,
+        }
+    }
+
+    fn from_provider_error(err: &ProviderError) -> Self {
+        match err {
+            ProviderError::MissingSetting { provider, variable } => {
+                AppError::MissingCredentials { provider, variable }
+            }
+            ProviderError::Http {
+                provider,
+                status: 429,
+                body,
+                retry_after,
+            } if !body.contains("insufficient_quota") => AppError::RateLimited {
+                provider,
+                retry_after: *retry_after,
+            },
+            ProviderError::Http {
+                provider,
+                status: 400 | 413,
+                body,
+                ..
+            } if is_context_overflow(body) => AppError::ContextOverflow {
+                provider,
+                message: body.trim().to_string(),
+            },
+            ProviderError::Http { status, .. } => AppError::Provider {
+                message: err.to_string(),
+                status: Some(*status),
+            },
+        }
+    }
+
+    /// Whether the offline generator can stand in: the model is out of
+    /// reach, as opposed to misconfigured or refusing the request. Missing
+    /// credentials are a configuration error, so they only lead offline
+    /// when the user asks for it with `--offline`.
+    pub fn allows_offline_fallback(&self) -> bool {
+        matches!(self, AppError::ProviderUnreachable(_))
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Git(_) | AppError::Io(_) | AppError::Other(_) => 1,
+            AppError::NeedsCommitOutput(_) => 2,
+            AppError::NotARepository(_) => 3,
+            AppError::NothingStaged => 4,
+            AppError::MissingCredentials { .. } => 5,
+            AppError::ProviderUnreachable(_) | AppError::Provider { .. } => 6,
+            AppError::RateLimited { .. } => 7,
+            AppError::ContextOverflow { .. } => 8,
+            AppError::InvalidExclude(_) => 9,
+            AppError::Template(_) => 10,
+            AppError::SecretsBlocked(_) => 11,
+        }
+    }
+
+    /// What the user can do about it, when there is something to suggest.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            AppError::NotARepository(_) => {
+                "run rcommit inside a git work tree, or set GIT_DIR".to_string()
+            }
+            AppError::NothingStaged => "stage changes with `git add`, or pass --all to stage \
+                 every change to tracked files; files matched by --exclude or .rcommitignore \
+                 do not count"
+                .to_string(),
+            AppError::MissingCredentials { variable, .. } => format!(
+                "export {}, pick another --provider, or use --offline",
+                variable
+            ),
+            AppError::ProviderUnreachable(_) => {
+                "check your network connection and the provider's base URL, or use --offline"
+                    .to_string()
+            }
+            AppError::Provider {
+                status: Some(401 | 403),
+                ..
+            } => "check that the API key is valid and allowed to use this model".to_string(),
+            AppError::Provider {
+                status: Some(404), ..
+            } => "check the model name (--model) and the base URL".to_string(),
+            AppError::Provider {
+                status: Some(500..=599),
+                ..
+            } => "the provider is having trouble; try again in a moment".to_string(),
+            AppError::Provider { message, .. } if message.contains("insufficient_quota") => {
+                "the account has run out of credits; check its billing settings".to_string()
+            }
+            AppError::RateLimited {
+                retry_after: Some(seconds),
+                ..
+            } => format!("wait {} seconds and try again", seconds),
+            AppError::RateLimited { .. } => "wait a moment and try again".to_string(),
+            AppError::ContextOverflow { .. } => "lower --max-tokens, exclude generated files \
+                 with --exclude, or pick a model with a larger context"
+                .to_string(),
+            AppError::InvalidExclude(_) => {
+                "patterns use .gitignore syntax; check --exclude and .rcommitignore".to_string()
+            }
+            AppError::Template(_) => {
+                "fix the template, or run `rcommit template init --force` for a fresh one"
+                    .to_string()
+            }
+            AppError::SecretsBlocked(_) => {
+                "exclude the files or reword the commits holding them, or use --secrets redact \
+                 or warn"
+                    .to_string()
+            }
+            AppError::NeedsCommitOutput(_) => {
+                "pass --commit, or set `output = \"commit\"` in the config".to_string()
+            }
+            _ => return None,
+        };
+        Some(hint)
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotARepository(err) => {
+                write!(f, "not in a git repository: {}", err.message())
+            }
+            AppError::NothingStaged => write!(f, "there are no staged changes to describe"),
+            AppError::MissingCredentials { provider, variable } => {
+                write!(f, "{} must be set for {}", variable, provider)
+            }
+            AppError::ProviderUnreachable(err) => {
+                write!(f, "could not reach the provider: {}", err)
+            }
+            AppError::Provider { message, .. } => write!(f, "the provider failed: {}", message),
+            AppError::RateLimited { provider, .. } => {
+                write!(f, "{} is rate limiting requests", provider)
+            }
+            AppError::ContextOverflow { provider, message } => write!(
+                f,
+                "the prompt is too long for the {} model: {}",
+                provider, message
+            ),
+            AppError::InvalidExclude(err) => write!(f, "invalid exclude pattern: {}", err),
+            AppError::Template(err) => write!(f, "{}", err),
+            AppError::SecretsBlocked(count) => {
+                write!(f, "found {} possible secrets, nothing was sent", count)
+            }
+            AppError::NeedsCommitOutput(flag) => {
+                write!(f, "--{} needs the message to be committed", flag)
+            }
+            AppError::Git(err) => write!(f, "{}", err.message()),
+            AppError::Io(err) => write!(f, "{}", err),
+            AppError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for AppError {}
+
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> Self {
+        AppError::Io(err)
+    }
+}
+
+impl From<ignore::Error> for AppError {
+    fn from(err: ignore::Error) -> Self {
+        AppError::InvalidExclude(err)
+    }
+}
+
+impl From<TemplateError> for AppError {
+    fn from(err: TemplateError) -> Self {
+        AppError::Template(err)
+    }
+}
+
+impl From<git2::Error> for AppError {
+    fn from(err: git2::Error) -> Self {
+        AppError::Git(err)
+    }
+}
+
+/// How OpenAI-style and Anthropic APIs word a prompt that does not fit.
+fn is_context_overflow(body: &str) -> bool {
+    [
+        "context_length_exceeded",
+        "maximum context length",
+        "prompt is too long",
+        "too many tokens",
+    ]
+    .iter()
+    .any(|marker| body.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http(status: u16, body: &str) -> Box<dyn Error> {
+        Box::new(ProviderError::Http {
+            provider: "openai",
+            status,
+            body: body.to_string(),
+            retry_after: Some(3),
+        })
+    }
+
+    #[test]
+    fn classifies_provider_errors() {
+        let missing = AppError::from_provider(Box::new(ProviderError::MissingSetting {
+            provider: "openai",
+            variable: "OPENAI_API_KEY",
+        }));
+        assert_eq!(missing.exit_code(), 5);
+        assert!(matches!(
+            AppError::from_provider(http(429, "slow down")),
+            AppError::RateLimited {
+                retry_after: Some(3),
+                ..
+            }
+        ));
+        assert!(matches!(
+            AppError::from_provider(http(429, "insufficient_quota")),
+            AppError::Provider {
+                status: Some(429),
+                ..
+            }
+        ));
+        assert_eq!(
+            AppError::from_provider(http(400, "context_length_exceeded")).exit_code(),
+            8
+        );
+        assert_eq!(AppError::from_provider(http(401, "bad key")).exit_code(), 6);
+    }
+
+    #[test]
+    fn only_an_unreachable_provider_falls_back_offline() {
+        assert!(AppError::ProviderUnreachable("refused".to_string()).allows_offline_fallback());
+        let missing = AppError::MissingCredentials {
+            provider: "openai",
+            variable: "OPENAI_API_KEY",
+        };
+        assert!(!missing.allows_offline_fallback());
+        assert!(!AppError::RateLimited {
+            provider: "openai",
+            retry_after: None
+        }
+        .allows_offline_fallback());
+    }
+
+    #[test]
+    fn input_errors_have_their_own_exit_codes() {
+        let exclude = crate::exclude::ExcludeMatcher::new(&std::env::temp_dir(), &["{a,b"])
+            .err()
+            .expect("an unclosed alternation is invalid");
+        let template = AppError::Template(TemplateError::MissingInput(std::path::PathBuf::from(
+            "t.txt",
+        )));
+        let codes = [
+            AppError::from(exclude).exit_code(),
+            template.exit_code(),
+            AppError::SecretsBlocked(2).exit_code(),
+        ];
+        assert_eq!(codes, [9, 10, 11]);
+        assert!(AppError::SecretsBlocked(2).hint().is_some());
+        assert_eq!(AppError::NeedsCommitOutput("amend").exit_code(), 2);
+    }
+}
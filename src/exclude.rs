@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Name of the repository-level file listing paths that never reach the prompt.
+pub const IGNORE_FILE_NAME: &str = ".rcommitignore";
+
+/// Decides which staged paths are left out of the prompt, using gitignore
+/// semantics: globs, `**`, directory patterns and `!` negation.
+pub struct ExcludeMatcher {
+    gitignore: Gitignore,
+}
+
+impl ExcludeMatcher {
+    /// Builds a matcher from `.rcommitignore` at `root` (if present) followed by
+    /// the `--exclude` patterns, so later command-line patterns win.
+    pub fn new(root: &Path, patterns: &[&str]) -> Result<Self, ignore::Error> {
+        let mut builder = GitignoreBuilder::new(root);
+        let ignore_file = root.join(IGNORE_FILE_NAME);
+        if ignore_file.is_file() {
+            if let Some(err) = builder.add(&ignore_file) {
+                return Err(err);
+            }
+        }
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(Self {
+            gitignore: builder.build()?,
+        })
+    }
+
+    /// Returns true when `path` (relative to the repository root) is excluded.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        self.gitignore
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// An empty directory for one test, removed first if a previous run left it.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rcommit-exclude-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn excluded(matcher: &ExcludeMatcher, path: &str) -> bool {
+        matcher.is_excluded(Path::new(path))
+    }
+
+    #[test]
+    fn globs_match_in_any_directory() {
+        let root = scratch_dir("globs");
+        let matcher = ExcludeMatcher::new(&root, &["*.lock"]).unwrap();
+        assert!(excluded(&matcher, "Cargo.lock"));
+        assert!(excluded(&matcher, "web/yarn.lock"));
+        assert!(!excluded(&matcher, "src/lock.rs"));
+    }
+
+    #[test]
+    fn double_star_and_directory_patterns() {
+        let root = scratch_dir("double-star");
+        let matcher = ExcludeMatcher::new(&root, &["docs/**/*.png", "vendor/"]).unwrap();
+        assert!(excluded(&matcher, "docs/a/b/diagram.png"));
+        assert!(excluded(&matcher, "docs/diagram.png"));
+        assert!(!excluded(&matcher, "assets/diagram.png"));
+        assert!(excluded(&matcher, "vendor/lib/mod.rs"));
+    }
+
+    #[test]
+    fn negation_re_includes_a_path() {
+        let root = scratch_dir("negation");
+        let matcher = ExcludeMatcher::new(&root, &["generated/", "!generated/schema.rs"]);
+        let matcher = matcher.unwrap();
+        assert!(excluded(&matcher, "generated/types.rs"));
+        // The path itself is checked before its parents, so unlike git a
+        // file can be re-included from an excluded directory.
+        assert!(!excluded(&matcher, "generated/schema.rs"));
+
+        let matcher = ExcludeMatcher::new(&root, &["*.snap", "!keep.snap"]).unwrap();
+        assert!(excluded(&matcher, "tests/a.snap"));
+        assert!(!excluded(&matcher, "tests/keep.snap"));
+    }
+
+    #[test]
+    fn command_line_patterns_override_the_ignore_file() {
+        let root = scratch_dir("precedence");
+        fs::write(root.join(IGNORE_FILE_NAME), "*.sql\n!seed.sql\n").unwrap();
+        let matcher = ExcludeMatcher::new(&root, &[]).unwrap();
+        assert!(excluded(&matcher, "db/schema.sql"));
+        assert!(!excluded(&matcher, "db/seed.sql"));
+
+        let matcher = ExcludeMatcher::new(&root, &["seed.sql", "!schema.sql"]).unwrap();
+        assert!(excluded(&matcher, "db/seed.sql"));
+        assert!(!excluded(&matcher, "db/schema.sql"));
+    }
+
+    #[test]
+    fn without_patterns_nothing_is_excluded() {
+        let root = scratch_dir("empty");
+        let matcher = ExcludeMatcher::new(&root, &[]).unwrap();
+        assert!(!excluded(&matcher, "src/main.rs"));
+    }
+}
@@ -0,0 +1,1028 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use git2::{
+    BranchType, Delta, Diff, DiffFindOptions, DiffOptions, FileMode, ObjectType, Oid, Patch,
+    Repository, Signature, Sort,
+};
+
+use crate::exclude::ExcludeMatcher;
+
+/// How a staged file differs from `HEAD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    Copied,
+    TypeChanged,
+}
+
+impl ChangeStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChangeStatus::Added => "added",
+            ChangeStatus::Modified => "modified",
+            ChangeStatus::Deleted => "deleted",
+            ChangeStatus::Renamed => "renamed",
+            ChangeStatus::Copied => "copied",
+            ChangeStatus::TypeChanged => "type changed",
+        }
+    }
+}
+
+/// A single hunk of a staged diff, with its `@@` header and the raw lines
+/// prefixed by their origin (`+`, `-` or ` `).
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub header: String,
+    pub lines: Vec<String>,
+}
+
+/// A staged change to one file.
+///
+/// `old_path` is set for renames and copies. Deletions keep no hunks, only
+/// the number of removed lines in `deletions`.
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub old_path: Option<PathBuf>,
+    pub status: ChangeStatus,
+    pub hunks: Vec<Hunk>,
+    pub binary: bool,
+    pub additions: usize,
+    pub deletions: usize,
+    pub mode_change: Option<(u32, u32)>,
+}
+
+impl FileChange {
+    /// Renders the change the way it is shown to the model: a header line
+    /// followed by `hunks`, or a one-line summary for deletions and binaries.
+    pub fn to_prompt_text(&self) -> String {
+        self.render(&self.hunks)
+    }
+
+    /// Like [`FileChange::to_prompt_text`] but with only the given hunks, so
+    /// callers can render a trimmed copy of the change.
+    pub fn render(&self, hunks: &[Hunk]) -> String {
+        let mut output = String::from("\n---------------------------\n name:");
+        if let Some(old_path) = &self.old_path {
+            output.push_str(&format!("{} -> ", old_path.to_string_lossy()));
+        }
+        output.push_str(&self.path.to_string_lossy());
+        output.push_str(&format!(
+            " ({}, +{} -{})\n",
+            self.status.label(),
+            self.additions,
+            self.deletions
+        ));
+        if let Some((old_mode, new_mode)) = self.mode_change {
+            output.push_str(&format!("mode changed {:o} -> {:o}\n", old_mode, new_mode));
+        }
+        if self.binary {
+            output.push_str("Binary file changed\n");
+            return output;
+        }
+        if self.status == ChangeStatus::Deleted {
+            output.push_str(&format!("File removed ({} lines)\n", self.deletions));
+            return output;
+        }
+        for hunk in hunks {
+            output.push_str(&hunk.header);
+            output.push('\n');
+            for line in &hunk.lines {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        output
+    }
+}
+
+/// Opens the repository containing the current working directory.
+pub fn open_repository() -> Result<Repository, git2::Error> {
+    Repository::open_from_env()
+}
+
+/// Short name of the checked-out branch, `None` when `HEAD` is detached.
+pub fn current_branch(repo: &Repository) -> Option<String> {
+    let head = match repo.head() {
+        Ok(head) => head,
+        // On an unborn branch HEAD still names the branch to be created.
+        Err(_) => {
+            let head = repo.find_reference("HEAD").ok()?;
+            let target = head.symbolic_target()?;
+            return target.strip_prefix("refs/heads/").map(str::to_string);
+        }
+    };
+    if !head.is_branch() {
+        return None;
+    }
+    head.shorthand().map(str::to_string)
+}
+
+/// Subject lines of the latest `limit` commits reachable from `HEAD`.
+pub fn recent_subjects(repo: &Repository, limit: usize) -> Result<Vec<String>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    if revwalk.push_head().is_err() {
+        return Ok(Vec::new());
+    }
+    let mut subjects = Vec::with_capacity(limit);
+    for oid in revwalk.take(limit) {
+        let commit = repo.find_commit(oid?)?;
+        if let Some(summary) = commit.summary() {
+            subjects.push(summary.to_string());
+        }
+    }
+    Ok(subjects)
+}
+
+/// Full messages of up to `limit` non-merge commits reachable from the
+/// revision `start`, newest first. With `author_email` set, only commits by
+/// that author (compared case-insensitively) are kept.
+pub fn commit_messages(
+    repo: &Repository,
+    start: &str,
+    author_email: Option<&str>,
+    limit: usize,
+) -> Result<Vec<String>, git2::Error> {
+    let start = match repo.revparse_single(start) {
+        Ok(object) => object.peel_to_commit()?.id(),
+        // An unborn branch has no history to sample.
+        Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(start)?;
+
+    let mut messages = Vec::with_capacity(limit);
+    for oid in revwalk {
+        if messages.len() >= limit {
+            break;
+        }
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        if let Some(email) = author_email {
+            let author = commit.author();
+            if !author
+                .email()
+                .is_some_and(|e| e.eq_ignore_ascii_case(email))
+            {
+                continue;
+            }
+        }
+        if let Some(message) = commit.message() {
+            messages.push(message.trim().to_string());
+        }
+    }
+    Ok(messages)
+}
+
+/// The repository's main branch as a revision: the branch `origin/HEAD`
+/// points to, else a local `main` or `master`. A local branch is preferred
+/// over its remote-tracking copy when both exist.
+pub fn main_branch(repo: &Repository) -> Option<String> {
+    let remote_default = repo
+        .find_reference("refs/remotes/origin/HEAD")
+        .ok()
+        .and_then(|reference| reference.symbolic_target().map(str::to_string))
+        .and_then(|target| target.strip_prefix("refs/remotes/").map(str::to_string));
+    let mut candidates = Vec::new();
+    if let Some(remote) = &remote_default {
+        if let Some((_, name)) = remote.split_once('/') {
+            candidates.push(name.to_string());
+        }
+    }
+    candidates.extend(["main".to_string(), "master".to_string()]);
+
+    candidates
+        .into_iter()
+        .find(|name| repo.find_branch(name, BranchType::Local).is_ok())
+        .or(remote_default)
+}
+
+/// The branch a pull request from the current branch would target: the
+/// branch it tracks, unless that is just its own copy on the remote (as
+/// after `git switch -c topic origin/develop`), else [`main_branch`].
+pub fn default_base(repo: &Repository) -> Option<String> {
+    let tracked = current_branch(repo).and_then(|name| {
+        let upstream = repo
+            .find_branch(&name, BranchType::Local)
+            .ok()?
+            .upstream()
+            .ok()?;
+        let upstream = upstream.name().ok()??.to_string();
+        let own_copy = upstream
+            .split_once('/')
+            .is_some_and(|(_, branch)| branch == name);
+        (!own_copy && upstream != name).then_some(upstream)
+    });
+    tracked.or_else(|| main_branch(repo))
+}
+
+/// The commit where `HEAD` forked from the revision `base`.
+pub fn merge_base(repo: &Repository, base: &str) -> Result<Oid, git2::Error> {
+    let base = repo.revparse_single(base)?.peel_to_commit()?.id();
+    let head = repo.head()?.peel_to_commit()?.id();
+    repo.merge_base(base, head)
+}
+
+/// Full messages of the non-merge commits on `HEAD` since `base`, oldest
+/// first.
+pub fn commits_since(repo: &Repository, base: Oid) -> Result<Vec<String>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.hide(base)?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    let mut messages = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        if let Some(message) = commit.message() {
+            messages.push(message.trim().to_string());
+        }
+    }
+    Ok(messages)
+}
+
+/// Non-merge commits reachable from the revision `to` but not from `from`,
+/// newest first, as their id and full message. Without `from` the whole
+/// history up to `to` is listed.
+pub fn commit_range(
+    repo: &Repository,
+    from: Option<&str>,
+    to: &str,
+) -> Result<Vec<(Oid, String)>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(repo.revparse_single(to)?.peel_to_commit()?.id())?;
+    if let Some(from) = from {
+        revwalk.hide(repo.revparse_single(from)?.peel_to_commit()?.id())?;
+    }
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        if let Some(message) = commit.message() {
+            commits.push((commit.id(), message.trim().to_string()));
+        }
+    }
+    Ok(commits)
+}
+
+/// Every tag with the commit it points to, annotated tags peeled.
+pub fn tags(repo: &Repository) -> Result<Vec<(String, Oid)>, git2::Error> {
+    let mut tags = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        let reference = repo.find_reference(&format!("refs/tags/{}", name))?;
+        if let Ok(commit) = reference.peel_to_commit() {
+            tags.push((name.to_string(), commit.id()));
+        }
+    }
+    Ok(tags)
+}
+
+/// Whether `commit` is `HEAD` or one of its ancestors.
+pub fn reachable_from_head(repo: &Repository, commit: Oid) -> Result<bool, git2::Error> {
+    let head = repo.head()?.peel_to_commit()?.id();
+    Ok(head == commit || repo.graph_descendant_of(head, commit)?)
+}
+
+/// Creates the annotated tag `name` on `HEAD` with `git tag`, so signing
+/// settings from git config apply.
+pub fn create_tag(repo: &Repository, name: &str, message: &str) -> io::Result<()> {
+    let workdir = repo.workdir().unwrap_or_else(|| repo.path());
+    let status = Command::new("git")
+        .current_dir(workdir)
+        .args(["tag", "--annotate", "--message", message, name])
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("git tag failed ({})", status)));
+    }
+    Ok(())
+}
+
+/// Remote-tracking branches, like `origin/main`, that already contain
+/// `commit`.
+pub fn remote_branches_containing(
+    repo: &Repository,
+    commit: Oid,
+) -> Result<Vec<String>, git2::Error> {
+    let mut names = Vec::new();
+    for branch in repo.branches(Some(BranchType::Remote))? {
+        let (branch, _) = branch?;
+        let Some(name) = branch.name()? else {
+            continue;
+        };
+        // `origin/HEAD` only points at another remote branch.
+        if name.ends_with("/HEAD") {
+            continue;
+        }
+        let Some(tip) = branch.get().target() else {
+            continue;
+        };
+        if tip == commit || repo.graph_descendant_of(tip, commit)? {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// What [`reword_commits`] did, with what the rewrite could not keep.
+#[derive(Debug)]
+pub struct Reworded {
+    pub head: Oid,
+    /// Commits recreated, the reworded ones and every one after them.
+    pub rewritten: usize,
+    /// Recreated commits that had a GPG or SSH signature, which is lost.
+    pub unsigned: usize,
+    /// Tags that still point at the old commits.
+    pub stale_tags: Vec<String>,
+}
+
+/// Gives the commits in `messages` their new message by recreating them and
+/// every commit after them up to `HEAD`, like a rebase that only rewords:
+/// trees and authors are kept, and the committer becomes the current user.
+/// Commits that are only moved keep their message bytes and `encoding`
+/// header. Signatures are not carried over and tags are not moved; the
+/// result counts what was affected. The branch is moved to the
+/// new tip and the old one saved as `ORIG_HEAD`; the index and working tree
+/// are left alone, as the tip's tree is the same.
+pub fn reword_commits(
+    repo: &Repository,
+    messages: &HashMap<Oid, String>,
+) -> Result<Reworded, git2::Error> {
+    let head = repo.head()?;
+    let head_id = head.peel_to_commit()?.id();
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head_id)?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    // Stop below the oldest reworded commits, but not below a parent that is
+    // itself reworded or comes after one.
+    for &oid in messages.keys() {
+        for parent in repo.find_commit(oid)?.parent_ids() {
+            let mut after_reworded = false;
+            for &reworded in messages.keys() {
+                if parent == reworded || repo.graph_descendant_of(parent, reworded)? {
+                    after_reworded = true;
+                    break;
+                }
+            }
+            if !after_reworded {
+                revwalk.hide(parent)?;
+            }
+        }
+    }
+
+    let committer = repo.signature()?;
+    let mut rewritten: HashMap<Oid, Oid> = HashMap::new();
+    let mut unsigned = 0;
+    for oid in revwalk {
+        let oid = oid?;
+        let commit = repo.find_commit(oid)?;
+        let moved = commit
+            .parent_ids()
+            .any(|parent| rewritten.contains_key(&parent));
+        if !moved && !messages.contains_key(&oid) {
+            continue;
+        }
+        if repo.extract_signature(&oid, None).is_ok() {
+            unsigned += 1;
+        }
+        let parents = commit
+            .parent_ids()
+            .map(|parent| repo.find_commit(rewritten.get(&parent).copied().unwrap_or(parent)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        let new_id = match messages.get(&oid) {
+            Some(message) => repo.commit(
+                None,
+                &commit.author(),
+                &committer,
+                &format!("{}\n", message.trim_end()),
+                &commit.tree()?,
+                &parents,
+            )?,
+            None => recreate_commit(repo, &commit, &committer, &parents)?,
+        };
+        rewritten.insert(oid, new_id);
+    }
+
+    let stale_tags = tags(repo)?
+        .into_iter()
+        .filter(|(_, commit)| rewritten.contains_key(commit))
+        .map(|(name, _)| name)
+        .collect();
+    let new_head = rewritten.get(&head_id).copied().unwrap_or(head_id);
+    repo.reference("ORIG_HEAD", head_id, true, "rcommit reword")?;
+    match head.name() {
+        Some(name) if head.is_branch() => {
+            repo.reference(name, new_head, true, "rcommit reword")?;
+        }
+        _ => repo.set_head_detached(new_head)?,
+    }
+    Ok(Reworded {
+        head: new_head,
+        rewritten: rewritten.len(),
+        unsigned,
+        stale_tags,
+    })
+}
+
+/// Writes `commit` again on top of `parents` with `committer`, keeping its
+/// message as raw bytes together with its `encoding` header, which
+/// [`Repository::commit`] would turn into UTF-8.
+fn recreate_commit(
+    repo: &Repository,
+    commit: &git2::Commit,
+    committer: &Signature,
+    parents: &[&git2::Commit],
+) -> Result<Oid, git2::Error> {
+    let buffer =
+        repo.commit_create_buffer(&commit.author(), committer, "", &commit.tree()?, parents)?;
+    // With an empty message the buffer ends in the line separating the
+    // headers from the message.
+    let mut object = buffer.to_vec();
+    if let Some(encoding) = commit.message_encoding() {
+        object.pop();
+        object.extend_from_slice(format!("encoding {}\n\n", encoding).as_bytes());
+    }
+    object.extend_from_slice(commit.message_raw_bytes());
+    repo.odb()?.write(ObjectType::Commit, &object)
+}
+
+/// Whether `name` is a tag rather than a branch or commit.
+pub fn is_tag(repo: &Repository, name: &str) -> bool {
+    repo.find_reference(&format!("refs/tags/{}", name)).is_ok()
+}
+
+/// When the commit at the revision `rev` was made, as seconds since the
+/// epoch and the committer's UTC offset in minutes.
+pub fn commit_time(repo: &Repository, rev: &str) -> Result<(i64, i32), git2::Error> {
+    let time = repo.revparse_single(rev)?.peel_to_commit()?.time();
+    Ok((time.seconds(), time.offset_minutes()))
+}
+
+/// `user.email` from git config, used to recognise the current author.
+pub fn user_email(repo: &Repository) -> Option<String> {
+    repo.config().ok()?.get_string("user.email").ok()
+}
+
+/// Reads the staged changes (index against `HEAD`), skipping any file whose
+/// path is matched by `excludes`. When `amend` is set the index is compared
+/// with `HEAD`'s parent instead, so the result covers the whole amended commit.
+pub fn staged_changes(
+    repo: &Repository,
+    excludes: &ExcludeMatcher,
+    amend: bool,
+) -> Result<Vec<FileChange>, git2::Error> {
+    let diff = staged_diff(repo, amend, DiffOptions::new())?;
+    collect_changes(&diff, excludes)
+}
+
+/// What the current branch changes since it forked from `base`: the tree of
+/// their merge base against `HEAD`'s, in the same form as [`staged_changes`].
+/// Uncommitted changes are not included.
+pub fn branch_changes(
+    repo: &Repository,
+    base: Oid,
+    excludes: &ExcludeMatcher,
+) -> Result<Vec<FileChange>, git2::Error> {
+    let base_tree = repo.find_commit(base)?.tree()?;
+    let head_tree = repo.head()?.peel_to_tree()?;
+    let mut diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&head_tree), None)?;
+    let mut find_opts = DiffFindOptions::new();
+    find_opts.renames(true).copies(true);
+    diff.find_similar(Some(&mut find_opts))?;
+    collect_changes(&diff, excludes)
+}
+
+/// What `commit` changed against its first parent, in the same form as
+/// [`staged_changes`]. A root commit is compared with the empty tree.
+pub fn commit_changes(
+    repo: &Repository,
+    commit: Oid,
+    excludes: &ExcludeMatcher,
+) -> Result<Vec<FileChange>, git2::Error> {
+    let commit = repo.find_commit(commit)?;
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+    let mut find_opts = DiffFindOptions::new();
+    find_opts.renames(true).copies(true);
+    diff.find_similar(Some(&mut find_opts))?;
+    collect_changes(&diff, excludes)
+}
+
+fn collect_changes(diff: &Diff, excludes: &ExcludeMatcher) -> Result<Vec<FileChange>, git2::Error> {
+    let mut changes = Vec::new();
+
+    for (idx, delta) in diff.deltas().enumerate() {
+        let status = match delta.status() {
+            Delta::Added => ChangeStatus::Added,
+            Delta::Modified => ChangeStatus::Modified,
+            Delta::Deleted => ChangeStatus::Deleted,
+            Delta::Renamed => ChangeStatus::Renamed,
+            Delta::Copied => ChangeStatus::Copied,
+            Delta::Typechange => ChangeStatus::TypeChanged,
+            _ => continue,
+        };
+        let path = match delta.new_file().path().or(delta.old_file().path()) {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        if excludes.is_excluded(&path) {
+            continue;
+        }
+        let old_path = match status {
+            ChangeStatus::Renamed | ChangeStatus::Copied => {
+                delta.old_file().path().map(|path| path.to_path_buf())
+            }
+            _ => None,
+        };
+
+        let patch = Patch::from_diff(diff, idx)?;
+        let binary = delta.flags().is_binary() || patch.is_none();
+        let (additions, deletions) = match &patch {
+            Some(patch) => {
+                let (_, additions, deletions) = patch.line_stats()?;
+                (additions, deletions)
+            }
+            None => (0, 0),
+        };
+        let hunks = match patch {
+            Some(patch) if !binary && status != ChangeStatus::Deleted => collect_hunks(&patch)?,
+            _ => Vec::new(),
+        };
+
+        changes.push(FileChange {
+            path,
+            old_path,
+            status,
+            hunks,
+            binary,
+            additions,
+            deletions,
+            mode_change: mode_change(delta.old_file().mode(), delta.new_file().mode()),
+        });
+    }
+
+    Ok(changes)
+}
+
+/// The staged change of each file as a patch that `git apply` accepts,
+/// binary files included, in the same order as [`staged_changes`]. The
+/// bytes are kept as they are, as the files need not be UTF-8.
+pub fn staged_patches(repo: &Repository) -> Result<Vec<(PathBuf, Vec<u8>)>, git2::Error> {
+    let mut opts = DiffOptions::new();
+    opts.show_binary(true);
+    let diff = staged_diff(repo, false, opts)?;
+    let mut patches = Vec::new();
+    for (idx, delta) in diff.deltas().enumerate() {
+        let path = match delta.new_file().path().or(delta.old_file().path()) {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        if let Some(mut patch) = Patch::from_diff(&diff, idx)? {
+            patches.push((path, patch.to_buf()?.to_vec()));
+        }
+    }
+    Ok(patches)
+}
+
+/// Whether the index differs from `HEAD` at all, excluded files included.
+pub fn has_staged_changes(repo: &Repository) -> Result<bool, git2::Error> {
+    Ok(staged_diff(repo, false, DiffOptions::new())?.deltas().len() > 0)
+}
+
+fn staged_diff(
+    repo: &Repository,
+    amend: bool,
+    mut opts: DiffOptions,
+) -> Result<Diff<'_>, git2::Error> {
+    // An unborn branch has no HEAD tree yet; diff the index against nothing.
+    let head_commit = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(err) => return Err(err),
+    };
+    let base_tree = match head_commit {
+        Some(commit) if amend => match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        },
+        Some(commit) => Some(commit.tree()?),
+        None => None,
+    };
+    let index = repo.index()?;
+    let mut diff = repo.diff_tree_to_index(base_tree.as_ref(), Some(&index), Some(&mut opts))?;
+
+    let mut find_opts = DiffFindOptions::new();
+    find_opts.renames(true).copies(true);
+    diff.find_similar(Some(&mut find_opts))?;
+    Ok(diff)
+}
+
+/// Only reports a mode change when the file exists on both sides.
+fn mode_change(old: FileMode, new: FileMode) -> Option<(u32, u32)> {
+    match (old, new) {
+        (FileMode::Unreadable, _) | (_, FileMode::Unreadable) => None,
+        (old, new) if old == new => None,
+        (old, new) => Some((u32::from(old), u32::from(new))),
+    }
+}
+
+fn collect_hunks(patch: &Patch) -> Result<Vec<Hunk>, git2::Error> {
+    let mut hunks = Vec::with_capacity(patch.num_hunks());
+    for hunk_idx in 0..patch.num_hunks() {
+        let (hunk, line_count) = patch.hunk(hunk_idx)?;
+        let mut lines = Vec::with_capacity(line_count);
+        for line_idx in 0..line_count {
+            let line = patch.line_in_hunk(hunk_idx, line_idx)?;
+            let content = String::from_utf8_lossy(line.content());
+            lines.push(format!(
+                "{}{}",
+                line.origin(),
+                content.trim_end_matches('\n')
+            ));
+        }
+        hunks.push(Hunk {
+            header: String::from_utf8_lossy(hunk.header())
+                .trim_end()
+                .to_string(),
+            lines,
+        });
+    }
+    Ok(hunks)
+}
+
+/// Flags forwarded to `git commit` by [`create_commit`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommitOptions {
+    pub amend: bool,
+    pub signoff: bool,
+}
+
+/// Commits the index with `message` and returns the new commit id.
+///
+/// This runs `git commit` rather than writing the commit through libgit2 so
+/// hooks and signing settings (`commit.gpgsign`, `gpg.format`,
+/// `user.signingkey`) behave exactly as for a manual commit. The message is
+/// passed on stdin, never through a shell.
+pub fn create_commit(
+    repo: &Repository,
+    message: &str,
+    options: CommitOptions,
+) -> io::Result<git2::Oid> {
+    let workdir = repo.workdir().unwrap_or_else(|| repo.path());
+    let mut command = Command::new("git");
+    command
+        .current_dir(workdir)
+        .args(["commit", "--file=-", "--cleanup=whitespace", "--quiet"]);
+    if options.amend {
+        command.arg("--amend");
+    }
+    if options.signoff {
+        command.arg("--signoff");
+    }
+
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::other("Could not open stdin for git commit"))?
+        .write_all(message.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("git commit failed ({})", status)));
+    }
+
+    repo.refname_to_id("HEAD").map_err(io::Error::other)
+}
+
+/// The state [`restore_index`] puts back: where `HEAD` pointed and what the
+/// index contained.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexSnapshot {
+    head: Option<git2::Oid>,
+    tree: git2::Oid,
+}
+
+pub fn snapshot_index(repo: &Repository) -> Result<IndexSnapshot, git2::Error> {
+    let mut index = repo.index()?;
+    index.read(true)?;
+    Ok(IndexSnapshot {
+        head: repo.refname_to_id("HEAD").ok(),
+        tree: index.write_tree()?,
+    })
+}
+
+/// Moves `HEAD` back to the snapshot, dropping commits made since, and
+/// restores the index. The working tree is left alone.
+pub fn restore_index(repo: &Repository, snapshot: &IndexSnapshot) -> Result<(), git2::Error> {
+    match snapshot.head {
+        Some(oid) => {
+            let commit = repo.find_object(oid, Some(git2::ObjectType::Commit))?;
+            repo.reset(&commit, git2::ResetType::Soft, None)?;
+        }
+        // The branch was unborn: delete it again.
+        None => {
+            let head = repo.find_reference("HEAD")?;
+            if let Some(target) = head.symbolic_target() {
+                if let Ok(mut branch) = repo.find_reference(target) {
+                    branch.delete()?;
+                }
+            }
+        }
+    }
+    let mut index = repo.index()?;
+    index.read_tree(&repo.find_tree(snapshot.tree)?)?;
+    index.write()
+}
+
+/// Unstages everything, leaving the index equal to `HEAD`.
+pub fn reset_index(repo: &Repository) -> Result<(), git2::Error> {
+    let mut index = repo.index()?;
+    match repo.head() {
+        Ok(head) => index.read_tree(&head.peel_to_tree()?)?,
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => index.clear()?,
+        Err(err) => return Err(err),
+    }
+    index.write()
+}
+
+/// Stages `patch` with `git apply --cached`, which tolerates hunks whose
+/// line numbers moved because other hunks of the file were left out.
+pub fn apply_to_index(repo: &Repository, patch: &[u8]) -> io::Result<()> {
+    let workdir = repo.workdir().unwrap_or_else(|| repo.path());
+    let mut child = Command::new("git")
+        .current_dir(workdir)
+        .args(["apply", "--cached", "--whitespace=nowarn", "-"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::other("Could not open stdin for git apply"))?
+        .write_all(patch)?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("git apply failed ({})", status)));
+    }
+    Ok(())
+}
+
+/// A work tree path whose changes are not staged yet.
+#[derive(Debug, Clone)]
+pub struct UnstagedPath {
+    pub path: PathBuf,
+    /// `false` for untracked files, which `--all` leaves alone.
+    pub tracked: bool,
+    pub deleted: bool,
+}
+
+/// Every path with unstaged changes, untracked files included and ignored
+/// files left out.
+pub fn unstaged_paths(repo: &Repository) -> Result<Vec<UnstagedPath>, git2::Error> {
+    let mut options = git2::StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+    let mut paths = Vec::new();
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        let status = entry.status();
+        let changed = git2::Status::WT_NEW
+            | git2::Status::WT_MODIFIED
+            | git2::Status::WT_DELETED
+            | git2::Status::WT_TYPECHANGE
+            | git2::Status::WT_RENAMED;
+        if !status.intersects(changed) {
+            continue;
+        }
+        if let Some(path) = entry.path() {
+            paths.push(UnstagedPath {
+                path: PathBuf::from(path),
+                tracked: !status.contains(git2::Status::WT_NEW),
+                deleted: status.contains(git2::Status::WT_DELETED),
+            });
+        }
+    }
+    Ok(paths)
+}
+
+/// Stages `paths` as they are in the work tree; deleted files are removed
+/// from the index.
+pub fn stage_paths(repo: &Repository, paths: &[UnstagedPath]) -> Result<(), git2::Error> {
+    let mut index = repo.index()?;
+    for path in paths {
+        if path.deleted {
+            index.remove_path(&path.path)?;
+        } else {
+            index.add_path(&path.path)?;
+        }
+    }
+    index.write()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scratch_repo(name: &str) -> Repository {
+        let dir = std::env::temp_dir().join(format!("rcommit-git-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        repo
+    }
+
+    /// A commit whose tree holds `file` with `content`, not on any branch.
+    fn commit(repo: &Repository, parents: &[Oid], file: &str, content: &str, message: &str) -> Oid {
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert(file, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let parents: Vec<git2::Commit> = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect();
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        let signature = repo.signature().unwrap();
+        repo.commit(None, &signature, &signature, message, &tree, &parents)
+            .unwrap()
+    }
+
+    fn checkout(repo: &Repository, tip: Oid) {
+        repo.reference("refs/heads/main", tip, true, "test")
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+    }
+
+    fn head(repo: &Repository) -> git2::Commit<'_> {
+        repo.head().unwrap().peel_to_commit().unwrap()
+    }
+
+    fn message(commit: &git2::Commit) -> String {
+        commit.message().unwrap().trim().to_string()
+    }
+
+    fn reword(repo: &Repository, changes: &[(Oid, &str)]) -> Reworded {
+        let messages = changes
+            .iter()
+            .map(|(oid, message)| (*oid, message.to_string()))
+            .collect();
+        reword_commits(repo, &messages).unwrap()
+    }
+
+    #[test]
+    fn rewords_the_root_commit_and_recreates_the_rest() {
+        let repo = scratch_repo("root");
+        let a = commit(&repo, &[], "f", "1", "wip");
+        let b = commit(&repo, &[a], "f", "2", "feat: two");
+        let c = commit(&repo, &[b], "f", "3", "feat: three");
+        checkout(&repo, c);
+
+        let result = reword(&repo, &[(a, "feat: one")]);
+        assert_eq!(result.rewritten, 3);
+        let new_c = head(&repo);
+        assert_eq!(new_c.id(), result.head);
+        assert_eq!(message(&new_c), "feat: three");
+        assert_eq!(new_c.tree_id(), repo.find_commit(c).unwrap().tree_id());
+        let new_b = new_c.parent(0).unwrap();
+        assert_eq!(message(&new_b), "feat: two");
+        let new_a = new_b.parent(0).unwrap();
+        assert_eq!(message(&new_a), "feat: one");
+        assert_eq!(new_a.parent_count(), 0);
+        assert_eq!(repo.refname_to_id("ORIG_HEAD").unwrap(), c);
+    }
+
+    #[test]
+    fn keeps_the_side_branch_of_a_later_merge() {
+        let repo = scratch_repo("merge");
+        let a = commit(&repo, &[], "f", "1", "feat: one");
+        let b = commit(&repo, &[a], "f", "2", "wip");
+        let side = commit(&repo, &[a], "f", "s", "fix: side");
+        let merge = commit(&repo, &[b, side], "f", "m", "Merge branch 'side'");
+        checkout(&repo, merge);
+
+        let result = reword(&repo, &[(b, "feat: two")]);
+        assert_eq!(result.rewritten, 2);
+        let new_merge = head(&repo);
+        assert_eq!(message(&new_merge), "Merge branch 'side'");
+        let parents: Vec<Oid> = new_merge.parent_ids().collect();
+        assert_ne!(parents[0], b);
+        assert_eq!(message(&repo.find_commit(parents[0]).unwrap()), "feat: two");
+        assert_eq!(parents[1], side);
+        assert_eq!(new_merge.parent(0).unwrap().parent_id(0).unwrap(), a);
+    }
+
+    #[test]
+    fn rewords_two_commits_on_one_line() {
+        let repo = scratch_repo("two");
+        let a = commit(&repo, &[], "f", "1", "feat: one");
+        let b = commit(&repo, &[a], "f", "2", "wip 1");
+        let c = commit(&repo, &[b], "f", "3", "feat: three");
+        let d = commit(&repo, &[c], "f", "4", "wip 2");
+        let e = commit(&repo, &[d], "f", "5", "feat: five");
+        checkout(&repo, e);
+
+        let result = reword(&repo, &[(d, "fix: four"), (b, "feat: two")]);
+        assert_eq!(result.rewritten, 4);
+        let mut messages = Vec::new();
+        let mut commit = head(&repo);
+        while commit.parent_count() > 0 {
+            messages.push(message(&commit));
+            commit = commit.parent(0).unwrap();
+        }
+        assert_eq!(commit.id(), a);
+        assert_eq!(
+            messages,
+            ["feat: five", "fix: four", "feat: three", "feat: two"]
+        );
+    }
+
+    #[test]
+    fn reports_signatures_and_tags_it_cannot_keep() {
+        let repo = scratch_repo("signed");
+        let a = commit(&repo, &[], "f", "1", "feat: one");
+        let b = commit(&repo, &[a], "f", "2", "wip");
+        // `c` is `b`'s child, signed and with an encoding header.
+        let signature = repo.signature().unwrap();
+        let tree = repo.find_commit(b).unwrap().tree().unwrap();
+        let parent = repo.find_commit(b).unwrap();
+        let buffer = repo
+            .commit_create_buffer(&signature, &signature, "feat: three\n", &tree, &[&parent])
+            .unwrap();
+        let buffer = buffer
+            .as_str()
+            .unwrap()
+            .replacen("\n\n", "\nencoding UTF-8\n\n", 1);
+        let c = repo
+            .commit_signed(
+                &buffer,
+                "-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----",
+                None,
+            )
+            .unwrap();
+        checkout(&repo, c);
+        repo.reference("refs/tags/v0.1.0", a, false, "test")
+            .unwrap();
+        repo.reference("refs/tags/v0.2.0", c, false, "test")
+            .unwrap();
+
+        let result = reword(&repo, &[(b, "feat: two")]);
+        assert_eq!(result.rewritten, 2);
+        assert_eq!(result.unsigned, 1);
+        assert_eq!(result.stale_tags, ["v0.2.0"]);
+        assert!(repo.extract_signature(&result.head, None).is_err());
+        assert_eq!(message(&head(&repo)), "feat: three");
+    }
+
+    #[test]
+    fn moved_commits_keep_their_message_bytes() {
+        let repo = scratch_repo("encoding");
+        let a = commit(&repo, &[], "f", "1", "wip");
+        // `b` has a Latin-1 message, which is not valid UTF-8.
+        let signature = repo.signature().unwrap();
+        let tree = repo.find_commit(a).unwrap().tree().unwrap();
+        let parent = repo.find_commit(a).unwrap();
+        let buffer = repo
+            .commit_create_buffer(&signature, &signature, "", &tree, &[&parent])
+            .unwrap();
+        let mut object = buffer.to_vec();
+        object.pop();
+        object.extend_from_slice(b"encoding ISO-8859-1\n\nfix: caf\xe9 menu\n\nVoil\xe0.\n");
+        let b = repo
+            .odb()
+            .unwrap()
+            .write(git2::ObjectType::Commit, &object)
+            .unwrap();
+        checkout(&repo, b);
+
+        let result = reword(&repo, &[(a, "feat: one")]);
+        assert_eq!(result.rewritten, 2);
+        let moved = repo.find_commit(result.head).unwrap();
+        assert_ne!(moved.id(), b);
+        assert_eq!(
+            moved.message_raw_bytes(),
+            b"fix: caf\xe9 menu\n\nVoil\xe0.\n"
+        );
+        assert_eq!(moved.message_encoding(), Some("ISO-8859-1"));
+        assert_eq!(message(&moved.parent(0).unwrap()), "feat: one");
+    }
+}
@@ -0,0 +1,89 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use git2::Repository;
+
+const HOOK_NAME: &str = "prepare-commit-msg";
+const HOOK_MARKER: &str = "# Installed by rcommit";
+
+/// Returns the directory git runs hooks from, honouring `core.hooksPath`.
+fn hooks_dir(repo: &Repository) -> PathBuf {
+    let configured = repo
+        .config()
+        .and_then(|config| config.get_path("core.hooksPath"))
+        .ok();
+    match configured {
+        Some(path) if path.is_absolute() => path,
+        Some(path) => repo.workdir().unwrap_or_else(|| repo.path()).join(path),
+        None => repo.path().join("hooks"),
+    }
+}
+
+fn is_rcommit_hook(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|script| script.contains(HOOK_MARKER))
+        .unwrap_or(false)
+}
+
+/// Writes a `prepare-commit-msg` hook that calls `rcommit hook run`. An
+/// existing hook that rcommit did not install is only replaced with `force`.
+pub fn install(repo: &Repository, force: bool) -> io::Result<PathBuf> {
+    let dir = hooks_dir(repo);
+    let path = dir.join(HOOK_NAME);
+    if path.exists() && !force && !is_rcommit_hook(&path) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists and was not installed by rcommit (use --force to replace it)",
+                path.display()
+            ),
+        ));
+    }
+
+    let exe = env::current_exe()?;
+    let script = format!(
+        "#!/bin/sh\n{}\nexec '{}' hook run \"$@\"\n",
+        HOOK_MARKER,
+        exe.to_string_lossy().replace('\'', "'\\''")
+    );
+    fs::create_dir_all(&dir)?;
+    fs::write(&path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(path)
+}
+
+/// Removes the hook if rcommit installed it. Returns the removed path.
+pub fn uninstall(repo: &Repository) -> io::Result<Option<PathBuf>> {
+    let path = hooks_dir(repo).join(HOOK_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    if !is_rcommit_hook(&path) {
+        return Err(io::Error::other(format!(
+            "{} was not installed by rcommit, leaving it in place",
+            path.display()
+        )));
+    }
+    fs::remove_file(&path)?;
+    Ok(Some(path))
+}
+
+/// Decides from git's commit-source argument whether a message should be
+/// generated. Messages given with `-m`/`-F`, merges, squashes and amends or
+/// `-c`/`-C` reuse already carry a message of their own.
+pub fn should_generate(source: Option<&str>) -> bool {
+    !matches!(source, Some("message" | "merge" | "squash" | "commit"))
+}
+
+/// Puts `message` at the top of the commit message file, keeping whatever
+/// git already wrote there (template text and the `#` help comments).
+pub fn prefill_message_file(path: &Path, message: &str) -> io::Result<()> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    fs::write(path, format!("{}\n{}", message.trim_end(), existing))
+}
@@ -1,42 +1,1604 @@
-use std::io::{self, BufRead};
-use std::process::{Command, Stdio};
+mod budget;
+mod bump;
+mod changelog;
+mod config;
+mod conventional;
+mod error;
+mod exclude;
+mod git;
+mod hook;
+mod offline;
+mod output;
+mod pr;
+mod provider;
+mod report;
+mod review;
+mod scope;
+mod secrets;
+mod split;
+mod style;
+mod template;
 
-use clap::{App, Arg};
-use clipboard::{ClipboardContext, ClipboardProvider};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::process::ExitCode;
+
+use budget::DiffPlan;
+use bump::{Bump, Version};
+use changelog::Changelog;
+use clap::{App, Arg, ArgMatches};
+use config::{Config, OutputMode};
+use error::AppError;
+use exclude::ExcludeMatcher;
+use git::{CommitOptions, FileChange};
+use git2::Repository;
 use langchain_rust::chain::chain_trait::Chain;
-use langchain_rust::chain::llm_chain::LLMChainBuilder;
-use langchain_rust::llm::openai::{OpenAI, OpenAIModel};
+use langchain_rust::chain::llm_chain::{LLMChain, LLMChainBuilder};
+use langchain_rust::language_models::{GenerateResult, TokenUsage};
 use langchain_rust::prompt::HumanMessagePromptTemplate;
-use langchain_rust::{prompt_args, template_jinja2};
+use langchain_rust::schemas::messages::Message;
+use langchain_rust::{
+    fmt_placeholder, fmt_template, message_formatter, prompt_args, template_jinja2,
+};
+use output::Copied;
+use provider::ChatModel;
+use review::{MixedScopesAction, ReviewAction, RewordAction, StageAction};
+use scope::ScopeResolver;
+use secrets::SecretPolicy;
+use serde_json::json;
+use split::{Group, StagedSplit};
+use style::SampleFilter;
+use template::{Template, TemplateVars};
+
+const MAX_VALIDATION_ATTEMPTS: usize = 3;
+const RECENT_COMMIT_COUNT: usize = 10;
+/// Reply length assumed per request when `--dry-run` estimates the cost.
+const ASSUMED_OUTPUT_TOKENS: usize = 200;
+/// Sent after a commit's old message to have `rcommit reword` replace it.
+const REWORD_REQUEST: &str = "The commit message above was written when the commit was made. \
+Rewrite it from the changes instead, keeping what the changes cannot show, such as the motivation \
+and ticket references. Reply with only the corrected commit message, no quotes or code fences.";
+
+const SUMMARY_PROMPT: &str = r#"
+    Summarize each file in the following changes as one short bullet point,
+    starting with the file path. Mention what changed and why if it is clear.
+    File changes:
+        {{input}}
+    "#;
 
 #[tokio::main] // This attribute makes your main function asynchronous
-async fn main() -> io::Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            if let Some(hint) = err.hint() {
+                eprintln!("hint: {}", hint);
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+async fn run() -> Result<(), AppError> {
     let matches = initialize_command_line_interface();
-    let context = matches.value_of("context").unwrap_or("no context");
-    let model = parse_model_argument(matches.value_of("model").unwrap_or("gpt3.5"));
-    let exclude_patterns = matches
-        .values_of("exclude")
-        .unwrap_or_default()
-        .collect::<Vec<&str>>();
-    let git_diff_output = execute_git_diff_command(&exclude_patterns)?;
-    let commit_message = generate_commit_message(&git_diff_output, &context, model).await;
-    let formatter = format!("git commit -m \"{}\"", commit_message.replace("\"", "\\\""));
-    if matches.is_present("git") {
-        copy_to_clipboard(&formatter).expect("Could not copy to clipboard");
+    let (command, command_matches) = matches.subcommand().unwrap_or(("", &matches));
+    let repo = git::open_repository().ok();
+    let config = Config::load(repo.as_ref().and_then(Repository::workdir), command_matches);
+    if command == "hook" {
+        return run_hook_command(command_matches, config).await;
+    }
+    let config = config?;
+    match command {
+        "config" => {
+            print!("{}", config);
+            Ok(())
+        }
+        "template" => run_template_command(command_matches, repo.as_ref()),
+        "split" => run_split_command(command_matches, &config).await,
+        "pr" => run_pr_command(command_matches, &config).await,
+        "changelog" => run_changelog_command(command_matches, &config).await,
+        "bump" => run_bump_command(command_matches),
+        "reword" => run_reword_command(command_matches, &config).await,
+        _ => run_commit_command(&matches, &config).await,
+    }
+}
+
+async fn run_commit_command(matches: &ArgMatches, config: &Config) -> Result<(), AppError> {
+    // Checked here rather than by clap: `output = "commit"` in the config or
+    // RCOMMIT_OUTPUT counts as much as `--commit`.
+    for flag in ["amend", "signoff"] {
+        if matches.is_present(flag) && config.output.value != OutputMode::Commit {
+            return Err(AppError::NeedsCommitOutput(flag));
+        }
+    }
+    let commit_options = CommitOptions {
+        amend: matches.is_present("amend"),
+        signoff: matches.is_present("signoff"),
+    };
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    // Only ask questions when someone is there to answer them.
+    let dry_run = matches.is_present("dry-run");
+    // Stdout belongs to the JSON document, so there is no review either.
+    let json_format =
+        matches.value_of("format") == Some("json") || config.output.value == OutputMode::Json;
+    let interactive =
+        !matches.is_present("yes") && !dry_run && !json_format && io::stdin().is_terminal();
+    if matches.is_present("all") {
+        stage_tracked_changes(&repo)?;
+    }
+    let mut file_changes =
+        execute_git_diff_command(&repo, &config.exclude_patterns(), commit_options.amend)?;
+    if file_changes.is_empty() && interactive && !git::has_staged_changes(&repo)? {
+        if !offer_to_stage(&repo)? {
+            println!("Aborted, nothing was committed or copied.");
+            return Ok(());
+        }
+        file_changes =
+            execute_git_diff_command(&repo, &config.exclude_patterns(), commit_options.amend)?;
+    }
+    if file_changes.is_empty() {
+        return Err(AppError::NothingStaged);
+    }
+    guard_secrets(config, &mut file_changes)?;
+    let scopes = infer_scopes(&repo, config, &file_changes)?;
+    if scopes.len() > 1 {
+        report::warning(format!(
+            "the staged changes span several scopes: {}",
+            scopes.join(", ")
+        ));
+        if interactive {
+            match review::confirm_mixed_scopes(&scopes, !commit_options.amend)? {
+                MixedScopesAction::Continue => {}
+                MixedScopesAction::Split => {
+                    return split_staged_changes(&repo, config, matches, commit_options).await;
+                }
+                MixedScopesAction::Abort => {
+                    println!("Aborted, nothing was committed or copied.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+    if dry_run {
+        return print_dry_run(&repo, config, matches, &file_changes, &scopes);
+    }
+    let mut session = MessageSession::start(&repo, config, matches, &file_changes, &scopes).await?;
+    if matches.is_present("show-prompt") {
+        session.show_prompt();
+    }
+    let mut history = Vec::new();
+    let mut commit_message = session.generate(&history).await?;
+
+    if interactive {
+        loop {
+            match review::review_message(&commit_message)? {
+                ReviewAction::Accept(message) => {
+                    commit_message = message;
+                    break;
+                }
+                ReviewAction::Regenerate { draft, .. } if session.is_offline() => {
+                    commit_message = draft;
+                    report::note(
+                        "the offline generator writes the same message every time, \
+                         edit it instead",
+                    );
+                }
+                ReviewAction::Regenerate { draft, feedback } => {
+                    history.push(Message::new_ai_message(&draft));
+                    history.push(Message::new_human_message(format!(
+                        "Revise the commit message above. Feedback: {}",
+                        feedback
+                    )));
+                    commit_message = session.generate(&history).await?;
+                }
+                ReviewAction::Abort => {
+                    println!("Aborted, nothing was committed or copied.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+    // `--output json` is shorthand for `--format json` printed to stdout.
+    match (&config.output.value, json_format) {
+        (OutputMode::Json, _) | (_, true) => {
+            print_json(&repo, &session, &commit_message, commit_options)?;
+        }
+        (OutputMode::Commit, _) => {
+            let oid = git::create_commit(&repo, &commit_message, commit_options)?;
+            println!("Created commit {}", oid);
+        }
+        (OutputMode::Git, _) => {
+            let formatter = format!("git commit -m {}", shell_quote(&commit_message));
+            copy_or_print(&formatter);
+        }
+        (OutputMode::Clipboard, _) => copy_or_print(&commit_message),
+        (OutputMode::Stdout, _) => println!("{}", commit_message),
+        (OutputMode::File(path), _) => {
+            fs::write(path, format!("{}\n", commit_message))?;
+            eprintln!("Wrote the message to {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Emits the message as a JSON document: committed first with `--commit`,
+/// written to the file with `--output file:<path>`, printed otherwise.
+fn print_json(
+    repo: &Repository,
+    session: &MessageSession<'_>,
+    commit_message: &str,
+    commit_options: CommitOptions,
+) -> Result<(), AppError> {
+    let commit = match session.config.output.value {
+        OutputMode::Commit => Some(git::create_commit(repo, commit_message, commit_options)?),
+        _ => None,
+    };
+    let document = session.to_json(commit_message, commit);
+    match &session.config.output.value {
+        OutputMode::File(path) => {
+            fs::write(path, format!("{:#}\n", document))?;
+            eprintln!("Wrote the message to {}", path.display());
+        }
+        _ => println!("{}", document),
+    }
+    Ok(())
+}
+
+/// Stages every change to tracked files, like `git commit -a`.
+fn stage_tracked_changes(repo: &Repository) -> Result<(), AppError> {
+    let tracked: Vec<_> = git::unstaged_paths(repo)?
+        .into_iter()
+        .filter(|path| path.tracked)
+        .collect();
+    git::stage_paths(repo, &tracked)?;
+    Ok(())
+}
+
+/// Asks what to stage when the index is empty. Returns `false` when the
+/// user stages nothing.
+fn offer_to_stage(repo: &Repository) -> Result<bool, AppError> {
+    let unstaged = git::unstaged_paths(repo)?;
+    if unstaged.is_empty() {
+        return Err(AppError::NothingStaged);
+    }
+    let has_tracked = unstaged.iter().any(|path| path.tracked);
+    let chosen: Vec<_> = match review::choose_what_to_stage(has_tracked)? {
+        StageAction::Tracked => unstaged.into_iter().filter(|path| path.tracked).collect(),
+        StageAction::Pick => {
+            let labels: Vec<String> = unstaged
+                .iter()
+                .map(|path| {
+                    let label = match (path.tracked, path.deleted) {
+                        (false, _) => "new",
+                        (true, true) => "deleted",
+                        (true, false) => "modified",
+                    };
+                    format!("{} ({})", path.path.display(), label)
+                })
+                .collect();
+            let picked = review::pick_files(&labels)?;
+            unstaged
+                .into_iter()
+                .enumerate()
+                .filter(|(idx, _)| picked.contains(idx))
+                .map(|(_, path)| path)
+                .collect()
+        }
+        StageAction::Abort => return Ok(false),
+    };
+    if chosen.is_empty() {
+        return Ok(false);
+    }
+    git::stage_paths(repo, &chosen)?;
+    Ok(true)
+}
+
+async fn run_split_command(matches: &ArgMatches, config: &Config) -> Result<(), AppError> {
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    let options = CommitOptions {
+        amend: false,
+        signoff: matches.is_present("signoff"),
+    };
+    split_staged_changes(&repo, config, matches, options).await
+}
+
+/// Asks the model to group the staged hunks into atomic commits, shows the
+/// plan and, once confirmed (or with `--yes`), commits each group in turn.
+/// If any step fails, `HEAD` and the index are put back as they were.
+async fn split_staged_changes(
+    repo: &Repository,
+    config: &Config,
+    matches: &ArgMatches,
+    options: CommitOptions,
+) -> Result<(), AppError> {
+    if config.offline.value {
+        return Err(AppError::Other(
+            "splitting needs a model to group the hunks and does not work offline".to_string(),
+        ));
+    }
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    let matcher = ExcludeMatcher::new(root, &config.exclude_patterns())?;
+    let resolver = ScopeResolver::new(
+        root,
+        &config.scope_rules.value,
+        config.workspace_scopes.value,
+        &config.scopes.value,
+    )?;
+    let mut staged = StagedSplit::read(repo, &matcher, &resolver)?;
+    let mut file_changes = git::staged_changes(repo, &matcher, false)?;
+    if file_changes.is_empty() && staged.len() == 0 {
+        return Err(AppError::NothingStaged);
+    }
+    guard_secrets(config, &mut file_changes)?;
+    if config.secrets.value == SecretPolicy::Redact {
+        staged.redact_secrets();
+    }
+    if staged.len() < 2 {
+        println!("Nothing to split: the staged change has fewer than two hunks.");
+        return Ok(());
+    }
+
+    let (prompt, shortened) = staged.prompt(
+        &config.types.value.join(", "),
+        &config.language.value,
+        config.max_tokens.value,
+    );
+    if shortened > 0 {
+        report::note(format!(
+            "the hunks are over {} tokens, {} of them were shortened",
+            config.max_tokens.value, shortened
+        ));
+    }
+    if matches.is_present("dry-run") {
+        return print_requests(config, &[("split", prompt)]);
+    }
+    if matches.is_present("show-prompt") {
+        print_prompt(&prompt);
+    }
+    let chain = build_commit_chain(build_llm(config)?)?;
+    let groups = propose_groups(&chain, config, &staged, &prompt).await?;
+    for (idx, group) in groups.iter().enumerate() {
+        println!("\nCommit {} of {}:", idx + 1, groups.len());
+        for line in group.message.lines() {
+            println!("    {}", line);
+        }
+        for line in staged.describe(group, idx + 1 == groups.len()) {
+            println!("  - {}", line);
+        }
+    }
+    println!();
+
+    if !matches.is_present("yes") {
+        if !io::stdin().is_terminal() {
+            println!("Run again with --yes to create these commits.");
+            return Ok(());
+        }
+        if !review::confirm(&format!("Create these {} commits?", groups.len()))? {
+            println!("Aborted, nothing was committed.");
+            return Ok(());
+        }
+    }
+    commit_groups(repo, &staged, &groups, options)
+}
+
+/// Asks for a grouping until every hunk is placed exactly once, feeding
+/// the problem back like [`generate_commit_message`] does. Messages that are
+/// still not conventional commits after the last attempt are used anyway.
+async fn propose_groups(
+    chain: &LLMChain,
+    config: &Config,
+    staged: &StagedSplit,
+    prompt: &str,
+) -> Result<Vec<Group>, AppError> {
+    let types = config.type_names();
+    let (groups, _) = generate_validated(
+        chain,
+        prompt,
+        &[],
+        "Reply with only the corrected JSON array.",
+        &mut None,
+        |raw| {
+            let mut groups = match staged.parse_groups(&conventional::clean_message(raw)) {
+                Ok(groups) => groups,
+                Err(err) => {
+                    return Verdict::Invalid {
+                        problem: format!("That grouping is not usable: {}", err),
+                        last_resort: Err(format!(
+                            "the model did not produce a usable split: {}",
+                            err
+                        )),
+                    }
+                }
+            };
+            let mut invalid = Vec::new();
+            for group in &mut groups {
+                let message = conventional::clean_message(&group.message);
+                match conventional::validate(&message, &types) {
+                    Ok(commit) => group.message = commit.to_string(),
+                    Err(err) => {
+                        invalid.push(format!(
+                            "`{}`: {}",
+                            message.lines().next().unwrap_or_default(),
+                            err
+                        ));
+                        group.message = message;
+                    }
+                }
+            }
+            if invalid.is_empty() {
+                return Verdict::Valid(groups);
+            }
+            Verdict::Invalid {
+                problem: format!(
+                    "Some messages are not valid Conventional Commits messages: {}",
+                    invalid.join("; ")
+                ),
+                last_resort: Ok((
+                    groups,
+                    invalid
+                        .iter()
+                        .map(|problem| {
+                            format!("the generated message still fails validation: {}", problem)
+                        })
+                        .collect(),
+                )),
+            }
+        },
+    )
+    .await?;
+    Ok(groups)
+}
+
+fn commit_groups(
+    repo: &Repository,
+    staged: &StagedSplit,
+    groups: &[Group],
+    options: CommitOptions,
+) -> Result<(), AppError> {
+    let snapshot = git::snapshot_index(repo)?;
+    let result = (|| -> Result<Vec<git2::Oid>, AppError> {
+        git::reset_index(repo)?;
+        let mut oids = Vec::with_capacity(groups.len());
+        for (idx, group) in groups.iter().enumerate() {
+            let last = idx + 1 == groups.len();
+            git::apply_to_index(repo, &staged.patch_for(group, last))?;
+            oids.push(git::create_commit(repo, &group.message, options)?);
+        }
+        Ok(oids)
+    })();
+
+    match result {
+        Ok(oids) => {
+            for (oid, group) in oids.iter().zip(groups) {
+                println!(
+                    "Created commit {} {}",
+                    oid,
+                    group.message.lines().next().unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        Err(err) => {
+            git::restore_index(repo, &snapshot)?;
+            Err(AppError::Other(format!(
+                "splitting failed, HEAD and the index were restored: {}",
+                err
+            )))
+        }
+    }
+}
+
+/// Writes a pull request title and description for the current branch from
+/// its commits and its combined diff against the base branch.
+async fn run_pr_command(matches: &ArgMatches, config: &Config) -> Result<(), AppError> {
+    if config.offline.value {
+        return Err(AppError::Other(
+            "writing a pull request needs a model and does not work offline".to_string(),
+        ));
+    }
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    let base = config
+        .pr_base
+        .value
+        .clone()
+        .or_else(|| git::default_base(&repo))
+        .ok_or_else(|| {
+            AppError::Other(
+                "could not detect the base branch; pass --base or set pr-base".to_string(),
+            )
+        })?;
+    let fork_point = git::merge_base(&repo, &base).map_err(|err| match err.code() {
+        git2::ErrorCode::NotFound => AppError::Other(format!(
+            "found no branch {} to compare with; pass another --base",
+            base
+        )),
+        _ => AppError::Git(err),
+    })?;
+    let mut commits = git::commits_since(&repo, fork_point)?;
+    if commits.is_empty() {
+        return Err(AppError::Other(format!(
+            "the current branch has no commits that are not on {}",
+            base
+        )));
+    }
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    let matcher = ExcludeMatcher::new(root, &config.exclude_patterns())?;
+    let mut file_changes = git::branch_changes(&repo, fork_point, &matcher)?;
+    guard_secrets(config, &mut file_changes)?;
+    guard_commit_messages(config, &mut commits)?;
+    let template = pr::load_template(repo.workdir())?;
+    if let Some(path) = &template.path {
+        report::note(format!("filling in {}", path.display()));
+    }
+
+    let branch = git::current_branch(&repo).unwrap_or_else(|| "(detached HEAD)".to_string());
+    let ticket = ticket(&repo, matches);
+    let context = matches
+        .value_of("context")
+        .filter(|context| *context != "no context");
+    let mut requests = Vec::new();
+    let mut usage = None;
+    let input = if matches.is_present("dry-run") {
+        dry_run_diff_input(config, &file_changes, &mut requests)
+    } else {
+        prepare_diff_input(config, &file_changes, &mut usage).await?
+    };
+    let prompt = template.prompt(&pr::PromptVars {
+        branch: &branch,
+        base: &base,
+        language: &config.language.value,
+        commits: &commits,
+        ticket: ticket.as_deref(),
+        context,
+        input: &input,
+    });
+    if matches.is_present("dry-run") {
+        requests.push(("pull request", prompt));
+        return print_requests(config, &requests);
+    }
+    if matches.is_present("show-prompt") {
+        print_prompt(&prompt);
+    }
+
+    let chain = build_commit_chain(build_llm(config)?)?;
+    let pull_request = generate_pull_request(&chain, &template, &prompt, &mut usage).await?;
+    if matches.value_of("format") == Some("json") {
+        println!(
+            "{}",
+            json!({
+                "title": pull_request.title,
+                "body": pull_request.body,
+                "base": base,
+                "commits": commits.len(),
+                "template": template.path.as_ref().map(|path| path.display().to_string()),
+                "usage": usage,
+                "warnings": report::diagnostics(),
+            })
+        );
+    } else {
+        println!("{}\n\n{}", pull_request.title, pull_request.body);
+    }
+    Ok(())
+}
+
+/// Asks for the pull request until the reply has a title and every section
+/// of the template, feeding the problem back like [`generate_commit_message`]
+/// does. After the last attempt, missing sections keep the template's text.
+async fn generate_pull_request(
+    chain: &LLMChain,
+    template: &pr::PrTemplate,
+    prompt: &str,
+    usage: &mut Option<TokenUsage>,
+) -> Result<pr::PullRequest, AppError> {
+    let (pull_request, _) = generate_validated(
+        chain,
+        prompt,
+        &[],
+        "Reply with the whole pull request again: the title, a blank line and every \
+         section of the template.",
+        usage,
+        |raw| match template.parse_reply(raw) {
+            Ok(pull_request) if pull_request.missing.is_empty() => Verdict::Valid(pull_request),
+            Ok(pull_request) => {
+                let missing = pull_request.missing.join(", ");
+                Verdict::Invalid {
+                    problem: format!(
+                        "The description leaves out these sections of the template: {}",
+                        missing
+                    ),
+                    last_resort: Ok((
+                        pull_request,
+                        vec![format!("the description still leaves out {}", missing)],
+                    )),
+                }
+            }
+            Err(err) => Verdict::Invalid {
+                problem: format!("That pull request is not usable: {}", err),
+                last_resort: Err(format!(
+                    "the model did not write a usable pull request: {}",
+                    err
+                )),
+            },
+        },
+    )
+    .await?;
+    Ok(pull_request)
+}
+
+/// Lists the conventional commits of a range by type, as Keep a Changelog
+/// Markdown or JSON, with `--rewrite` turning the subjects into release
+/// notes first.
+async fn run_changelog_command(matches: &ArgMatches, config: &Config) -> Result<(), AppError> {
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    let (from, to) = match matches.value_of("range") {
+        Some(spec) => changelog::parse_range(spec),
+        None => default_changelog_range(&repo)?,
+    };
+    let range = format!("{}..{}", from.as_deref().unwrap_or_default(), to);
+    let commits: Vec<(String, String)> = git::commit_range(&repo, from.as_deref(), &to)?
+        .into_iter()
+        .map(|(oid, message)| (oid.to_string()[..7].to_string(), message))
+        .collect();
+    let version = matches
+        .value_of("release")
+        .map(str::to_string)
+        .or_else(|| git::is_tag(&repo, &to).then(|| to.clone()))
+        .map(|version| match version.strip_prefix('v') {
+            Some(number) if number.starts_with(|c: char| c.is_ascii_digit()) => number.to_string(),
+            _ => version,
+        });
+    let date = match version {
+        Some(_) => {
+            let (seconds, offset) = git::commit_time(&repo, &to)?;
+            Some(changelog::format_date(seconds, offset))
+        }
+        None => None,
+    };
+    let mut changelog = Changelog::new(version, date, &commits, matches.is_present("all"));
+    if changelog.skipped > 0 {
+        report::note(format!(
+            "left out {} of {} commits that are not conventional or only touch internals \
+             (pass --all to list them)",
+            changelog.skipped,
+            commits.len()
+        ));
+    }
+    if changelog.entries.is_empty() {
+        return Err(AppError::Other(format!(
+            "found no commits to list in {}",
+            range
+        )));
+    }
+
+    let mut usage = None;
+    if matches.is_present("rewrite") {
+        if config.offline.value {
+            return Err(AppError::Other(
+                "rewriting release notes needs a model and does not work offline".to_string(),
+            ));
+        }
+        let prompt = changelog.rewrite_prompt(&config.language.value);
+        if matches.is_present("dry-run") {
+            return print_requests(config, &[("release notes", prompt)]);
+        }
+        if matches.is_present("show-prompt") {
+            print_prompt(&prompt);
+        }
+        let chain = build_commit_chain(build_llm(config)?)?;
+        rewrite_release_notes(&chain, &mut changelog, &prompt, &mut usage).await?;
+    } else if matches.is_present("dry-run") {
+        report::note("nothing would be sent, only --rewrite calls the model");
+    }
+
+    if matches.value_of("format") == Some("json") {
+        println!(
+            "{}",
+            json!({
+                "version": changelog.version,
+                "date": changelog.date,
+                "range": range,
+                "sections": changelog.sections(),
+                "skipped": changelog.skipped,
+                "usage": usage,
+                "warnings": report::diagnostics(),
+            })
+        );
+    } else if let Some(path) = matches.value_of("prepend") {
+        if matches.is_present("dry-run") {
+            print!("{}", changelog.to_markdown());
+            return Ok(());
+        }
+        let existing = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        fs::write(path, changelog.prepend_to(&existing))?;
+        eprintln!("Added {} changes to {}", changelog.entries.len(), path);
+    } else {
+        print!("{}", changelog.to_markdown());
+    }
+    Ok(())
+}
+
+/// The range to list without one: from the latest release tag to `HEAD`,
+/// or, when `HEAD` is itself tagged, from the release before it to that tag.
+fn default_changelog_range(repo: &Repository) -> Result<(Option<String>, String), AppError> {
+    let tags = release_tags(repo)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    let Some(idx) = tags.iter().rposition(|(_, _, commit)| *commit == head) else {
+        return Ok((
+            tags.last().map(|(_, name, _)| name.clone()),
+            "HEAD".to_string(),
+        ));
+    };
+    let from = tags[..idx]
+        .iter()
+        .rev()
+        .find(|(_, _, commit)| *commit != head)
+        .map(|(_, name, _)| name.clone());
+    Ok((from, tags[idx].1.clone()))
+}
+
+/// Tags that parse as versions and are reachable from `HEAD`, oldest
+/// version first. Other tags, such as `deploy-*`, are not releases.
+fn release_tags(repo: &Repository) -> Result<Vec<(Version, String, git2::Oid)>, AppError> {
+    let mut tagged = Vec::new();
+    for (name, commit) in git::tags(repo)? {
+        if let Some(version) = Version::parse(&name) {
+            if git::reachable_from_head(repo, commit)? {
+                tagged.push((version, name, commit));
+            }
+        }
+    }
+    tagged.sort();
+    Ok(tagged)
+}
+
+/// Asks for one release note per entry until the reply fits, feeding the
+/// problem back like [`generate_commit_message`] does. If no reply fits, the
+/// commit subjects are kept with a warning.
+async fn rewrite_release_notes(
+    chain: &LLMChain,
+    changelog: &mut Changelog,
+    prompt: &str,
+    usage: &mut Option<TokenUsage>,
+) -> Result<(), AppError> {
+    generate_validated(
+        chain,
+        prompt,
+        &[],
+        "Reply with only the corrected JSON array.",
+        usage,
+        |raw| match changelog.apply_rewrite(&conventional::clean_message(raw)) {
+            Ok(()) => Verdict::Valid(()),
+            Err(err) => Verdict::Invalid {
+                problem: format!("That reply is not usable: {}", err),
+                last_resort: Ok((
+                    (),
+                    vec![format!(
+                        "could not use the rewritten notes ({}), keeping the commit subjects",
+                        err
+                    )],
+                )),
+            },
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Works out the next version from the conventional commits since the last
+/// semver tag, and optionally writes it to the manifests and tags it.
+fn run_bump_command(matches: &ArgMatches) -> Result<(), AppError> {
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    let root = repo.workdir().unwrap_or_else(|| repo.path()).to_path_buf();
+    let tagged: Vec<(Version, String)> = release_tags(&repo)?
+        .into_iter()
+        .map(|(version, name, _)| (version, name))
+        .collect();
+    let latest = tagged.last();
+    let stable = tagged
+        .iter()
+        .rev()
+        .find(|(version, _)| !version.is_prerelease());
+
+    let since_latest = git::commit_range(&repo, latest.map(|(_, tag)| tag.as_str()), "HEAD")?;
+    // A pre-release can be promoted, or moved to another channel, without
+    // new commits.
+    let promoting = latest.is_some_and(|(version, _)| {
+        version.is_prerelease()
+            && version.pre.first().map(String::as_str) != matches.value_of("pre")
+    });
+    if since_latest.is_empty() && !promoting {
+        return Err(AppError::Other(format!(
+            "there are no commits since {}, nothing to release",
+            latest.map_or("the start", |(_, tag)| tag.as_str())
+        )));
+    }
+    let since_stable = git::commit_range(&repo, stable.map(|(_, tag)| tag.as_str()), "HEAD")?;
+    let bumps: Vec<Bump> = since_stable
+        .iter()
+        .map(|(_, message)| Bump::of_message(message))
+        .collect();
+    let count = |kind: Bump| bumps.iter().filter(|bump| **bump == kind).count();
+    let required = bumps.iter().copied().max().unwrap_or(Bump::None);
+    let bump = if matches.is_present("release-as") {
+        matches.value_of_t_or_exit("release-as")
+    } else if required == Bump::None {
+        return Err(AppError::Other(format!(
+            "no feat, fix or breaking commits since {}; pass --release-as to release anyway",
+            stable.map_or("the start", |(_, tag)| tag.as_str())
+        )));
+    } else {
+        required
+    };
+
+    // Without a tag, start from the version the manifest already has.
+    let manifest_version =
+        || bump::cargo_version(&fs::read_to_string(root.join("Cargo.toml")).ok()?);
+    let stable_version = match stable {
+        Some((version, _)) => version.clone(),
+        None if latest.is_none() => manifest_version().unwrap_or(Version::ZERO),
+        None => Version::ZERO,
+    };
+    let latest_version = latest.map_or(stable_version.clone(), |(version, _)| version.clone());
+    let next = bump::next_version(
+        &stable_version,
+        &latest_version,
+        bump,
+        matches.value_of("pre"),
+    );
+    if next <= latest_version {
+        report::warning(format!(
+            "{} sorts before {}, which is already tagged",
+            next, latest_version
+        ));
+    }
+    let prefix = match latest {
+        Some((_, tag)) if !tag.starts_with('v') => "",
+        _ => "v",
+    };
+    let new_tag = format!("{}{}", prefix, next);
+    report::note(format!(
+        "{} -> {} ({}: {} breaking, {} feat, {} fix since {})",
+        latest.map_or(stable_version.to_string(), |(_, tag)| tag.clone()),
+        next,
+        bump,
+        count(Bump::Major),
+        count(Bump::Minor),
+        count(Bump::Patch),
+        stable.map_or("the start", |(_, tag)| tag.as_str())
+    ));
+
+    let mut updated = Vec::new();
+    if matches.is_present("write") {
+        // The release commit must hold the version change and nothing else.
+        if matches.is_present("tag") {
+            if git::has_staged_changes(&repo)? {
+                return Err(AppError::Other(
+                    "other changes are staged; commit or unstage them before tagging a release"
+                        .to_string(),
+                ));
+            }
+            let unstaged = git::unstaged_paths(&repo)?;
+            if let Some(file) = RELEASE_FILES
+                .iter()
+                .flat_map(|(manifest, lock, ..)| [manifest, lock])
+                .find(|file| unstaged.iter().any(|path| path.path == Path::new(file)))
+            {
+                return Err(AppError::Other(format!(
+                    "{} has unstaged changes; commit or stash them before tagging a release",
+                    file
+                )));
+            }
+        }
+        for (file, lock_file, set_version, set_lock_version) in RELEASE_FILES {
+            let path = root.join(file);
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Some(new_text) = set_version(&text, &next) else {
+                report::warning(format!("{} has no version to update", file));
+                continue;
+            };
+            fs::write(&path, new_text)?;
+            updated.push(file);
+            let lock_path = root.join(lock_file);
+            let Ok(lock) = fs::read_to_string(&lock_path) else {
+                continue;
+            };
+            match set_lock_version(&lock, &text, &next) {
+                Some(lock) => {
+                    fs::write(&lock_path, lock)?;
+                    updated.push(lock_file);
+                }
+                None => report::warning(format!("{} has no version to update", lock_file)),
+            }
+        }
+        if updated.is_empty() {
+            report::warning("found no Cargo.toml or package.json with a version to update");
+        }
+    }
+    if matches.is_present("tag") {
+        if !updated.is_empty() {
+            commit_release(&repo, &updated, &next)?;
+        }
+        git::create_tag(&repo, &new_tag, &format!("Release {}", next))?;
+    }
+
+    if matches.value_of("format") == Some("json") {
+        println!(
+            "{}",
+            json!({
+                "current": latest.map(|(version, _)| version.to_string()),
+                "next": next.to_string(),
+                "bump": bump.to_string(),
+                "since": latest.map(|(_, tag)| tag),
+                "commits": since_latest.len(),
+                "updated": updated,
+                "tag": matches.is_present("tag").then_some(&new_tag),
+                "warnings": report::diagnostics(),
+            })
+        );
     } else {
-        copy_to_clipboard(&commit_message).expect("Could not copy to clipboard");
+        println!("{}", next);
+        if !updated.is_empty() {
+            eprintln!("Updated {}", updated.join(", "));
+        }
+        if matches.is_present("tag") {
+            eprintln!("Created tag {}", new_tag);
+        }
+    }
+    Ok(())
+}
+
+type SetVersion = fn(&str, &Version) -> Option<String>;
+type SetLockVersion = fn(&str, &str, &Version) -> Option<String>;
+
+/// The manifests `bump --write` updates, each with its lock file.
+const RELEASE_FILES: [(&str, &str, SetVersion, SetLockVersion); 2] = [
+    (
+        "Cargo.toml",
+        "Cargo.lock",
+        bump::set_cargo_version,
+        bump::set_cargo_lock_version,
+    ),
+    (
+        "package.json",
+        "package-lock.json",
+        bump::set_package_json_version,
+        bump::set_package_lock_version,
+    ),
+];
+
+/// Commits the version change in `files` as `chore(release): <version>`.
+/// Ignored files, such as a lock file kept out of the repository, are not
+/// committed.
+fn commit_release(repo: &Repository, files: &[&str], version: &Version) -> Result<(), AppError> {
+    let mut tracked = Vec::new();
+    for file in files {
+        if !repo.is_path_ignored(file)? {
+            tracked.push(file);
+        }
+    }
+    let paths: Vec<git::UnstagedPath> = tracked
+        .iter()
+        .map(|file| git::UnstagedPath {
+            path: file.into(),
+            tracked: true,
+            deleted: false,
+        })
+        .collect();
+    git::stage_paths(repo, &paths)?;
+    git::create_commit(
+        repo,
+        &format!("chore(release): {}", version),
+        CommitOptions::default(),
+    )?;
+    Ok(())
+}
+
+/// Regenerates the messages of existing commits from their diffs, shows each
+/// next to the old one and rewrites the branch with the accepted ones.
+async fn run_reword_command(matches: &ArgMatches, config: &Config) -> Result<(), AppError> {
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    let targets = reword_targets(&repo, matches)?;
+    check_pushed(&repo, config, &targets, matches.is_present("force"))?;
+    let dry_run = matches.is_present("dry-run");
+    if dry_run && config.offline.value {
+        println!("Nothing would be sent: --offline writes the messages locally.");
+        return Ok(());
+    }
+    let interactive = !matches.is_present("yes") && !dry_run && io::stdin().is_terminal();
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    let matcher = ExcludeMatcher::new(root, &config.exclude_patterns())?;
+
+    let mut requests = Vec::new();
+    let mut messages = HashMap::new();
+    for (oid, old) in &targets {
+        let label = format!(
+            "{} {}",
+            &oid.to_string()[..7],
+            old.lines().next().unwrap_or_default()
+        );
+        let mut file_changes = git::commit_changes(&repo, *oid, &matcher)?;
+        if file_changes.is_empty() {
+            report::note(format!("{} changes no files, keeping its message", label));
+            continue;
+        }
+        guard_secrets(config, &mut file_changes)?;
+        let scopes = infer_scopes(&repo, config, &file_changes)?;
+        if dry_run {
+            let diff_input = dry_run_diff_input(config, &file_changes, &mut requests);
+            let vars = template_vars(&repo, config, matches, &file_changes, &scopes, diff_input);
+            requests.push((
+                "commit message",
+                load_template(config, &repo)?.render(&vars),
+            ));
+            continue;
+        }
+        let mut session =
+            MessageSession::start(&repo, config, matches, &file_changes, &scopes).await?;
+        if matches.is_present("show-prompt") {
+            session.show_prompt();
+        }
+        let mut history = vec![
+            Message::new_ai_message(old),
+            Message::new_human_message(REWORD_REQUEST),
+        ];
+        let mut new = session.generate(&history).await?;
+        if !interactive {
+            println!("\n{}", label);
+            println!("{}", review::side_by_side(old, &new));
+            messages.insert(*oid, new);
+            continue;
+        }
+        loop {
+            match review::review_reword(&label, old, &new)? {
+                RewordAction::Use(message) => {
+                    messages.insert(*oid, message);
+                    break;
+                }
+                RewordAction::Keep => break,
+                RewordAction::Regenerate { draft, .. } if session.is_offline() => {
+                    new = draft;
+                    report::note(
+                        "the offline generator writes the same message every time, \
+                         edit it instead",
+                    );
+                }
+                RewordAction::Regenerate { draft, feedback } => {
+                    history.push(Message::new_ai_message(&draft));
+                    history.push(Message::new_human_message(format!(
+                        "Revise the commit message above. Feedback: {}",
+                        feedback
+                    )));
+                    new = session.generate(&history).await?;
+                }
+                RewordAction::Abort => {
+                    println!("Aborted, no commit was reworded.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+    if dry_run {
+        return print_requests(config, &requests);
+    }
+
+    messages.retain(|oid, message| {
+        targets
+            .iter()
+            .any(|(target, old)| target == oid && old.trim() != message.trim())
+    });
+    if messages.is_empty() {
+        println!("\nNothing to reword, every commit keeps its message.");
+        return Ok(());
+    }
+    if !interactive && !matches.is_present("yes") {
+        println!("\nRun again with --yes to reword these commits.");
+        return Ok(());
+    }
+    let reworded = git::reword_commits(&repo, &messages)?;
+    let head = reworded.head;
+    if reworded.unsigned > 0 {
+        report::warning(format!(
+            "{} of the {} rewritten commits were signed and are not anymore; \
+             sign them again if the branch needs it",
+            reworded.unsigned, reworded.rewritten
+        ));
+    }
+    if !reworded.stale_tags.is_empty() {
+        report::warning(format!(
+            "tags still point at the old commits: {} (move them with `git tag -f`)",
+            reworded.stale_tags.join(", ")
+        ));
+    }
+    println!(
+        "\nReworded {} {}, HEAD is now {}.",
+        messages.len(),
+        if messages.len() == 1 {
+            "commit"
+        } else {
+            "commits"
+        },
+        &head.to_string()[..7]
+    );
+    println!("Undo with: git reset --keep ORIG_HEAD");
+    Ok(())
+}
+
+/// The commits to reword, oldest first: those of `--range`, or the single
+/// revision (`HEAD` by default). Each must be on the current branch.
+fn reword_targets(
+    repo: &Repository,
+    matches: &ArgMatches,
+) -> Result<Vec<(git2::Oid, String)>, AppError> {
+    let mut targets = match matches.value_of("range") {
+        Some(spec) => {
+            let (from, to) = changelog::parse_range(spec);
+            let commits = git::commit_range(repo, from.as_deref(), &to)?;
+            if commits.is_empty() {
+                return Err(AppError::Other(format!(
+                    "found no commits to reword in {}",
+                    spec
+                )));
+            }
+            commits
+        }
+        None => {
+            let rev = matches.value_of("rev").unwrap_or("HEAD");
+            let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+            if commit.parent_count() > 1 {
+                return Err(AppError::Other(format!(
+                    "{} is a merge commit; reword the commits it merges instead",
+                    rev
+                )));
+            }
+            let message = commit.message().unwrap_or_default().trim().to_string();
+            vec![(commit.id(), message)]
+        }
+    };
+    targets.reverse();
+    for (oid, _) in &targets {
+        if !git::reachable_from_head(repo, *oid)? {
+            return Err(AppError::Other(format!(
+                "{} is not on the current branch; check out a branch that contains it",
+                &oid.to_string()[..7]
+            )));
+        }
+    }
+    Ok(targets)
+}
+
+/// Refuses, unless `force`, to reword commits a protected remote branch
+/// already contains, and warns when other remote branches will need a
+/// force-push.
+fn check_pushed(
+    repo: &Repository,
+    config: &Config,
+    targets: &[(git2::Oid, String)],
+    force: bool,
+) -> Result<(), AppError> {
+    let mut pushed: Vec<String> = Vec::new();
+    for (oid, _) in targets {
+        for name in git::remote_branches_containing(repo, *oid)? {
+            let branch = name
+                .split_once('/')
+                .map_or(name.as_str(), |(_, branch)| branch);
+            if config.is_protected_branch(branch) && !force {
+                return Err(AppError::Other(format!(
+                    "{} is already on the protected branch {}, rewording it would rewrite \
+                     shared history (pass --force to do it anyway)",
+                    &oid.to_string()[..7],
+                    name
+                )));
+            }
+            if !pushed.contains(&name) {
+                pushed.push(name);
+            }
+        }
+    }
+    if !pushed.is_empty() {
+        report::warning(format!(
+            "some of the commits are already pushed to {}, which will need a force-push",
+            pushed.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+fn run_template_command(matches: &ArgMatches, repo: Option<&Repository>) -> Result<(), AppError> {
+    match matches.subcommand() {
+        Some(("init", init_matches)) => {
+            let path = if init_matches.is_present("user") {
+                template::user_template_path().ok_or_else(|| {
+                    AppError::Other("Could not find the user config directory".to_string())
+                })?
+            } else {
+                let root = repo.and_then(Repository::workdir).ok_or_else(|| {
+                    AppError::Other(
+                        "Not in a git work tree (use --user for a user template)".to_string(),
+                    )
+                })?;
+                root.join(template::REPO_TEMPLATE_PATH)
+            };
+            template::init(&path, init_matches.is_present("force"))?;
+            println!("Wrote {}", path.display());
+            println!("Available variables:");
+            for (name, description) in template::VARIABLES {
+                println!("  {{{{{}}}}}  {}", name, description);
+            }
+        }
+        _ => {
+            return Err(AppError::Other(
+                "missing template subcommand, see `rcommit template --help`".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Takes the configuration unchecked: a broken `.rcommit.toml` must not make
+/// `hook run` fail, and installing the hook does not read it.
+async fn run_hook_command(
+    matches: &ArgMatches,
+    config: io::Result<Config>,
+) -> Result<(), AppError> {
+    let repo = git::open_repository().map_err(AppError::NotARepository)?;
+    match matches.subcommand() {
+        Some(("install", install_matches)) => {
+            let path = hook::install(&repo, install_matches.is_present("force"))?;
+            println!("Installed {}", path.display());
+        }
+        Some(("uninstall", _)) => match hook::uninstall(&repo)? {
+            Some(path) => println!("Removed {}", path.display()),
+            None => println!("No rcommit hook installed"),
+        },
+        Some(("run", run_matches)) => {
+            if !hook::should_generate(run_matches.value_of("source")) {
+                return Ok(());
+            }
+            let message_file = run_matches.value_of("msg-file").unwrap_or_default();
+            // A failing hook would block the commit; fall back to the empty
+            // editor instead.
+            let config = match config {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("rcommit: could not load the configuration: {}", err);
+                    return Ok(());
+                }
+            };
+            if let Err(err) = prefill_from_hook(&repo, run_matches, &config, message_file).await {
+                eprintln!("rcommit: could not generate a commit message: {}", err);
+            }
+        }
+        _ => {
+            return Err(AppError::Other(
+                "missing hook subcommand, see `rcommit hook --help`".to_string(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+async fn prefill_from_hook(
+    repo: &Repository,
+    matches: &ArgMatches,
+    config: &Config,
+    message_file: &str,
+) -> Result<(), AppError> {
+    let mut file_changes = execute_git_diff_command(repo, &config.exclude_patterns(), false)?;
+    guard_secrets(config, &mut file_changes)?;
+    let scopes = infer_scopes(repo, config, &file_changes)?;
+    if scopes.len() > 1 {
+        eprintln!(
+            "rcommit: the staged changes span several scopes ({}); consider splitting the commit",
+            scopes.join(", ")
+        );
+    }
+    let mut session = MessageSession::start(repo, config, matches, &file_changes, &scopes).await?;
+    let commit_message = session.generate(&[]).await?;
+    Ok(hook::prefill_message_file(
+        Path::new(message_file),
+        &commit_message,
+    )?)
+}
+
+fn build_llm(config: &Config) -> Result<ChatModel, AppError> {
+    provider::build_chat_model(&config.provider.value, config.model.value.as_deref())
+        .map_err(AppError::from_provider)
+}
+
+/// Looks for credentials in the staged diff before any of it is sent, and
+/// blocks, redacts or only reports them as `config.secrets` says.
+fn guard_secrets(config: &Config, file_changes: &mut [FileChange]) -> Result<(), AppError> {
+    // The offline generator sends nothing, so there is nothing to guard.
+    if config.offline.value {
+        return Ok(());
+    }
+    let findings =
+        secrets::scan_changes(file_changes, config.secrets.value == SecretPolicy::Redact);
+    settle_secrets(config, findings.iter().map(ToString::to_string).collect())
+}
+
+/// Like [`guard_secrets`], for the commit messages `rcommit pr` sends along
+/// with the diff.
+fn guard_commit_messages(config: &Config, messages: &mut [String]) -> Result<(), AppError> {
+    let count = messages.len();
+    let mut findings = Vec::new();
+    for (index, message) in messages.iter_mut().enumerate() {
+        let found = secrets::find_secrets(message);
+        for &(kind, start, _) in &found {
+            let line = message[..start].matches('\n').count() + 1;
+            findings.push(format!(
+                "commit {} of {}, message line {}: {}",
+                index + 1,
+                count,
+                line,
+                kind
+            ));
+        }
+        if !found.is_empty() && config.secrets.value == SecretPolicy::Redact {
+            *message = secrets::redact(message);
+        }
+    }
+    settle_secrets(config, findings)
+}
+
+/// Reports every finding and applies the `secrets` policy to them.
+fn settle_secrets(config: &Config, findings: Vec<String>) -> Result<(), AppError> {
+    if findings.is_empty() {
+        return Ok(());
+    }
+    let policy = config.secrets.value;
+    for finding in &findings {
+        let message = format!("possible secret at {}", finding);
+        match policy {
+            SecretPolicy::Block => eprintln!("error: {}", message),
+            SecretPolicy::Redact => report::note(message),
+            SecretPolicy::Warn => report::warning(message),
+        }
+    }
+    match policy {
+        SecretPolicy::Block => Err(AppError::SecretsBlocked(findings.len())),
+        SecretPolicy::Redact => {
+            report::note(format!(
+                "{} possible secrets were replaced with placeholders before sending",
+                findings.len()
+            ));
+            Ok(())
+        }
+        SecretPolicy::Warn => Ok(()),
+    }
+}
+
+/// Fits the staged diff into the `--max-tokens` budget and reports what had
+/// to be trimmed or summarized.
+fn plan_diff_input(config: &Config, file_changes: &[FileChange]) -> DiffPlan {
+    let max_tokens = config.max_tokens.value;
+    let plan = budget::plan_diff(file_changes, max_tokens);
+    let trimmed = match &plan {
+        DiffPlan::Single { trimmed, .. } | DiffPlan::MapReduce { trimmed, .. } => trimmed,
+    };
+    for note in trimmed {
+        report::note(format!("{}", note));
+    }
+    if let DiffPlan::MapReduce { chunks, .. } = &plan {
+        report::note(format!(
+            "diff is over {} tokens, summarizing it in {} parts first",
+            max_tokens,
+            chunks.len()
+        ));
+    }
+    plan
+}
+
+/// The diff for the prompt. When it is too large for one prompt, each chunk
+/// is summarized first and the summaries are used in place of the diff.
+async fn prepare_diff_input(
+    config: &Config,
+    file_changes: &[FileChange],
+    usage: &mut Option<TokenUsage>,
+) -> Result<String, AppError> {
+    match plan_diff_input(config, file_changes) {
+        DiffPlan::Single { diff, .. } => Ok(diff),
+        DiffPlan::MapReduce { chunks, .. } => {
+            let max_tokens = config.max_tokens.value;
+            let chain = build_summary_chain(build_llm(config)?)?;
+            let mut summaries = summarize_chunks(&chain, &chunks, usage).await?;
+            // The summaries of a very large diff can be over the budget too:
+            // summarize them again in fewer parts, and cut what still is.
+            while budget::estimate_tokens(&summaries.join("\n")) > max_tokens {
+                let chunks = budget::pack_chunks(&summaries, "\n", max_tokens);
+                if chunks.len() >= summaries.len() {
+                    report::note(format!(
+                        "the summaries are still over {} tokens, cutting the last ones",
+                        max_tokens
+                    ));
+                    summaries = vec![budget::truncate_to(&summaries.join("\n"), max_tokens)];
+                    break;
+                }
+                report::note(format!(
+                    "the summaries are over {} tokens, summarizing them again in {} parts",
+                    max_tokens,
+                    chunks.len()
+                ));
+                summaries = summarize_chunks(&chain, &chunks, usage).await?;
+            }
+            Ok(summaries_input(&summaries.join("\n")))
+        }
+    }
+}
+
+/// Summarizes every chunk, adding the tokens used to `usage`.
+async fn summarize_chunks(
+    chain: &LLMChain,
+    chunks: &[String],
+    usage: &mut Option<TokenUsage>,
+) -> Result<Vec<String>, AppError> {
+    let mut summaries = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        let summary = summarize_changes(chain, chunk).await?;
+        add_usage(usage, summary.tokens.as_ref());
+        summaries.push(summary.generation);
+    }
+    Ok(summaries)
+}
+
+/// Adds the tokens of one request to `total`, which stays `None` until a
+/// provider reports any.
+fn add_usage(total: &mut Option<TokenUsage>, usage: Option<&TokenUsage>) {
+    if let Some(usage) = usage {
+        let total = total.get_or_insert_with(TokenUsage::default);
+        total.prompt_tokens += usage.prompt_tokens;
+        total.completion_tokens += usage.completion_tokens;
+        total.total_tokens += usage.total_tokens;
+    }
+}
+
+fn summaries_input(summaries: &str) -> String {
+    format!(
+        "Summaries of the staged changes, file by file:\n{}",
+        summaries
+    )
+}
+
+/// Prints every prompt `run_commit_command` would send, without sending
+/// anything. Summaries of a large diff are not known yet, so the final
+/// prompt shows a placeholder where they would go.
+fn print_dry_run(
+    repo: &Repository,
+    config: &Config,
+    matches: &ArgMatches,
+    file_changes: &[FileChange],
+    scopes: &[String],
+) -> Result<(), AppError> {
+    if config.offline.value {
+        println!("Nothing would be sent: --offline writes this message locally.\n");
+        println!(
+            "{}",
+            offline::generate(
+                file_changes,
+                single_scope(scopes),
+                &config.type_names(),
+                &config.scope_names(),
+                ticket(repo, matches).as_deref(),
+            )
+        );
+        return Ok(());
+    }
+    let mut requests = Vec::new();
+    let diff_input = dry_run_diff_input(config, file_changes, &mut requests);
+    let vars = template_vars(repo, config, matches, file_changes, scopes, diff_input);
+    requests.push(("commit message", load_template(config, repo)?.render(&vars)));
+    print_requests(config, &requests)
+}
+
+/// The diff input as [`prepare_diff_input`] would build it, with the summary
+/// requests it would make added to `requests` instead of sent.
+fn dry_run_diff_input(
+    config: &Config,
+    file_changes: &[FileChange],
+    requests: &mut Vec<(&str, String)>,
+) -> String {
+    match plan_diff_input(config, file_changes) {
+        DiffPlan::Single { diff, .. } => diff,
+        DiffPlan::MapReduce { chunks, .. } => {
+            for chunk in &chunks {
+                requests.push(("summary", SUMMARY_PROMPT.replacen("{{input}}", chunk, 1)));
+            }
+            summaries_input(&format!(
+                "[the model's answers to the {} summary prompts above]",
+                chunks.len()
+            ))
+        }
+    }
+}
+
+/// Prints `requests` as `(purpose, prompt)` on stdout and the token and cost
+/// estimate for the configured model on stderr.
+fn print_requests(config: &Config, requests: &[(&str, String)]) -> Result<(), AppError> {
+    for (idx, (purpose, prompt)) in requests.iter().enumerate() {
+        if requests.len() > 1 {
+            println!(
+                "{}",
+                prompt_banner(&format!(
+                    "request {} of {}: {}",
+                    idx + 1,
+                    requests.len(),
+                    purpose
+                ))
+            );
+        }
+        println!("{}", prompt);
     }
 
+    let provider_name = &config.provider.value;
+    let provider = provider::find_provider(provider_name)
+        .ok_or_else(|| AppError::Other(format!("Unknown provider: {}", provider_name)))?;
+    let model = provider::model_name(provider, config.model.value.as_deref());
+    let input_tokens: usize = requests
+        .iter()
+        .map(|(_, prompt)| budget::estimate_tokens(prompt))
+        .sum();
+    let output_tokens = ASSUMED_OUTPUT_TOKENS * requests.len();
+    report::note(format!(
+        "{} {}, about {} input tokens for {} model {}",
+        requests.len(),
+        if requests.len() == 1 {
+            "request"
+        } else {
+            "requests"
+        },
+        input_tokens,
+        provider_name,
+        model
+    ));
+    let cost = match provider_name.as_str() {
+        "ollama" => Some(0.0),
+        _ => budget::estimate_cost(&model, input_tokens, output_tokens),
+    };
+    match cost {
+        Some(cost) => report::note(format!(
+            "estimated cost ${:.4}, assuming {} output tokens per request \
+             (each validation retry costs about as much again)",
+            cost, ASSUMED_OUTPUT_TOKENS
+        )),
+        None => report::note(format!("no price known for {}, cost not estimated", model)),
+    }
+    report::note("nothing was sent (--dry-run)");
     Ok(())
 }
 
-fn initialize_command_line_interface() -> clap::ArgMatches {
+fn prompt_banner(title: &str) -> String {
+    format!("----- {} -----", title)
+}
+
+/// Prints a prompt between banners to stderr, for `--show-prompt`.
+fn print_prompt(prompt: &str) {
+    eprintln!("{}", prompt_banner("prompt"));
+    eprintln!("{}", prompt);
+    eprintln!("{}", prompt_banner("end of prompt"));
+}
+
+fn initialize_command_line_interface() -> ArgMatches {
+    command_line_interface().get_matches()
+}
+
+fn command_line_interface() -> App<'static> {
     App::new("rcommit")
         .version("0.1.0")
         .author("Luis Fernando Miranda")
         .about("Uses AI to write commit messages")
         .arg(
             Arg::new("context")
+                .global(true)
                 .short('c')
                 .long("context")
                 .takes_value(true)
@@ -45,20 +1607,104 @@ fn initialize_command_line_interface() -> clap::ArgMatches {
         )
         .arg(
             Arg::new("exclude")
+                .global(true)
                 .short('e')
                 .long("exclude")
                 .takes_value(true)
                 .multiple_values(true)
-                .help("List of files to exclude from the git diff"),
+                .help("Gitignore-style patterns to exclude from the git diff (prefix with ! to re-include)"),
+        )
+        .arg(
+            Arg::new("provider")
+                .global(true)
+                .short('p')
+                .long("provider")
+                .takes_value(true)
+                .possible_values(provider::provider_names())
+                .help("Specifies the LLM provider to use"),
         )
         .arg(
             Arg::new("model")
+                .global(true)
                 .short('m')
                 .long("model")
                 .takes_value(true)
-                .possible_values(&["gpt3.5", "gpt4", "gpt4-turbo"])
-                .default_value("gpt3.5")
-                .help("Specifies the OpenAI model to use"),
+                .help("Specifies the model to use (defaults to the provider's default model)"),
+        )
+        .arg(
+            Arg::new("max-tokens")
+                .global(true)
+                .long("max-tokens")
+                .takes_value(true)
+                .help("Approximate token budget for the diff; larger diffs are trimmed or summarized"),
+        )
+        .arg(
+            Arg::new("language")
+                .global(true)
+                .short('l')
+                .long("language")
+                .takes_value(true)
+                .help("Language the commit message is written in (default English)"),
+        )
+        .arg(
+            Arg::new("ticket")
+                .global(true)
+                .short('t')
+                .long("ticket")
+                .takes_value(true)
+                .help("Ticket id for the prompt (inferred from the branch name by default)"),
+        )
+        .arg(
+            Arg::new("secrets")
+                .global(true)
+                .long("secrets")
+                .takes_value(true)
+                .possible_values(["block", "redact", "warn"])
+                .help("What to do with likely secrets in the diff before it is sent (default redact)"),
+        )
+        .arg(
+            Arg::new("style-from-history")
+                .global(true)
+                .long("style-from-history")
+                .takes_value(true)
+                .value_name("N")
+                .help("Learns the message style from the last N commits and shows examples to the model"),
+        )
+        .arg(
+            Arg::new("style-author-only")
+                .global(true)
+                .long("style-author-only")
+                .takes_value(false)
+                .help("Only samples commits by the current git user.email (with --style-from-history)"),
+        )
+        .arg(
+            Arg::new("style-main-branch")
+                .global(true)
+                .long("style-main-branch")
+                .takes_value(false)
+                .help("Samples the main branch instead of HEAD (with --style-from-history)"),
+        )
+        .arg(
+            Arg::new("offline")
+                .global(true)
+                .long("offline")
+                .takes_value(false)
+                .help("Writes the message from the shape of the diff without calling a model"),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .global(true)
+                .long("dry-run")
+                .takes_value(false)
+                .help("Prints the prompt that would be sent with a token and cost estimate, without calling the model"),
+        )
+        .arg(
+            Arg::new("show-prompt")
+                .global(true)
+                .long("show-prompt")
+                .takes_value(false)
+                .conflicts_with("dry-run")
+                .help("Prints the prompt to stderr before generating the message"),
         )
         .arg(
             Arg::new("git")
@@ -67,83 +1713,697 @@ fn initialize_command_line_interface() -> clap::ArgMatches {
                 .takes_value(false)
                 .help("Saves the formatted commit message to the clipboard"),
         )
-        .get_matches()
+        .arg(
+            Arg::new("commit")
+                .long("commit")
+                .takes_value(false)
+                .conflicts_with("git")
+                .help("Creates the commit with the generated message instead of copying it"),
+        )
+        .arg(
+            Arg::new("all")
+                .short('a')
+                .long("all")
+                .takes_value(false)
+                .conflicts_with("dry-run")
+                .help("Stages every change to tracked files first, like git commit -a"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .takes_value(true)
+                .value_name("TARGET")
+                .conflicts_with_all(&["git", "commit"])
+                .help("Where the message goes: stdout, clipboard, file:<path> or json (default clipboard)"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["text", "json"])
+                .conflicts_with("dry-run")
+                .help("Prints the result as text or as a JSON object for scripts and editors (implies --yes)"),
+        )
+        .arg(
+            Arg::new("amend")
+                .long("amend")
+                .takes_value(false)
+                .help("Amends the previous commit (with --commit)"),
+        )
+        .arg(
+            Arg::new("signoff")
+                .long("signoff")
+                .takes_value(false)
+                .help("Adds a Signed-off-by trailer (with --commit)"),
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .takes_value(false)
+                .help("Skips the interactive review and uses the first generated message"),
+        )
+        .subcommand(
+            App::new("hook")
+                .about("Manages the prepare-commit-msg git hook")
+                .subcommand_required(true)
+                .subcommand(
+                    App::new("install")
+                        .about("Installs the prepare-commit-msg hook")
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .takes_value(false)
+                                .help("Replaces an existing hook not installed by rcommit"),
+                        ),
+                )
+                .subcommand(
+                    App::new("uninstall").about("Removes the hook installed by rcommit"),
+                )
+                .subcommand(
+                    App::new("run")
+                        .about("Entry point called by the hook; fills in the commit message file")
+                        .arg(Arg::new("msg-file").required(true))
+                        .arg(Arg::new("source"))
+                        .arg(Arg::new("sha")),
+                ),
+        )
+        .subcommand(
+            App::new("split")
+                .about("Splits the staged change into several atomic commits proposed by the model")
+                .arg(
+                    Arg::new("yes")
+                        .short('y')
+                        .long("yes")
+                        .takes_value(false)
+                        .help("Creates the commits without asking for confirmation"),
+                )
+                .arg(
+                    Arg::new("signoff")
+                        .long("signoff")
+                        .takes_value(false)
+                        .help("Adds a Signed-off-by trailer to every commit"),
+                ),
+        )
+        .subcommand(
+            App::new("pr")
+                .about("Writes a pull request title and description for the current branch")
+                .arg(
+                    Arg::new("base")
+                        .short('b')
+                        .long("base")
+                        .takes_value(true)
+                        .help("Branch the pull request targets (detected from the upstream by default)"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(["text", "json"])
+                        .help("Prints the title and description as text or as a JSON object"),
+                ),
+        )
+        .subcommand(
+            App::new("changelog")
+                .about("Writes a changelog from the conventional commits in a range")
+                .arg(
+                    Arg::new("range")
+                        .value_name("FROM..TO")
+                        .help("Commits to list, e.g. v1.2.0..HEAD (default: since the latest tag)"),
+                )
+                .arg(
+                    Arg::new("release")
+                        .long("release")
+                        .takes_value(true)
+                        .value_name("VERSION")
+                        .help("Version in the heading (default: TO when it is a tag, else Unreleased)"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .takes_value(false)
+                        .help("Also lists chores, tests, CI and commits that are not conventional"),
+                )
+                .arg(
+                    Arg::new("rewrite")
+                        .long("rewrite")
+                        .takes_value(false)
+                        .help("Has the model rewrite the commit subjects into user-facing release notes"),
+                )
+                .arg(
+                    Arg::new("prepend")
+                        .long("prepend")
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .default_missing_value("CHANGELOG.md")
+                        .value_name("FILE")
+                        .help("Adds the release to the top of a Keep a Changelog file (default CHANGELOG.md)"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(["markdown", "json"])
+                        .conflicts_with("prepend")
+                        .help("Prints the changelog as Keep a Changelog Markdown or as JSON"),
+                ),
+        )
+        .subcommand(
+            App::new("bump")
+                .about("Prints the next semantic version from the conventional commits since the last tag")
+                .arg(
+                    Arg::new("pre")
+                        .long("pre")
+                        .takes_value(true)
+                        .validator(bump::validate_channel)
+                        .value_name("CHANNEL")
+                        .help("Makes a pre-release such as 1.3.0-rc.1 on the given channel (rc, beta, ...)"),
+                )
+                .arg(
+                    Arg::new("release-as")
+                        .long("release-as")
+                        .takes_value(true)
+                        .possible_values(["major", "minor", "patch"])
+                        .help("Overrides the bump computed from the commits"),
+                )
+                .arg(
+                    Arg::new("write")
+                        .long("write")
+                        .takes_value(false)
+                        .help("Writes the version to Cargo.toml and package.json at the repository root"),
+                )
+                .arg(
+                    Arg::new("tag")
+                        .long("tag")
+                        .takes_value(false)
+                        .help("Creates the tag, after committing the updated manifests with --write"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(["text", "json"])
+                        .help("Prints the version alone or a JSON object with the details"),
+                ),
+        )
+        .subcommand(
+            App::new("reword")
+                .about("Rewrites the messages of existing commits from their diffs")
+                .arg(
+                    Arg::new("rev")
+                        .value_name("REV")
+                        .conflicts_with("range")
+                        .help("Commit to reword (default HEAD)"),
+                )
+                .arg(
+                    Arg::new("range")
+                        .long("range")
+                        .takes_value(true)
+                        .value_name("FROM..TO")
+                        .help("Rewords every commit in a range, e.g. main..HEAD"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .short('y')
+                        .long("yes")
+                        .takes_value(false)
+                        .help("Uses every new message without asking"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .takes_value(false)
+                        .help("Rewords commits even when a protected remote branch already has them"),
+                ),
+        )
+        .subcommand(
+            App::new("template")
+                .about("Manages prompt templates")
+                .subcommand_required(true)
+                .subcommand(
+                    App::new("init")
+                        .about("Writes the built-in template to .rcommit/template.txt")
+                        .arg(
+                            Arg::new("user")
+                                .long("user")
+                                .takes_value(false)
+                                .help("Writes it next to the user config instead"),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .long("force")
+                                .takes_value(false)
+                                .help("Overwrites an existing template"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("config")
+                .about("Inspects the merged configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    App::new("show")
+                        .about("Prints the effective configuration and where each value came from"),
+                ),
+        )
+}
+
+fn execute_git_diff_command(
+    repo: &Repository,
+    excludes: &[&str],
+    amend: bool,
+) -> Result<Vec<FileChange>, AppError> {
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    let matcher = ExcludeMatcher::new(root, excludes)?;
+    Ok(git::staged_changes(repo, &matcher, amend)?)
+}
+
+fn build_commit_chain(llm: ChatModel) -> Result<LLMChain, AppError> {
+    // The prompt is rendered by `Template` beforehand; the chain only adds
+    // earlier drafts and the user's feedback on them, so a regeneration
+    // refines the last draft instead of starting over.
+    let prompt = HumanMessagePromptTemplate::new(template_jinja2!("{{prompt}}", "prompt"));
+    LLMChainBuilder::new()
+        .prompt(message_formatter![
+            fmt_template!(prompt),
+            fmt_placeholder!("history")
+        ])
+        .llm(llm)
+        .build()
+        .map_err(|err| AppError::Other(format!("could not build the LLM chain: {}", err)))
+}
+
+/// Collects everything the prompt template can reference.
+fn template_vars(
+    repo: &Repository,
+    config: &Config,
+    matches: &ArgMatches,
+    file_changes: &[FileChange],
+    scopes: &[String],
+    diff_input: String,
+) -> TemplateVars {
+    let branch = git::current_branch(repo);
+    let ticket = ticket(repo, matches);
+    let files: Vec<String> = file_changes
+        .iter()
+        .map(|change| format!("- {} ({})", change.path.display(), change.status.label()))
+        .collect();
+    let recent_commits: Vec<String> = git::recent_subjects(repo, RECENT_COMMIT_COUNT)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|subject| format!("- {}", subject))
+        .collect();
+    let style = learn_style(repo, config);
+
+    TemplateVars {
+        input: diff_input,
+        context: matches
+            .value_of("context")
+            .unwrap_or("no context")
+            .to_string(),
+        branch: branch.unwrap_or_else(|| "(detached HEAD)".to_string()),
+        files: files.join("\n"),
+        recent_commits: recent_commits.join("\n"),
+        style,
+        types: config.types.value.join(", "),
+        scopes: config.scopes.value.join(", "),
+        inferred_scopes: scopes.join(", "),
+        ticket: ticket.unwrap_or_default(),
+        language: config.language.value.clone(),
+    }
 }
 
-fn parse_model_argument(model_arg: &str) -> OpenAIModel {
-    match model_arg {
-        "gpt3.5" => OpenAIModel::Gpt35,
-        "gpt4" => OpenAIModel::Gpt4,
-        "gpt4-turbo" => OpenAIModel::Gpt4Turbo,
-        _ => unreachable!("Invalid model specified"), // clap's possible_values constraint prevents reaching here
+/// The `--ticket` id, or the one found in the branch name.
+fn ticket(repo: &Repository, matches: &ArgMatches) -> Option<String> {
+    matches.value_of("ticket").map(str::to_string).or_else(|| {
+        git::current_branch(repo)
+            .as_deref()
+            .and_then(template::ticket_from_branch)
+    })
+}
+
+/// Scopes of the staged paths, from `scope-rules` and workspace members.
+fn infer_scopes(
+    repo: &Repository,
+    config: &Config,
+    file_changes: &[FileChange],
+) -> Result<Vec<String>, AppError> {
+    let root = repo.workdir().unwrap_or_else(|| repo.path());
+    let resolver = ScopeResolver::new(
+        root,
+        &config.scope_rules.value,
+        config.workspace_scopes.value,
+        &config.scopes.value,
+    )?;
+    Ok(resolver.scopes_for(file_changes))
+}
+
+/// The scope a message must use: only set when every scoped path agrees.
+fn single_scope(scopes: &[String]) -> Option<&str> {
+    match scopes {
+        [scope] => Some(scope),
+        _ => None,
+    }
+}
+
+/// Describes the style of the last `--style-from-history` commits, or
+/// returns an empty string when style learning is off.
+fn learn_style(repo: &Repository, config: &Config) -> String {
+    let count = config.style_from_history.value;
+    if count == 0 {
+        return String::new();
+    }
+    let filter = SampleFilter {
+        author_only: config.style_author_only.value,
+        main_branch: config.style_main_branch.value,
+    };
+    match style::sample(repo, count, filter) {
+        Ok(messages) => style::analyze(&messages).to_string(),
+        Err(err) => {
+            report::warning(format!("could not sample the commit history: {}", err));
+            String::new()
+        }
     }
 }
 
-fn execute_git_diff_command(excludes: &[&str]) -> io::Result<String> {
-    let exclude_pattern = excludes.iter().fold(String::new(), |acc, &file| {
-        if acc.is_empty() {
-            format!("grep -vE '^{}$'", file)
+fn load_template(config: &Config, repo: &Repository) -> Result<Template, AppError> {
+    let path = template::find_template(config.template.value.as_deref(), repo.workdir());
+    Ok(template::load(path.as_deref())?)
+}
+
+/// Where commit messages come from.
+enum Generator {
+    Model(LLMChain),
+    /// The heuristics in [`offline`], chosen with `--offline` or when no
+    /// model can be reached.
+    Offline,
+}
+
+/// What it takes to generate, and regenerate, the message for one set of
+/// staged changes.
+struct MessageSession<'a> {
+    generator: Generator,
+    config: &'a Config,
+    file_changes: &'a [FileChange],
+    prompt: String,
+    required_scope: Option<&'a str>,
+    ticket: Option<String>,
+    /// The model's reply behind the last message, before it was cleaned up.
+    raw: Option<String>,
+    /// Tokens used by every request so far, as reported by the provider.
+    usage: Option<TokenUsage>,
+}
+
+impl<'a> MessageSession<'a> {
+    /// Picks the generator and, for a model, renders the prompt. A provider
+    /// that cannot be reached falls back to the offline generator.
+    async fn start(
+        repo: &Repository,
+        config: &'a Config,
+        matches: &ArgMatches,
+        file_changes: &'a [FileChange],
+        scopes: &'a [String],
+    ) -> Result<MessageSession<'a>, AppError> {
+        let generator = if config.offline.value {
+            Generator::Offline
         } else {
-            format!("{} | grep -vE '^{}$'", acc, file)
+            match build_llm(config) {
+                Ok(llm) => Generator::Model(build_commit_chain(llm)?),
+                Err(err) if err.allows_offline_fallback() => {
+                    report::warning(format!("{}; using the offline generator", err));
+                    Generator::Offline
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        let mut usage = None;
+        let prompt = match generator {
+            Generator::Model(_) => {
+                let diff_input = prepare_diff_input(config, file_changes, &mut usage).await?;
+                let vars = template_vars(repo, config, matches, file_changes, scopes, diff_input);
+                load_template(config, repo)?.render(&vars)
+            }
+            Generator::Offline => String::new(),
+        };
+        Ok(MessageSession {
+            generator,
+            config,
+            file_changes,
+            prompt,
+            required_scope: single_scope(scopes),
+            ticket: ticket(repo, matches),
+            raw: None,
+            usage,
+        })
+    }
+
+    /// Prints the rendered prompt to stderr, for `--show-prompt`.
+    fn show_prompt(&self) {
+        match self.generator {
+            Generator::Model(_) => print_prompt(&self.prompt),
+            Generator::Offline => report::note("no prompt, the offline generator is used"),
         }
-    });
+    }
 
-    let shell_command = if exclude_pattern.is_empty() {
-        "git diff --cached --name-only --diff-filter=ACM | while read -r file; do echo \"\\n---------------------------\\n name:$file\"; git diff --cached \"$file\" | sed 's/^/+/'; done".to_string()
-    } else {
-        format!(
-            "git diff --cached --name-only --diff-filter=ACM | {} | while read -r file; do echo \"\\n---------------------------\\n name:$file\"; git diff --cached \"$file\" | sed 's/^/+/'; done",
-            exclude_pattern
-        )
-    };
+    fn is_offline(&self) -> bool {
+        matches!(self.generator, Generator::Offline)
+    }
+
+    /// Describes `message` and how it was made, for `--format json`. The
+    /// fields taken from the message are null when it does not parse as a
+    /// Conventional Commit.
+    fn to_json(&self, message: &str, commit: Option<git2::Oid>) -> serde_json::Value {
+        let parsed = conventional::parse(message).ok();
+        let footers: Vec<_> = parsed
+            .iter()
+            .flat_map(|commit| &commit.footers)
+            .map(|footer| json!({ "token": footer.token, "value": footer.value }))
+            .collect();
+        let (generator, provider, model) = match self.generator {
+            Generator::Model(_) => {
+                let name = &self.config.provider.value;
+                let model = provider::find_provider(name).map(|provider| {
+                    provider::model_name(provider, self.config.model.value.as_deref())
+                });
+                ("model", Some(name), model)
+            }
+            Generator::Offline => ("offline", None, None),
+        };
+        json!({
+            "message": message,
+            "type": parsed.as_ref().map(|commit| &commit.commit_type),
+            "scope": parsed.as_ref().and_then(|commit| commit.scope.as_ref()),
+            "subject": parsed.as_ref().map(|commit| &commit.subject),
+            "body": parsed.as_ref().and_then(|commit| commit.body.as_ref()),
+            "footers": footers,
+            "breaking": parsed.as_ref().is_some_and(|commit| commit.is_breaking()),
+            "raw": self.raw,
+            "generator": generator,
+            "provider": provider,
+            "model": model,
+            "usage": self.usage,
+            "warnings": report::diagnostics(),
+            "commit": commit.map(|oid| oid.to_string()),
+        })
+    }
 
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(shell_command)
-        .stdout(Stdio::piped())
-        .spawn()?
-        .stdout
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Could not capture stdout."))?;
+    /// Generates a message, switching to the offline generator for good if
+    /// the provider turns out to be unreachable.
+    async fn generate(&mut self, history: &[Message]) -> Result<String, AppError> {
+        if let Generator::Model(chain) = &self.generator {
+            let result = generate_commit_message(
+                chain,
+                self.config,
+                &self.prompt,
+                history,
+                self.required_scope,
+            )
+            .await;
+            match result {
+                Ok(generation) => {
+                    add_usage(&mut self.usage, generation.usage.as_ref());
+                    self.raw = Some(generation.raw);
+                    return Ok(generation.message);
+                }
+                Err(err) if err.allows_offline_fallback() => {
+                    report::warning(format!("{}; using the offline generator", err));
+                    self.generator = Generator::Offline;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        self.raw = None;
+        Ok(offline::generate(
+            self.file_changes,
+            self.required_scope,
+            &self.config.type_names(),
+            &self.config.scope_names(),
+            self.ticket.as_deref(),
+        ))
+    }
+}
 
-    let reader = io::BufReader::new(output);
-    reader
-        .lines()
-        .collect::<Result<Vec<String>, _>>()
-        .map(|lines| lines.join("\n"))
+/// A message from the model, with the reply it was cleaned up from.
+struct Generation {
+    message: String,
+    raw: String,
+    /// Tokens used by every attempt.
+    usage: Option<TokenUsage>,
 }
 
+/// Generates a message and checks it against the Conventional Commits
+/// grammar and, when given, the scope inferred from the staged paths. A
+/// non-compliant draft is sent back with the validation error, up to
+/// `MAX_VALIDATION_ATTEMPTS` times, before the last draft is used anyway.
 async fn generate_commit_message(
-    git_diff_output: &str,
-    context: &str,
-    model: OpenAIModel,
-) -> String {
-    let llm = OpenAI::default().with_model(model);
-    let chain = LLMChainBuilder::new()
+    chain: &LLMChain,
+    config: &Config,
+    prompt: &str,
+    history: &[Message],
+    required_scope: Option<&str>,
+) -> Result<Generation, AppError> {
+    let types = config.type_names();
+    let mut usage = None;
+    let (message, raw) = generate_validated(
+        chain,
+        prompt,
+        history,
+        "Reply with only the corrected commit message, no quotes or code fences.",
+        &mut usage,
+        |raw| {
+            let message = conventional::clean_message(raw);
+            let checked =
+                conventional::validate(&message, &types).and_then(|commit| match required_scope {
+                    Some(scope) => conventional::check_scope(&commit, scope).map(|_| commit),
+                    None => Ok(commit),
+                });
+            match checked {
+                Ok(commit) => Verdict::Valid(commit.to_string()),
+                Err(err) => Verdict::Invalid {
+                    problem: format!("That is not a valid Conventional Commits message: {}", err),
+                    last_resort: Ok((
+                        message,
+                        vec![format!(
+                            "the generated message still fails validation: {}",
+                            err
+                        )],
+                    )),
+                },
+            }
+        },
+    )
+    .await?;
+    Ok(Generation {
+        message,
+        raw,
+        usage,
+    })
+}
+
+/// What a validation closure makes of one reply from the model.
+enum Verdict<T> {
+    Valid(T),
+    Invalid {
+        /// Sent back to the model along with its reply.
+        problem: String,
+        /// What to settle for once no attempts are left: a value, after the
+        /// warnings are printed, or an error with this message.
+        last_resort: Result<(T, Vec<String>), String>,
+    },
+}
+
+/// Calls `chain` until `check` accepts the reply, sending each rejected
+/// reply back with its problem and `instruction`, up to
+/// `MAX_VALIDATION_ATTEMPTS` times. Returns the value with the reply it
+/// came from.
+async fn generate_validated<T>(
+    chain: &LLMChain,
+    prompt: &str,
+    history: &[Message],
+    instruction: &str,
+    usage: &mut Option<TokenUsage>,
+    mut check: impl FnMut(&str) -> Verdict<T>,
+) -> Result<(T, String), AppError> {
+    let mut history = history.to_vec();
+    let mut attempt = 1;
+    loop {
+        let result = chain
+            .call(prompt_args! {
+                "prompt" => prompt,
+                "history" => history
+            })
+            .await
+            .map_err(AppError::from_provider)?;
+        add_usage(usage, result.tokens.as_ref());
+        let raw = result.generation;
+        match check(&raw) {
+            Verdict::Valid(value) => return Ok((value, raw)),
+            Verdict::Invalid { last_resort, .. } if attempt >= MAX_VALIDATION_ATTEMPTS => {
+                let (value, warnings) = last_resort.map_err(AppError::Other)?;
+                for warning in warnings {
+                    report::warning(warning);
+                }
+                return Ok((value, raw));
+            }
+            Verdict::Invalid { problem, .. } => {
+                history.push(Message::new_ai_message(&raw));
+                history.push(Message::new_human_message(format!(
+                    "{}. {}",
+                    problem, instruction
+                )));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn build_summary_chain(llm: ChatModel) -> Result<LLMChain, AppError> {
+    LLMChainBuilder::new()
         .prompt(HumanMessagePromptTemplate::new(template_jinja2!(
-            r#"
-    Create a conventional commit message for the following changes.
-    Some context about the changes: {{context}}
-    File changes: 
-        {{input}}
-    "#,
-            "input",
-            "context"
+            SUMMARY_PROMPT,
+            "input"
         )))
         .llm(llm)
         .build()
-        .expect("Could not build LLM chain");
+        .map_err(|err| AppError::Other(format!("could not build the LLM chain: {}", err)))
+}
 
+async fn summarize_changes(chain: &LLMChain, chunk: &str) -> Result<GenerateResult, AppError> {
     chain
-        .invoke(prompt_args! {
-            "input" => git_diff_output,
-            "context" => context
+        .call(prompt_args! {
+            "input" => chunk
         })
         .await
-        .expect("Error invoking LLMChain")
+        .map_err(AppError::from_provider)
 }
 
-fn copy_to_clipboard(text: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut ctx: ClipboardContext = ClipboardProvider::new()?;
-    ctx.set_contents(text.to_owned())?;
-    Ok(())
+/// Copies `text` to the clipboard, or prints it to stdout when there is no
+/// clipboard to copy to, so the message is never lost.
+fn copy_or_print(text: &str) {
+    match output::copy_to_clipboard(text) {
+        Ok(Copied::Native) => {}
+        Ok(Copied::Osc52) => report::note(
+            "copied through the terminal (OSC 52); if nothing arrived, allow clipboard \
+             access in your terminal or use --output stdout",
+        ),
+        Err(err) => {
+            report::warning(format!(
+                "no clipboard available ({}), printing instead",
+                err
+            ));
+            println!("{}", text);
+        }
+    }
+}
+
+/// Wraps `text` in single quotes so `$`, backticks and double quotes reach
+/// git untouched when the command is pasted into a POSIX shell.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
 }
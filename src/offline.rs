@@ -0,0 +1,647 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::git::{ChangeStatus, FileChange};
+
+/// Subjects are kept under the usual 72-column limit.
+const MAX_SUBJECT_LEN: usize = 72;
+/// How many symbols or files the subject names before summarizing.
+const MAX_NAMED: usize = 2;
+
+/// Path segments that say nothing about what changed, skipped when a scope
+/// is derived from directories.
+const GENERIC_DIRS: &[&str] = &["src", "lib", "app", "pkg", "internal", "source", "main"];
+
+/// What a file is, judging by its path alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Docs,
+    Test,
+    Ci,
+    Build,
+    Chore,
+    Code,
+}
+
+impl FileKind {
+    fn commit_type(self) -> &'static str {
+        match self {
+            FileKind::Docs => "docs",
+            FileKind::Test => "test",
+            FileKind::Ci => "ci",
+            FileKind::Build => "build",
+            FileKind::Chore | FileKind::Code => "chore",
+        }
+    }
+}
+
+/// Writes a Conventional Commits message from the shape of the diff, without
+/// a model. The type comes from the kinds of files touched and whether code
+/// was added, changed or only removed; the scope is `scope` when given, else
+/// the deepest directory all changed files share, if `allowed_scopes` is
+/// empty or lists it; the subject names new symbols, the functions touched or
+/// the files.
+pub fn generate(
+    changes: &[FileChange],
+    scope: Option<&str>,
+    allowed_types: &[&str],
+    allowed_scopes: &[&str],
+    ticket: Option<&str>,
+) -> String {
+    if changes.is_empty() {
+        return "chore: update files".to_string();
+    }
+    let kinds: Vec<FileKind> = changes
+        .iter()
+        .map(|change| classify(&change.path))
+        .collect();
+    let code: Vec<&FileChange> = changes
+        .iter()
+        .zip(&kinds)
+        .filter(|(_, kind)| **kind == FileKind::Code)
+        .map(|(change, _)| change)
+        .collect();
+
+    let (commit_type, subject) = if code.is_empty() {
+        let first = kinds[0];
+        let kind = if kinds.iter().all(|kind| *kind == first) {
+            first
+        } else {
+            FileKind::Chore
+        };
+        let verb = if changes.iter().all(|c| c.status == ChangeStatus::Added) {
+            "add"
+        } else {
+            "update"
+        };
+        let all: Vec<&FileChange> = changes.iter().collect();
+        (kind.commit_type(), format!("{} {}", verb, name_files(&all)))
+    } else {
+        describe_code(&code)
+    };
+    let commit_type = pick_type(commit_type, allowed_types);
+
+    let scope = scope.map(str::to_string).or_else(|| {
+        common_scope(changes.iter().map(|change| change.path.as_path()))
+            // `docs(docs)` says nothing a bare `docs` doesn't.
+            .filter(|scope| scope != commit_type)
+            .filter(|scope| allowed_scopes.is_empty() || allowed_scopes.contains(&scope.as_str()))
+    });
+    let mut header = match &scope {
+        Some(scope) => format!("{}({}): ", commit_type, scope),
+        None => format!("{}: ", commit_type),
+    };
+    header.push_str(&truncate(
+        &subject,
+        MAX_SUBJECT_LEN.saturating_sub(header.len()),
+    ));
+
+    let mut message = header;
+    if changes.len() > 1 {
+        message.push_str("\n\n");
+        let lines: Vec<String> = changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "- {} ({}, +{} -{})",
+                    change.path.display(),
+                    change.status.label(),
+                    change.additions,
+                    change.deletions
+                )
+            })
+            .collect();
+        message.push_str(&lines.join("\n"));
+    }
+    if let Some(ticket) = ticket.filter(|ticket| !ticket.is_empty()) {
+        message.push_str(&format!("\n\nRefs: {}", ticket));
+    }
+    message
+}
+
+/// Type and subject for a change that touches code: `feat` when files or
+/// symbols are added, `refactor` when code is only removed or moved. Other
+/// edits are `fix` only when the changed lines talk about a bug, and
+/// otherwise `refactor` when they replace code or `chore` when they only add
+/// some.
+fn describe_code(code: &[&FileChange]) -> (&'static str, String) {
+    let mut added_symbols = BTreeSet::new();
+    let mut removed_symbols = BTreeSet::new();
+    let mut touched = BTreeSet::new();
+    let mut mentions_bug = false;
+    for change in code {
+        for hunk in &change.hunks {
+            for line in &hunk.lines {
+                if let Some(rest) = line.strip_prefix('+') {
+                    added_symbols.extend(symbol(rest));
+                    mentions_bug |= mentions_bug_fix(rest);
+                } else if let Some(rest) = line.strip_prefix('-') {
+                    removed_symbols.extend(symbol(rest));
+                    mentions_bug |= mentions_bug_fix(rest);
+                }
+            }
+            // Hunk headers end with the enclosing function, when git finds one.
+            if let Some(context) = hunk.header.rsplit("@@").next() {
+                touched.extend(symbol(context.trim()));
+            }
+        }
+    }
+    let new_symbols: Vec<String> = added_symbols
+        .difference(&removed_symbols)
+        .cloned()
+        .collect();
+    let gone_symbols: Vec<String> = removed_symbols
+        .difference(&added_symbols)
+        .cloned()
+        .collect();
+
+    let added_files: Vec<&FileChange> = code
+        .iter()
+        .copied()
+        .filter(|change| change.status == ChangeStatus::Added)
+        .collect();
+    if !new_symbols.is_empty() {
+        return ("feat", format!("add {}", name_symbols(&new_symbols)));
+    }
+    if !added_files.is_empty() {
+        return ("feat", format!("add {}", name_files(&added_files)));
+    }
+
+    let only_removed = code.iter().all(|change| {
+        change.status == ChangeStatus::Deleted || (change.additions == 0 && change.deletions > 0)
+    });
+    if only_removed {
+        return if gone_symbols.is_empty() {
+            ("refactor", format!("remove {}", name_files(code)))
+        } else {
+            (
+                "refactor",
+                format!("remove {}", name_symbols(&gone_symbols)),
+            )
+        };
+    }
+    if code.iter().all(|change| {
+        matches!(change.status, ChangeStatus::Renamed) && change.additions + change.deletions == 0
+    }) {
+        let first = code[0];
+        let from = first.old_path.as_deref().map(file_name).unwrap_or_default();
+        return (
+            "refactor",
+            if code.len() == 1 {
+                format!("rename {} to {}", from, file_name(&first.path))
+            } else {
+                format!("rename {} files", code.len())
+            },
+        );
+    }
+
+    let commit_type = if mentions_bug {
+        "fix"
+    } else if code.iter().any(|change| change.deletions > 0) {
+        "refactor"
+    } else {
+        "chore"
+    };
+    let touched: Vec<String> = touched.into_iter().collect();
+    if touched.is_empty() {
+        (commit_type, format!("update {}", name_files(code)))
+    } else {
+        (commit_type, format!("update {}", name_symbols(&touched)))
+    }
+}
+
+/// Whether a changed line says it fixes something, usually in a comment
+/// such as `// Work around the crash on empty input`.
+fn mentions_bug_fix(line: &str) -> bool {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN
+        .get_or_init(|| {
+            Regex::new(
+                r"(?i)\b(fix(es|ed)?|bugs?|crash(es|ed)?|panics?|regression|workaround|work around|typo|off-by-one|race condition|deadlock|leak)\b",
+            )
+            .expect("bug pattern is valid")
+        })
+        .is_match(line)
+}
+
+fn classify(path: &Path) -> FileKind {
+    let text = path.to_string_lossy().replace('\\', "/");
+    let name = file_name(path);
+    let lower = name.to_ascii_lowercase();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let dirs: Vec<String> = path
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_ascii_lowercase())
+                .collect()
+        })
+        .unwrap_or_default();
+    let in_dir = |names: &[&str]| dirs.iter().any(|dir| names.contains(&dir.as_str()));
+
+    if text.starts_with(".github/workflows/")
+        || text.starts_with(".circleci/")
+        || text.starts_with(".buildkite/")
+        || [
+            ".gitlab-ci.yml",
+            ".travis.yml",
+            "azure-pipelines.yml",
+            "jenkinsfile",
+        ]
+        .contains(&lower.as_str())
+    {
+        return FileKind::Ci;
+    }
+    if [
+        "cargo.toml",
+        "cargo.lock",
+        "build.rs",
+        "package.json",
+        "package-lock.json",
+        "yarn.lock",
+        "pnpm-lock.yaml",
+        "go.mod",
+        "go.sum",
+        "makefile",
+        "cmakelists.txt",
+        "dockerfile",
+        "pom.xml",
+        "build.gradle",
+        "build.gradle.kts",
+        "pyproject.toml",
+        "setup.py",
+        "setup.cfg",
+        "gemfile",
+        "gemfile.lock",
+    ]
+    .contains(&lower.as_str())
+        || lower.starts_with("requirements") && extension == "txt"
+    {
+        return FileKind::Build;
+    }
+    if in_dir(&["tests", "test", "__tests__", "spec", "testdata"])
+        || lower.contains("_test.")
+        || lower.contains(".test.")
+        || lower.contains(".spec.")
+        || lower.contains("_spec.")
+        || lower.starts_with("test_")
+    {
+        return FileKind::Test;
+    }
+    if in_dir(&["docs", "doc", "documentation"])
+        || ["md", "rst", "adoc", "txt"].contains(&extension.as_str())
+        || ["readme", "changelog", "license", "contributing"]
+            .iter()
+            .any(|prefix| lower.starts_with(prefix))
+    {
+        return FileKind::Docs;
+    }
+    if lower.starts_with('.')
+        || ["toml", "yaml", "yml", "ini", "cfg", "conf", "lock"].contains(&extension.as_str())
+    {
+        return FileKind::Chore;
+    }
+    FileKind::Code
+}
+
+/// The name defined on `line`, if it starts a function, type or class in
+/// one of the common languages.
+fn symbol(line: &str) -> Option<String> {
+    static PATTERNS: OnceLock<Vec<Regex>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        [
+            // Rust
+            r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:async\s+)?(?:unsafe\s+)?(?:fn|struct|enum|trait|mod|type)\s+([A-Za-z_][A-Za-z0-9_]*)",
+            // JavaScript and TypeScript
+            r"^\s*(?:export\s+)?(?:default\s+)?(?:async\s+)?(?:function\*?|class|interface)\s+([A-Za-z_$][A-Za-z0-9_$]*)",
+            // Python and Ruby
+            r"^\s*(?:async\s+)?(?:def|class)\s+([A-Za-z_][A-Za-z0-9_]*[?!]?)",
+            // Go
+            r"^\s*func\s+(?:\([^)]*\)\s*)?([A-Za-z_][A-Za-z0-9_]*)",
+            // Java, C# and friends
+            r"^\s*(?:public|private|protected)\s+(?:static\s+)?(?:final\s+)?(?:class|interface|enum|record)\s+([A-Za-z_][A-Za-z0-9_]*)",
+        ]
+        .iter()
+        .map(|pattern| Regex::new(pattern).expect("symbol patterns are valid"))
+        .collect()
+    });
+    patterns
+        .iter()
+        .find_map(|pattern| pattern.captures(line))
+        .and_then(|captures| captures.get(1))
+        .map(|name| name.as_str().to_string())
+}
+
+/// Scope from the deepest directory every path shares, skipping generic
+/// names such as `src`. `None` when the paths share no directory.
+fn common_scope<'a>(paths: impl Iterator<Item = &'a Path>) -> Option<String> {
+    let mut common: Option<Vec<String>> = None;
+    for path in paths {
+        let dirs: Vec<String> = path
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .filter_map(|c| match c {
+                        Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        common = Some(match common {
+            None => dirs,
+            Some(prefix) => prefix
+                .into_iter()
+                .zip(dirs)
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    common?
+        .into_iter()
+        .rev()
+        .find(|dir| {
+            !GENERIC_DIRS.contains(&dir.to_ascii_lowercase().as_str()) && !dir.starts_with('.')
+        })
+        .map(|dir| dir.to_ascii_lowercase())
+}
+
+/// Falls back to `chore`, then to the first allowed type, when the
+/// inferred one is not allowed.
+fn pick_type<'a>(inferred: &'a str, allowed: &[&'a str]) -> &'a str {
+    if allowed.is_empty() || allowed.contains(&inferred) {
+        inferred
+    } else if allowed.contains(&"chore") {
+        "chore"
+    } else {
+        allowed[0]
+    }
+}
+
+fn name_symbols(symbols: &[String]) -> String {
+    let quoted: Vec<String> = symbols.iter().map(|s| format!("`{}`", s)).collect();
+    name_list(&quoted, "symbols")
+}
+
+fn name_files(changes: &[&FileChange]) -> String {
+    let names: Vec<String> = changes
+        .iter()
+        .map(|change| file_name(&change.path))
+        .collect();
+    name_list(&names, "files")
+}
+
+/// `a`, `a and b`, or `a, b and 3 more files`.
+fn name_list(names: &[String], noun: &str) -> String {
+    match names {
+        [] => noun.to_string(),
+        [one] => one.clone(),
+        [first, second] => format!("{} and {}", first, second),
+        _ => format!(
+            "{} and {} more {}",
+            names[..MAX_NAMED].join(", "),
+            names.len() - MAX_NAMED,
+            noun
+        ),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max.saturating_sub(3)).collect();
+    format!("{}...", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::Hunk;
+    use std::path::PathBuf;
+
+    fn change(path: &str, status: ChangeStatus, header: &str, lines: &[&str]) -> FileChange {
+        let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        FileChange {
+            path: PathBuf::from(path),
+            old_path: None,
+            status,
+            additions: lines.iter().filter(|line| line.starts_with('+')).count(),
+            deletions: lines.iter().filter(|line| line.starts_with('-')).count(),
+            hunks: vec![Hunk {
+                header: header.to_string(),
+                lines,
+            }],
+            binary: false,
+            mode_change: None,
+        }
+    }
+
+    #[test]
+    fn classifies_paths() {
+        assert_eq!(classify(Path::new("README.md")), FileKind::Docs);
+        assert_eq!(classify(Path::new("docs/guide/setup.html")), FileKind::Docs);
+        assert_eq!(classify(Path::new("tests/cli.rs")), FileKind::Test);
+        assert_eq!(classify(Path::new("web/app.test.ts")), FileKind::Test);
+        assert_eq!(classify(Path::new("pkg/server_test.go")), FileKind::Test);
+        assert_eq!(
+            classify(Path::new(".github/workflows/ci.yml")),
+            FileKind::Ci
+        );
+        assert_eq!(classify(Path::new(".gitlab-ci.yml")), FileKind::Ci);
+        assert_eq!(classify(Path::new("Cargo.toml")), FileKind::Build);
+        assert_eq!(classify(Path::new("web/package.json")), FileKind::Build);
+        assert_eq!(classify(Path::new("requirements-dev.txt")), FileKind::Build);
+        assert_eq!(classify(Path::new(".editorconfig")), FileKind::Chore);
+        assert_eq!(classify(Path::new("src/main.rs")), FileKind::Code);
+    }
+
+    #[test]
+    fn docs_only_changes_are_docs() {
+        let changes = [change(
+            "docs/usage.md",
+            ChangeStatus::Modified,
+            "@@ -1,2 +1,2 @@",
+            &["-old text", "+new text"],
+        )];
+        assert_eq!(
+            generate(&changes, None, &[], &[], None),
+            "docs: update usage.md"
+        );
+    }
+
+    #[test]
+    fn added_symbol_is_a_feature() {
+        let changes = [change(
+            "src/auth/token.rs",
+            ChangeStatus::Modified,
+            "@@ -10,3 +10,8 @@ impl Token {",
+            &[
+                "+pub fn refresh(&mut self) {",
+                "+    self.expires = 0;",
+                "+}",
+            ],
+        )];
+        assert_eq!(
+            generate(&changes, None, &[], &[], None),
+            "feat(auth): add `refresh`"
+        );
+    }
+
+    #[test]
+    fn deletion_only_is_a_refactor() {
+        let changes = [change(
+            "src/auth/token.rs",
+            ChangeStatus::Modified,
+            "@@ -10,3 +10,0 @@",
+            &["-fn legacy_refresh() {", "-    todo!()", "-}"],
+        )];
+        assert_eq!(
+            generate(&changes, None, &[], &[], None),
+            "refactor(auth): remove `legacy_refresh`"
+        );
+    }
+
+    #[test]
+    fn pure_rename_is_a_refactor() {
+        let mut renamed = change("src/net/client.rs", ChangeStatus::Renamed, "", &[]);
+        renamed.hunks.clear();
+        renamed.old_path = Some(PathBuf::from("src/net/http.rs"));
+        assert_eq!(
+            generate(&[renamed], None, &[], &[], None),
+            "refactor(net): rename http.rs to client.rs"
+        );
+    }
+
+    #[test]
+    fn edits_are_fix_only_when_the_diff_mentions_a_bug() {
+        let header = "@@ -3,4 +3,4 @@ fn parse(input: &str) -> Option<u32> {";
+        let replaced = change(
+            "src/parse.rs",
+            ChangeStatus::Modified,
+            header,
+            &["-    input.parse().ok()", "+    input.trim().parse().ok()"],
+        );
+        assert_eq!(
+            generate(&[replaced], None, &[], &[], None),
+            "refactor: update `parse`"
+        );
+
+        let fixed = change(
+            "src/parse.rs",
+            ChangeStatus::Modified,
+            header,
+            &[
+                "-    input.parse().ok()",
+                "+    // Trailing newlines used to crash the caller.",
+                "+    input.trim().parse().ok()",
+            ],
+        );
+        assert_eq!(
+            generate(&[fixed], None, &[], &[], None),
+            "fix: update `parse`"
+        );
+
+        let added = change(
+            "src/parse.rs",
+            ChangeStatus::Modified,
+            header,
+            &["+    log::debug!(\"parsing {}\", input);"],
+        );
+        assert_eq!(
+            generate(&[added], None, &[], &[], None),
+            "chore: update `parse`"
+        );
+    }
+
+    #[test]
+    fn bug_words_need_word_boundaries() {
+        assert!(mentions_bug_fix("// fixes #12"));
+        assert!(mentions_bug_fix("// Work around the panic"));
+        assert!(!mentions_bug_fix("let prefix = fixture();"));
+        assert!(!mentions_bug_fix("let debugger = attach();"));
+    }
+
+    #[test]
+    fn body_lists_files_and_ticket_is_referenced() {
+        let changes = [
+            change("docs/a.md", ChangeStatus::Added, "", &["+a"]),
+            change("docs/b.md", ChangeStatus::Added, "", &["+b"]),
+        ];
+        assert_eq!(
+            generate(&changes, Some("guide"), &[], &[], Some("ABC-1")),
+            "docs(guide): add a.md and b.md\n\n\
+             - docs/a.md (added, +1 -0)\n\
+             - docs/b.md (added, +1 -0)\n\n\
+             Refs: ABC-1"
+        );
+    }
+
+    #[test]
+    fn inferred_scope_must_be_allowed() {
+        let changes = [
+            change(
+                "src/auth/token.rs",
+                ChangeStatus::Modified,
+                "",
+                &["-a", "+b"],
+            ),
+            change(
+                "src/auth/session.rs",
+                ChangeStatus::Modified,
+                "",
+                &["-a", "+b"],
+            ),
+        ];
+        let subject = |allowed: &[&str]| {
+            let message = generate(&changes, None, &[], allowed, None);
+            message.lines().next().unwrap().to_string()
+        };
+        assert!(subject(&[]).starts_with("refactor(auth): "));
+        assert!(subject(&["auth", "db"]).starts_with("refactor(auth): "));
+        assert!(subject(&["api", "db"]).starts_with("refactor: "));
+    }
+
+    #[test]
+    fn scope_is_the_deepest_shared_specific_directory() {
+        let scope = |paths: &[&str]| common_scope(paths.iter().map(Path::new));
+        assert_eq!(
+            scope(&["src/auth/token.rs", "src/auth/session.rs"]),
+            Some("auth".to_string())
+        );
+        assert_eq!(
+            scope(&["crates/Parser/src/lib.rs"]),
+            Some("parser".to_string())
+        );
+        assert_eq!(scope(&["src/auth/token.rs", "src/db/pool.rs"]), None);
+        assert_eq!(scope(&["src/main.rs"]), None);
+        assert_eq!(
+            scope(&[".github/workflows/ci.yml"]),
+            Some("workflows".to_string())
+        );
+        assert_eq!(scope(&["README.md"]), None);
+    }
+
+    #[test]
+    fn picks_an_allowed_type() {
+        assert_eq!(pick_type("feat", &[]), "feat");
+        assert_eq!(pick_type("feat", &["feat", "fix"]), "feat");
+        assert_eq!(pick_type("refactor", &["feat", "chore"]), "chore");
+        assert_eq!(pick_type("refactor", &["feat", "fix"]), "feat");
+    }
+}
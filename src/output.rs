@@ -0,0 +1,127 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use clipboard::{ClipboardContext, ClipboardProvider};
+
+/// How text reached the clipboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Copied {
+    /// Through the system clipboard of this machine.
+    Native,
+    /// Through an OSC 52 escape sequence, which the terminal may ignore.
+    Osc52,
+}
+
+/// Copies `text` to the clipboard. In an SSH session the local clipboard is
+/// on the other end, so the terminal is asked to copy it with OSC 52;
+/// otherwise the system clipboard is used. The error explains why neither
+/// worked.
+pub fn copy_to_clipboard(text: &str) -> Result<Copied, String> {
+    if in_ssh_session() {
+        return osc52_copy(text).map(|_| Copied::Osc52);
+    }
+    native_copy(text).map(|_| Copied::Native)
+}
+
+fn native_copy(text: &str) -> Result<(), String> {
+    let mut ctx: ClipboardContext = ClipboardProvider::new().map_err(|err| err.to_string())?;
+    ctx.set_contents(text.to_owned())
+        .map_err(|err| err.to_string())
+}
+
+fn in_ssh_session() -> bool {
+    ["SSH_TTY", "SSH_CONNECTION"]
+        .iter()
+        .any(|var| env::var_os(var).is_some())
+}
+
+/// Writes the OSC 52 "set clipboard" sequence to the controlling terminal.
+fn osc52_copy(text: &str) -> Result<(), String> {
+    let term = env::var("TERM").ok();
+    let sequence = osc52_sequence(text, env::var_os("TMUX").is_some(), term.as_deref());
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|err| format!("no terminal to send OSC 52 to ({})", err))?;
+    tty.write_all(sequence.as_bytes())
+        .and_then(|_| tty.flush())
+        .map_err(|err| err.to_string())
+}
+
+/// The OSC 52 "set clipboard" sequence for `text`, wrapped for tmux and
+/// screen so they pass it on to the outer terminal.
+fn osc52_sequence(text: &str, in_tmux: bool, term: Option<&str>) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    if in_tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else if term.is_some_and(|term| term.starts_with("screen")) {
+        format!("\x1bP{}\x1b\\", sequence)
+    } else {
+        sequence
+    }
+}
+
+/// Standard base64 with padding, all OSC 52 needs.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (idx, &byte)| n | (byte as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * idx)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(base64(input.as_bytes()), expected, "input {:?}", input);
+        }
+        assert_eq!(base64(&[0xfb, 0xff, 0xfe]), "+//+");
+    }
+
+    #[test]
+    fn osc52_is_plain_outside_multiplexers() {
+        assert_eq!(
+            osc52_sequence("foo", false, Some("xterm-256color")),
+            "\x1b]52;c;Zm9v\x07"
+        );
+        assert_eq!(osc52_sequence("foo", false, None), "\x1b]52;c;Zm9v\x07");
+    }
+
+    #[test]
+    fn osc52_is_wrapped_for_tmux_and_screen() {
+        // tmux wants every escape inside the passthrough doubled.
+        assert_eq!(
+            osc52_sequence("foo", true, Some("screen-256color")),
+            "\x1bPtmux;\x1b\x1b]52;c;Zm9v\x07\x1b\\"
+        );
+        assert_eq!(
+            osc52_sequence("foo", false, Some("screen")),
+            "\x1bP\x1b]52;c;Zm9v\x07\x1b\\"
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where GitHub looks for a pull request template, in the order checked.
+const TEMPLATE_PATHS: &[&str] = &[
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "pull_request_template.md",
+    "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+    "docs/PULL_REQUEST_TEMPLATE.md",
+];
+
+/// The sections used when the repository has no template of its own.
+const DEFAULT_TEMPLATE: &str = "## Summary
+<!-- What the pull request does and why, in two or three sentences. -->
+
+## Changes
+<!-- A bullet list of the notable changes. -->
+
+## Testing
+<!-- How the changes were tested, or how a reviewer can check them. -->
+
+## Breaking changes
+<!-- What users of the project have to change, or \"None\". -->
+";
+
+/// The instructions sent with the commits and the combined diff.
+const PR_PROMPT: &str = r#"Write a pull request for the branch `{branch}`, which is to be merged into `{base}`. Write in {language}.
+The first line of your reply is the pull request title: one short line, without a trailing period.
+After a blank line, fill in the description template below section by section. Keep every heading, unchanged and in the same order, and replace the guidance under it (HTML comments and placeholders) with the content for that section. Keep checklists, ticking only the items the changes clearly satisfy. Use Markdown and do not wrap the reply in a code block.
+{extra}
+Template:
+{template}
+
+Commits on the branch, oldest first:
+{commits}
+
+Combined changes:
+{input}"#;
+
+/// A pull request description template, cut at its Markdown headings.
+#[derive(Debug, Clone)]
+pub struct PrTemplate {
+    /// `None` for the built-in template.
+    pub path: Option<PathBuf>,
+    text: String,
+    sections: Vec<Section>,
+}
+
+/// A heading and everything up to the next one. The text before the first
+/// heading is a section with an empty heading.
+#[derive(Debug, Clone)]
+struct Section {
+    heading: String,
+    content: String,
+}
+
+/// What the model wrote, with the description put in template order.
+#[derive(Debug, Clone)]
+pub struct PullRequest {
+    pub title: String,
+    pub body: String,
+    /// Template headings the reply left out; their guidance is kept.
+    pub missing: Vec<String>,
+}
+
+/// What goes into the prompt besides the diff.
+pub struct PromptVars<'a> {
+    pub branch: &'a str,
+    pub base: &'a str,
+    pub language: &'a str,
+    pub commits: &'a [String],
+    pub ticket: Option<&'a str>,
+    pub context: Option<&'a str>,
+    pub input: &'a str,
+}
+
+/// The repository's pull request template, found where GitHub looks for
+/// it under `repo_root`, or the built-in one.
+pub fn load_template(repo_root: Option<&Path>) -> io::Result<PrTemplate> {
+    let path = repo_root.and_then(|root| {
+        TEMPLATE_PATHS
+            .iter()
+            .map(|candidate| root.join(candidate))
+            .find(|path| path.is_file())
+    });
+    let text = match &path {
+        Some(path) => fs::read_to_string(path)?,
+        None => DEFAULT_TEMPLATE.to_string(),
+    };
+    Ok(PrTemplate {
+        path,
+        sections: split_sections(&text),
+        text,
+    })
+}
+
+impl PrTemplate {
+    /// The prompt asking for a title and the filled-in template.
+    pub fn prompt(&self, vars: &PromptVars) -> String {
+        let mut extra = String::new();
+        if let Some(ticket) = vars.ticket {
+            extra.push_str(&format!(
+                "Mention the ticket {} in the description.\n",
+                ticket
+            ));
+        }
+        if let Some(context) = vars.context {
+            extra.push_str(&format!(
+                "Additional context from the author: {}\n",
+                context
+            ));
+        }
+        let commits: Vec<String> = vars
+            .commits
+            .iter()
+            .map(|message| {
+                let item = format!("- {}", message.replace('\n', "\n  "));
+                item.lines()
+                    .map(str::trim_end)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect();
+        let commits = commits.join("\n");
+        let value = |name: &str| match name {
+            "branch" => Some(vars.branch),
+            "base" => Some(vars.base),
+            "language" => Some(vars.language),
+            "extra" => Some(extra.as_str()),
+            "template" => Some(self.text.trim()),
+            "commits" => Some(commits.as_str()),
+            "input" => Some(vars.input),
+            _ => None,
+        };
+
+        // One pass, so a `{input}` inside a commit message or the template
+        // is left as it is.
+        let mut output = String::with_capacity(PR_PROMPT.len() + vars.input.len());
+        let mut rest = PR_PROMPT;
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after
+                .find('}')
+                .and_then(|end| Some((end, value(&after[..end])?)))
+            {
+                Some((end, text)) => {
+                    output.push_str(text);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    output.push('{');
+                    rest = after;
+                }
+            }
+        }
+        output.push_str(rest);
+        output
+    }
+
+    /// Reads the title and description out of `reply` and lays the
+    /// description out like the template: its headings in order, each with
+    /// the reply's content, or the template's own text when the reply has no
+    /// such section. Sections the template does not have are kept at the end.
+    pub fn parse_reply(&self, reply: &str) -> Result<PullRequest, String> {
+        let reply = strip_fence(reply.trim());
+        let (title, body) = reply.split_once('\n').unwrap_or((reply, ""));
+        let title = clean_title(title);
+        if title.is_empty() {
+            return Err("the first line, the title, is empty".to_string());
+        }
+        let body = body.trim();
+        if body.is_empty() {
+            return Err("the description after the title is missing".to_string());
+        }
+
+        let headed: Vec<&Section> = self
+            .sections
+            .iter()
+            .filter(|section| !section.heading.is_empty())
+            .collect();
+        if headed.is_empty() {
+            return Ok(PullRequest {
+                title,
+                body: body.to_string(),
+                missing: Vec::new(),
+            });
+        }
+
+        let mut written = split_sections(body);
+        let mut missing = Vec::new();
+        let mut filled = Vec::new();
+        if let Some(preamble) = written
+            .iter()
+            .position(|section| section.heading.is_empty())
+        {
+            filled.push(written.remove(preamble).content);
+        }
+        for section in headed {
+            let found = written
+                .iter()
+                .position(|candidate| same_heading(&candidate.heading, &section.heading));
+            let content = match found {
+                Some(idx) => written.remove(idx).content,
+                None => {
+                    missing.push(heading_text(&section.heading).to_string());
+                    section.content.clone()
+                }
+            };
+            filled.push(join_section(&section.heading, &content));
+        }
+        for section in written {
+            filled.push(join_section(&section.heading, &section.content));
+        }
+        Ok(PullRequest {
+            title,
+            body: filled
+                .iter()
+                .map(|part| part.trim())
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            missing,
+        })
+    }
+}
+
+/// Cuts Markdown at its ATX headings (`#` to `######`), ignoring lines in
+/// fenced code blocks.
+fn split_sections(text: &str) -> Vec<Section> {
+    let mut sections = vec![Section {
+        heading: String::new(),
+        content: String::new(),
+    }];
+    let mut in_fence = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        if !in_fence && is_heading(trimmed) {
+            sections.push(Section {
+                heading: trimmed.trim_end().to_string(),
+                content: String::new(),
+            });
+            continue;
+        }
+        let content = &mut sections.last_mut().expect("never empty").content;
+        content.push_str(line);
+        content.push('\n');
+    }
+    if sections[0].content.trim().is_empty() {
+        sections.remove(0);
+    }
+    sections
+}
+
+fn is_heading(line: &str) -> bool {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with(' ')
+}
+
+/// The words of a heading, without its `#`s.
+fn heading_text(heading: &str) -> &str {
+    heading.trim_start_matches('#').trim()
+}
+
+/// Headings match whatever their level, case or surrounding emphasis.
+fn same_heading(a: &str, b: &str) -> bool {
+    let normalize = |heading: &str| {
+        heading_text(heading)
+            .trim_matches(|c: char| c == '*' || c == '_' || c == ':')
+            .to_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+fn join_section(heading: &str, content: &str) -> String {
+    format!("{}\n{}", heading, content.trim())
+}
+
+/// Unwraps a reply the model put in a code block after all.
+fn strip_fence(reply: &str) -> &str {
+    let Some(rest) = reply.strip_prefix("```") else {
+        return reply;
+    };
+    let rest = rest.split_once('\n').map_or("", |(_, rest)| rest);
+    rest.trim_end().strip_suffix("```").unwrap_or(rest).trim()
+}
+
+/// Drops the decoration models like to put around a title.
+fn clean_title(line: &str) -> String {
+    let line = line.trim().trim_start_matches('#').trim();
+    let line = ["Title:", "title:", "**Title:**"]
+        .iter()
+        .find_map(|label| line.strip_prefix(label))
+        .unwrap_or(line)
+        .trim();
+    line.trim_matches(|c: char| c == '"' || c == '`' || c == '*')
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(text: &str) -> PrTemplate {
+        PrTemplate {
+            path: None,
+            text: text.to_string(),
+            sections: split_sections(text),
+        }
+    }
+
+    const REPLY: &str = "Add login with passkeys
+
+## Summary
+Adds passkey login.
+
+## Changes
+- new `/login/passkey` route
+
+## Testing
+Ran the browser tests.
+
+## Breaking changes
+None.";
+
+    #[test]
+    fn prompt_leaves_placeholders_inside_values_alone() {
+        let commits = ["fix: escape {input} in templates".to_string()];
+        let prompt = template("## Summary\n<!-- {commits} -->\n").prompt(&PromptVars {
+            branch: "fix/{base}",
+            base: "main",
+            language: "English",
+            commits: &commits,
+            ticket: None,
+            context: None,
+            input: "diff --git a/x b/x",
+        });
+        assert!(prompt.starts_with(
+            "Write a pull request for the branch `fix/{base}`, which is to be merged into `main`."
+        ));
+        assert!(prompt.contains("Template:\n## Summary\n<!-- {commits} -->\n\nCommits"));
+        assert!(prompt.contains("- fix: escape {input} in templates\n"));
+        assert!(prompt.ends_with("Combined changes:\ndiff --git a/x b/x"));
+    }
+
+    #[test]
+    fn parses_a_complete_reply() {
+        let pull_request = template(DEFAULT_TEMPLATE).parse_reply(REPLY).unwrap();
+        assert_eq!(pull_request.title, "Add login with passkeys");
+        assert!(pull_request.missing.is_empty());
+        assert_eq!(pull_request.body, REPLY.split_once("\n\n").unwrap().1);
+    }
+
+    #[test]
+    fn unwraps_fenced_replies_and_decorated_titles() {
+        let reply = "```markdown\nTitle: \"Add login\"\n\n## Summary\nAdds login.\n```";
+        let pull_request = template(DEFAULT_TEMPLATE).parse_reply(reply).unwrap();
+        assert_eq!(pull_request.title, "Add login");
+        assert!(pull_request
+            .body
+            .starts_with("## Summary\nAdds login.\n\n## Changes"));
+        assert!(!pull_request.body.contains("```"));
+    }
+
+    #[test]
+    fn keeps_the_template_text_for_missing_sections() {
+        let reply = "Add login\n\n## Summary\nAdds login.\n\n## Changes\n- a route";
+        let pull_request = template(DEFAULT_TEMPLATE).parse_reply(reply).unwrap();
+        assert_eq!(pull_request.missing, ["Testing", "Breaking changes"]);
+        assert!(pull_request.body.contains(
+            "## Testing\n<!-- How the changes were tested, or how a reviewer can check them. -->"
+        ));
+    }
+
+    #[test]
+    fn puts_sections_in_template_order_and_extra_ones_last() {
+        let template = template("## Summary\n<!-- what -->\n\n## Testing\n<!-- how -->\n");
+        let reply = "Title\n\n## Notes\nSee the issue.\n\n## Testing\nUnit tests.\n\n\
+                     ## Summary\nDoes it.";
+        let pull_request = template.parse_reply(reply).unwrap();
+        assert_eq!(
+            pull_request.body,
+            "## Summary\nDoes it.\n\n## Testing\nUnit tests.\n\n## Notes\nSee the issue."
+        );
+        assert!(pull_request.missing.is_empty());
+    }
+
+    #[test]
+    fn matches_headings_at_other_levels_and_keeps_the_template_heading() {
+        let template = template("## Summary\n<!-- what -->\n");
+        let pull_request = template
+            .parse_reply("Title\n\n### **summary:**\nDoes it.")
+            .unwrap();
+        assert_eq!(pull_request.body, "## Summary\nDoes it.");
+        assert!(pull_request.missing.is_empty());
+    }
+
+    #[test]
+    fn keeps_text_before_the_first_heading() {
+        let template = template("## Summary\n<!-- what -->\n");
+        let pull_request = template
+            .parse_reply("Title\n\nCloses #4.\n\n## Summary\nDoes it.")
+            .unwrap();
+        assert_eq!(pull_request.body, "Closes #4.\n\n## Summary\nDoes it.");
+    }
+
+    #[test]
+    fn takes_the_body_as_is_when_the_template_has_no_headings() {
+        let template = template("Describe the change.\n");
+        let pull_request = template.parse_reply("Title\n\nJust a paragraph.").unwrap();
+        assert_eq!(pull_request.body, "Just a paragraph.");
+        assert!(pull_request.missing.is_empty());
+    }
+
+    #[test]
+    fn rejects_replies_without_a_title_or_description() {
+        let template = template(DEFAULT_TEMPLATE);
+        assert!(template.parse_reply("").is_err());
+        assert!(template.parse_reply("**Title:**\n\n## Summary\nx").is_err());
+        assert!(template.parse_reply("Only a title").is_err());
+    }
+
+    #[test]
+    fn splits_at_headings_outside_code_blocks() {
+        let sections = split_sections(
+            "Intro\n# One\ntext\n```sh\n# a comment\n```\n#hashtag\n####### seven\n## Two\n",
+        );
+        let headings: Vec<&str> = sections.iter().map(|s| s.heading.as_str()).collect();
+        assert_eq!(headings, ["", "# One", "## Two"]);
+        assert_eq!(sections[0].content, "Intro\n");
+        assert_eq!(
+            sections[1].content,
+            "text\n```sh\n# a comment\n```\n#hashtag\n####### seven\n"
+        );
+        assert!(split_sections("\n## Only\n")[0].heading == "## Only");
+    }
+
+    #[test]
+    fn compares_headings_loosely() {
+        assert!(same_heading("## Summary", "### summary"));
+        assert!(same_heading("## Testing", "## **Testing:**"));
+        assert!(same_heading(
+            "# Breaking changes",
+            "#### _breaking changes_"
+        ));
+        assert!(!same_heading("## Summary", "## Changes"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use langchain_rust::language_models::llm::LLM;
+use langchain_rust::language_models::options::CallOptions;
+use langchain_rust::language_models::{GenerateResult, TokenUsage};
+use langchain_rust::schemas::messages::{Message, MessageType};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{ChatModel, Provider, ProviderError, ProviderSettings};
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+const API_VERSION: &str = "2023-06-01";
+/// The messages API requires an explicit output limit.
+const DEFAULT_MAX_TOKENS: u16 = 1024;
+
+pub struct Anthropic;
+
+impl Provider for Anthropic {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn default_model(&self) -> &'static str {
+        "claude-3-haiku-20240307"
+    }
+
+    fn api_key_env(&self) -> Option<&'static str> {
+        Some("ANTHROPIC_API_KEY")
+    }
+
+    fn base_url_env(&self) -> &'static str {
+        "ANTHROPIC_BASE_URL"
+    }
+
+    fn build(&self, settings: ProviderSettings) -> Result<ChatModel, Box<dyn Error>> {
+        let api_key = settings.api_key.ok_or(ProviderError::MissingSetting {
+            provider: self.name(),
+            variable: "ANTHROPIC_API_KEY",
+        })?;
+        let base_url = settings
+            .base_url
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Ok(ChatModel::new(AnthropicChat {
+            endpoint: format!("{}/messages", base_url.trim_end_matches('/')),
+            api_key,
+            model: settings.model,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            temperature: 0.0,
+        }))
+    }
+}
+
+struct AnthropicChat {
+    endpoint: String,
+    api_key: String,
+    model: String,
+    max_tokens: u16,
+    temperature: f32,
+}
+
+impl AnthropicChat {
+    fn request_body(&self, messages: &[Message]) -> serde_json::Value {
+        // System prompts are a top-level field rather than a message role.
+        let system = messages
+            .iter()
+            .filter(|m| matches!(m.message_type, MessageType::SystemMessage))
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let turns: Vec<_> = messages
+            .iter()
+            .filter_map(|m| match m.message_type {
+                MessageType::HumanMessage => Some(json!({ "role": "user", "content": m.content })),
+                MessageType::AIMessage => {
+                    Some(json!({ "role": "assistant", "content": m.content }))
+                }
+                MessageType::SystemMessage => None,
+            })
+            .collect();
+
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": self.max_tokens,
+            "temperature": self.temperature,
+            "messages": turns,
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system);
+        }
+        body
+    }
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    usage: Option<Usage>,
+}
+
+impl MessagesResponse {
+    /// The text of every content block, joined.
+    fn into_result(self) -> GenerateResult {
+        GenerateResult {
+            generation: self
+                .content
+                .into_iter()
+                .map(|block| block.text)
+                .collect::<Vec<_>>()
+                .join(""),
+            tokens: self
+                .usage
+                .map(|usage| TokenUsage::new(usage.input_tokens, usage.output_tokens)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+#[async_trait]
+impl LLM for AnthropicChat {
+    async fn generate(&self, messages: &[Message]) -> Result<GenerateResult, Box<dyn Error>> {
+        let response = reqwest::Client::new()
+            .post(&self.endpoint)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&self.request_body(messages))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ProviderError::from_response("anthropic", response)
+                .await
+                .into());
+        }
+        let reply: MessagesResponse = response.json().await?;
+        Ok(reply.into_result())
+    }
+
+    async fn invoke(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        self.generate(&[Message::new_human_message(prompt)])
+            .await
+            .map(|result| result.generation)
+    }
+
+    fn with_options(&mut self, options: CallOptions) {
+        if let Some(max_tokens) = options.max_tokens {
+            self.max_tokens = max_tokens;
+        }
+        if let Some(temperature) = options.temperature {
+            self.temperature = temperature;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderMap;
+
+    use super::*;
+    use crate::error::AppError;
+
+    fn chat() -> AnthropicChat {
+        AnthropicChat {
+            endpoint: format!("{}/messages", DEFAULT_BASE_URL),
+            api_key: "sk-ant-test".to_string(),
+            model: "claude-3-haiku-20240307".to_string(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            temperature: 0.0,
+        }
+    }
+
+    #[test]
+    fn moves_system_prompts_to_the_top_level() {
+        let messages = [
+            Message::new_system_message("Write commit messages."),
+            Message::new_system_message("Use English."),
+            Message::new_human_message("diff"),
+            Message::new_ai_message("fix: typo"),
+        ];
+        assert_eq!(
+            chat().request_body(&messages),
+            json!({
+                "model": "claude-3-haiku-20240307",
+                "max_tokens": 1024,
+                "temperature": 0.0,
+                "system": "Write commit messages.\n\nUse English.",
+                "messages": [
+                    { "role": "user", "content": "diff" },
+                    { "role": "assistant", "content": "fix: typo" },
+                ],
+            })
+        );
+
+        let body = chat().request_body(&[Message::new_human_message("diff")]);
+        assert!(body.get("system").is_none());
+    }
+
+    #[test]
+    fn joins_text_blocks_and_reads_usage() {
+        let reply = r#"{
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                { "type": "text", "text": "feat: add login\n\n" },
+                { "type": "text", "text": "Adds a login form." }
+            ],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 95, "output_tokens": 12 }
+        }"#;
+        let result = serde_json::from_str::<MessagesResponse>(reply)
+            .unwrap()
+            .into_result();
+        assert_eq!(result.generation, "feat: add login\n\nAdds a login form.");
+        let usage = result.tokens.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (95, 12));
+    }
+
+    #[test]
+    fn classifies_error_payloads() {
+        let overflow = r#"{"type": "error", "error": {"type": "invalid_request_error", "message": "prompt is too long: 210000 tokens > 200000 maximum"}}"#;
+        let err =
+            ProviderError::from_parts("anthropic", 400, &HeaderMap::new(), overflow.to_string());
+        assert!(matches!(
+            AppError::from_provider(err.into()),
+            AppError::ContextOverflow {
+                provider: "anthropic",
+                ..
+            }
+        ));
+
+        let overloaded =
+            r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#;
+        let err =
+            ProviderError::from_parts("anthropic", 529, &HeaderMap::new(), overloaded.to_string());
+        assert!(matches!(
+            AppError::from_provider(err.into()),
+            AppError::Provider {
+                status: Some(529),
+                ..
+            }
+        ));
+    }
+}
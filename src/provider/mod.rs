@@ -0,0 +1,190 @@
+mod anthropic;
+mod openai;
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+
+use async_trait::async_trait;
+use langchain_rust::language_models::llm::LLM;
+use langchain_rust::language_models::options::CallOptions;
+use langchain_rust::language_models::GenerateResult;
+use langchain_rust::schemas::messages::Message;
+
+pub use anthropic::Anthropic;
+pub use openai::{OpenAICompatible, OpenAIFlavor};
+
+/// Provider used when `--provider` is not given.
+pub const DEFAULT_PROVIDER: &str = "openai";
+
+/// A named LLM backend that `--provider` can select.
+///
+/// Each provider reads its own base URL and API key from environment
+/// variables and turns a model string into a chat model the chain can drive.
+pub trait Provider: Sync {
+    fn name(&self) -> &'static str;
+    fn default_model(&self) -> &'static str;
+    /// Environment variable holding the API key, if the backend needs one.
+    fn api_key_env(&self) -> Option<&'static str>;
+    /// Environment variable overriding the backend's base URL.
+    fn base_url_env(&self) -> &'static str;
+    fn build(&self, settings: ProviderSettings) -> Result<ChatModel, Box<dyn Error>>;
+}
+
+/// Why a provider could not be set up or did not answer.
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The provider needs `variable` (an API key or base URL) and it is unset.
+    MissingSetting {
+        provider: &'static str,
+        variable: &'static str,
+    },
+    /// The provider answered with a non-success HTTP status.
+    Http {
+        provider: &'static str,
+        status: u16,
+        body: String,
+        /// Seconds to wait, from a `Retry-After` header.
+        retry_after: Option<u64>,
+    },
+}
+
+impl ProviderError {
+    /// Reads the error out of a failed response.
+    pub async fn from_response(provider: &'static str, response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        Self::from_parts(provider, status, &headers, body)
+    }
+
+    fn from_parts(
+        provider: &'static str,
+        status: u16,
+        headers: &reqwest::header::HeaderMap,
+        body: String,
+    ) -> Self {
+        let retry_after = headers
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+        ProviderError::Http {
+            provider,
+            status,
+            body,
+            retry_after,
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::MissingSetting { provider, variable } => {
+                write!(f, "{} must be set for {}", variable, provider)
+            }
+            ProviderError::Http {
+                provider,
+                status,
+                body,
+                ..
+            } => write!(f, "{} returned HTTP {}: {}", provider, status, body.trim()),
+        }
+    }
+}
+
+impl Error for ProviderError {}
+
+/// Resolved connection settings handed to [`Provider::build`].
+#[derive(Debug, Clone)]
+pub struct ProviderSettings {
+    pub model: String,
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+}
+
+static PROVIDERS: &[&dyn Provider] = &[
+    &OpenAICompatible {
+        flavor: OpenAIFlavor::OpenAI,
+    },
+    &OpenAICompatible {
+        flavor: OpenAIFlavor::Azure,
+    },
+    &OpenAICompatible {
+        flavor: OpenAIFlavor::Ollama,
+    },
+    &OpenAICompatible {
+        flavor: OpenAIFlavor::Compatible,
+    },
+    &Anthropic,
+];
+
+/// Names accepted by `--provider`.
+pub fn provider_names() -> Vec<&'static str> {
+    PROVIDERS.iter().map(|provider| provider.name()).collect()
+}
+
+pub fn find_provider(name: &str) -> Option<&'static dyn Provider> {
+    PROVIDERS
+        .iter()
+        .copied()
+        .find(|provider| provider.name() == name)
+}
+
+/// Looks up `name` and builds its chat model, falling back to the provider's
+/// default model and reading credentials from the environment.
+pub fn build_chat_model(name: &str, model: Option<&str>) -> Result<ChatModel, Box<dyn Error>> {
+    let provider = find_provider(name).ok_or_else(|| format!("Unknown provider: {}", name))?;
+    let model = model_name(provider, model);
+    let api_key = provider.api_key_env().and_then(|var| env::var(var).ok());
+    let base_url = env::var(provider.base_url_env()).ok();
+    provider.build(ProviderSettings {
+        model,
+        base_url,
+        api_key,
+    })
+}
+
+/// The model `provider` is asked for: `model` with its alias resolved, or
+/// the provider's default.
+pub fn model_name(provider: &dyn Provider, model: Option<&str>) -> String {
+    model
+        .map(resolve_model_alias)
+        .unwrap_or(provider.default_model())
+        .to_string()
+}
+
+/// Keeps the short model names rcommit has always accepted working.
+fn resolve_model_alias(model: &str) -> &str {
+    match model {
+        "gpt3.5" => "gpt-3.5-turbo",
+        "gpt4" => "gpt-4",
+        "gpt4-turbo" => "gpt-4-turbo-preview",
+        other => other,
+    }
+}
+
+/// A provider's chat model behind a single concrete type, so it can be passed
+/// to `LLMChainBuilder::llm`.
+pub struct ChatModel(Box<dyn LLM>);
+
+impl ChatModel {
+    pub fn new<L: LLM + 'static>(llm: L) -> Self {
+        Self(Box::new(llm))
+    }
+}
+
+#[async_trait]
+impl LLM for ChatModel {
+    async fn generate(&self, messages: &[Message]) -> Result<GenerateResult, Box<dyn Error>> {
+        self.0.generate(messages).await
+    }
+
+    async fn invoke(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        self.0.invoke(prompt).await
+    }
+
+    fn with_options(&mut self, options: CallOptions) {
+        self.0.with_options(options)
+    }
+}
@@ -0,0 +1,368 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use langchain_rust::language_models::llm::LLM;
+use langchain_rust::language_models::options::CallOptions;
+use langchain_rust::language_models::{GenerateResult, TokenUsage};
+use langchain_rust::schemas::messages::{Message, MessageType};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{ChatModel, Provider, ProviderError, ProviderSettings};
+
+const AZURE_API_VERSION_ENV: &str = "AZURE_OPENAI_API_VERSION";
+const AZURE_DEFAULT_API_VERSION: &str = "2024-02-01";
+
+/// The backends that speak the OpenAI chat completions API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAIFlavor {
+    OpenAI,
+    Azure,
+    Ollama,
+    Compatible,
+}
+
+pub struct OpenAICompatible {
+    pub flavor: OpenAIFlavor,
+}
+
+impl OpenAICompatible {
+    fn default_base_url(&self) -> Option<&'static str> {
+        match self.flavor {
+            OpenAIFlavor::OpenAI => Some("https://api.openai.com/v1"),
+            OpenAIFlavor::Ollama => Some("http://localhost:11434"),
+            OpenAIFlavor::Azure | OpenAIFlavor::Compatible => None,
+        }
+    }
+
+    fn requires_api_key(&self) -> bool {
+        matches!(self.flavor, OpenAIFlavor::OpenAI | OpenAIFlavor::Azure)
+    }
+
+    /// The chat completions URL under `base_url`.
+    fn endpoint(&self, base_url: &str, model: &str) -> String {
+        let base_url = base_url.trim_end_matches('/');
+        match self.flavor {
+            OpenAIFlavor::Azure => {
+                let api_version = std::env::var(AZURE_API_VERSION_ENV)
+                    .unwrap_or_else(|_| AZURE_DEFAULT_API_VERSION.to_string());
+                format!(
+                    "{}/openai/deployments/{}/chat/completions?api-version={}",
+                    base_url, model, api_version
+                )
+            }
+            // Ollama's own tools accept `OLLAMA_HOST=127.0.0.1:11434`.
+            OpenAIFlavor::Ollama if !base_url.contains("://") => {
+                format!("http://{}/v1/chat/completions", base_url)
+            }
+            OpenAIFlavor::Ollama => format!("{}/v1/chat/completions", base_url),
+            OpenAIFlavor::OpenAI | OpenAIFlavor::Compatible => {
+                format!("{}/chat/completions", base_url)
+            }
+        }
+    }
+}
+
+impl Provider for OpenAICompatible {
+    fn name(&self) -> &'static str {
+        match self.flavor {
+            OpenAIFlavor::OpenAI => "openai",
+            OpenAIFlavor::Azure => "azure",
+            OpenAIFlavor::Ollama => "ollama",
+            OpenAIFlavor::Compatible => "openai-compatible",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self.flavor {
+            OpenAIFlavor::OpenAI | OpenAIFlavor::Compatible => "gpt-3.5-turbo",
+            OpenAIFlavor::Azure => "gpt-35-turbo",
+            OpenAIFlavor::Ollama => "llama3",
+        }
+    }
+
+    fn api_key_env(&self) -> Option<&'static str> {
+        match self.flavor {
+            OpenAIFlavor::OpenAI => Some("OPENAI_API_KEY"),
+            OpenAIFlavor::Azure => Some("AZURE_OPENAI_API_KEY"),
+            OpenAIFlavor::Ollama => None,
+            OpenAIFlavor::Compatible => Some("RCOMMIT_API_KEY"),
+        }
+    }
+
+    fn base_url_env(&self) -> &'static str {
+        match self.flavor {
+            OpenAIFlavor::OpenAI => "OPENAI_BASE_URL",
+            OpenAIFlavor::Azure => "AZURE_OPENAI_ENDPOINT",
+            OpenAIFlavor::Ollama => "OLLAMA_HOST",
+            OpenAIFlavor::Compatible => "RCOMMIT_BASE_URL",
+        }
+    }
+
+    fn build(&self, settings: ProviderSettings) -> Result<ChatModel, Box<dyn Error>> {
+        let base_url = settings
+            .base_url
+            .or_else(|| self.default_base_url().map(str::to_string))
+            .ok_or_else(|| ProviderError::MissingSetting {
+                provider: self.name(),
+                variable: self.base_url_env(),
+            })?;
+        if self.requires_api_key() && settings.api_key.is_none() {
+            return Err(ProviderError::MissingSetting {
+                provider: self.name(),
+                variable: self.api_key_env().unwrap_or_default(),
+            }
+            .into());
+        }
+
+        let endpoint = self.endpoint(&base_url, &settings.model);
+        Ok(ChatModel::new(OpenAIChat {
+            provider: self.name(),
+            endpoint,
+            api_key: settings.api_key,
+            azure_auth: self.flavor == OpenAIFlavor::Azure,
+            model: settings.model,
+            max_tokens: None,
+            temperature: 0.0,
+        }))
+    }
+}
+
+/// A chat model reached through an OpenAI-style `/chat/completions` endpoint.
+struct OpenAIChat {
+    provider: &'static str,
+    endpoint: String,
+    api_key: Option<String>,
+    azure_auth: bool,
+    model: String,
+    max_tokens: Option<u16>,
+    temperature: f32,
+}
+
+impl OpenAIChat {
+    fn request_body(&self, messages: &[Message]) -> serde_json::Value {
+        let messages: Vec<_> = messages
+            .iter()
+            .map(|m| json!({ "role": role(&m.message_type), "content": m.content }))
+            .collect();
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": self.temperature,
+        });
+        if let Some(max_tokens) = self.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        body
+    }
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+impl CompletionResponse {
+    /// The first choice's text; a reply without one reads as empty.
+    fn into_result(self) -> GenerateResult {
+        GenerateResult {
+            generation: self
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .unwrap_or_default(),
+            tokens: self
+                .usage
+                .map(|usage| TokenUsage::new(usage.prompt_tokens, usage.completion_tokens)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+fn role(message_type: &MessageType) -> &'static str {
+    match message_type {
+        MessageType::SystemMessage => "system",
+        MessageType::HumanMessage => "user",
+        MessageType::AIMessage => "assistant",
+    }
+}
+
+#[async_trait]
+impl LLM for OpenAIChat {
+    async fn generate(&self, messages: &[Message]) -> Result<GenerateResult, Box<dyn Error>> {
+        let mut request = reqwest::Client::new()
+            .post(&self.endpoint)
+            .json(&self.request_body(messages));
+        if let Some(api_key) = &self.api_key {
+            request = if self.azure_auth {
+                request.header("api-key", api_key)
+            } else {
+                request.bearer_auth(api_key)
+            };
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(ProviderError::from_response(self.provider, response)
+                .await
+                .into());
+        }
+        let completion: CompletionResponse = response.json().await?;
+        Ok(completion.into_result())
+    }
+
+    async fn invoke(&self, prompt: &str) -> Result<String, Box<dyn Error>> {
+        self.generate(&[Message::new_human_message(prompt)])
+            .await
+            .map(|result| result.generation)
+    }
+
+    fn with_options(&mut self, options: CallOptions) {
+        if let Some(max_tokens) = options.max_tokens {
+            self.max_tokens = Some(max_tokens);
+        }
+        if let Some(temperature) = options.temperature {
+            self.temperature = temperature;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderMap;
+
+    use super::*;
+    use crate::error::AppError;
+
+    fn chat() -> OpenAIChat {
+        OpenAIChat {
+            provider: "openai",
+            endpoint: "https://api.openai.com/v1/chat/completions".to_string(),
+            api_key: Some("sk-test".to_string()),
+            azure_auth: false,
+            model: "gpt-4".to_string(),
+            max_tokens: None,
+            temperature: 0.0,
+        }
+    }
+
+    #[test]
+    fn sends_every_message_with_its_role() {
+        let mut chat = chat();
+        let messages = [
+            Message::new_system_message("Write commit messages."),
+            Message::new_human_message("diff"),
+            Message::new_ai_message("fix: typo"),
+        ];
+        let body = chat.request_body(&messages);
+        assert_eq!(
+            body,
+            json!({
+                "model": "gpt-4",
+                "temperature": 0.0,
+                "messages": [
+                    { "role": "system", "content": "Write commit messages." },
+                    { "role": "user", "content": "diff" },
+                    { "role": "assistant", "content": "fix: typo" },
+                ],
+            })
+        );
+
+        chat.with_options(CallOptions::default().with_max_tokens(200));
+        assert_eq!(chat.request_body(&messages)["max_tokens"], 200);
+    }
+
+    #[test]
+    fn reads_the_first_choice_and_usage() {
+        let reply = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "choices": [
+                { "index": 0, "message": { "role": "assistant", "content": "feat: add login" }, "finish_reason": "stop" },
+                { "index": 1, "message": { "role": "assistant", "content": "ignored" }, "finish_reason": "stop" }
+            ],
+            "usage": { "prompt_tokens": 120, "completion_tokens": 8, "total_tokens": 128 }
+        }"#;
+        let result = serde_json::from_str::<CompletionResponse>(reply)
+            .unwrap()
+            .into_result();
+        assert_eq!(result.generation, "feat: add login");
+        let usage = result.tokens.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (120, 8));
+
+        let empty = r#"{ "choices": [{ "message": { "role": "assistant", "content": null } }] }"#;
+        let result = serde_json::from_str::<CompletionResponse>(empty)
+            .unwrap()
+            .into_result();
+        assert_eq!(result.generation, "");
+        assert!(result.tokens.is_none());
+    }
+
+    #[test]
+    fn classifies_error_payloads() {
+        let overflow = r#"{"error": {"message": "This model's maximum context length is 8192 tokens.", "type": "invalid_request_error", "code": "context_length_exceeded"}}"#;
+        let err = ProviderError::from_parts("openai", 400, &HeaderMap::new(), overflow.to_string());
+        assert!(matches!(
+            AppError::from_provider(err.into()),
+            AppError::ContextOverflow {
+                provider: "openai",
+                ..
+            }
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(reqwest::header::RETRY_AFTER, "20".parse().unwrap());
+        let limited = r#"{"error": {"message": "Rate limit reached", "type": "requests", "code": "rate_limit_exceeded"}}"#;
+        let err = ProviderError::from_parts("openai", 429, &headers, limited.to_string());
+        assert!(matches!(
+            AppError::from_provider(err.into()),
+            AppError::RateLimited {
+                retry_after: Some(20),
+                ..
+            }
+        ));
+
+        let bad_key = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "code": "invalid_api_key"}}"#;
+        let err = ProviderError::from_parts("openai", 401, &HeaderMap::new(), bad_key.to_string());
+        assert!(err.to_string().contains("Incorrect API key provided"));
+        assert!(matches!(
+            AppError::from_provider(err.into()),
+            AppError::Provider {
+                status: Some(401),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn ollama_host_may_omit_the_scheme() {
+        let ollama = OpenAICompatible {
+            flavor: OpenAIFlavor::Ollama,
+        };
+        assert_eq!(
+            ollama.endpoint("127.0.0.1:11434", "llama3"),
+            "http://127.0.0.1:11434/v1/chat/completions"
+        );
+        assert_eq!(
+            ollama.endpoint("https://ollama.example.com/", "llama3"),
+            "https://ollama.example.com/v1/chat/completions"
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+/// Notes and warnings printed so far, kept for `--format json`.
+static DIAGNOSTICS: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Note,
+    Warning,
+}
+
+/// Something the user was told on stderr while the message was prepared.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+}
+
+/// Prints `note: <message>` to stderr and records it.
+pub fn note(message: impl Into<String>) {
+    record(Level::Note, message.into());
+}
+
+/// Prints `warning: <message>` to stderr and records it.
+pub fn warning(message: impl Into<String>) {
+    record(Level::Warning, message.into());
+}
+
+/// Everything recorded so far, oldest first.
+pub fn diagnostics() -> Vec<Diagnostic> {
+    DIAGNOSTICS
+        .lock()
+        .map(|diagnostics| diagnostics.clone())
+        .unwrap_or_default()
+}
+
+fn record(level: Level, message: String) {
+    let label = match level {
+        Level::Note => "note",
+        Level::Warning => "warning",
+    };
+    eprintln!("{}: {}", label, message);
+    if let Ok(mut diagnostics) = DIAGNOSTICS.lock() {
+        diagnostics.push(Diagnostic { level, message });
+    }
+}
@@ -0,0 +1,240 @@
+use std::io;
+
+use dialoguer::console::Term;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Editor, Input, MultiSelect, Select};
+
+/// What the user chose to do with a generated commit message.
+pub enum ReviewAction {
+    Accept(String),
+    /// Asks for a new draft; `draft` is the message as last shown, with any
+    /// edits made before.
+    Regenerate {
+        draft: String,
+        feedback: String,
+    },
+    Abort,
+}
+
+const CHOICES: &[&str] = &[
+    "Accept",
+    "Edit in $EDITOR",
+    "Regenerate with feedback",
+    "Abort",
+];
+
+/// Shows `message` and asks the user what to do with it. Editing loops back
+/// to the menu with the edited text, so the result can still be reviewed.
+pub fn review_message(message: &str) -> io::Result<ReviewAction> {
+    let theme = ColorfulTheme::default();
+    let mut message = message.to_string();
+    loop {
+        print_message(&message);
+        let choice = Select::with_theme(&theme)
+            .with_prompt("What do you want to do with this message?")
+            .items(CHOICES)
+            .default(0)
+            .interact_opt()
+            .map_err(io::Error::other)?;
+
+        match choice {
+            Some(0) => return Ok(ReviewAction::Accept(message)),
+            Some(1) => {
+                if let Some(edited) = Editor::new().edit(&message).map_err(io::Error::other)? {
+                    message = edited.trim().to_string();
+                }
+            }
+            Some(2) => {
+                let feedback: String = Input::with_theme(&theme)
+                    .with_prompt("Feedback for the model (e.g. \"shorter\")")
+                    .interact_text()
+                    .map_err(io::Error::other)?;
+                return Ok(ReviewAction::Regenerate {
+                    draft: message,
+                    feedback,
+                });
+            }
+            _ => return Ok(ReviewAction::Abort),
+        }
+    }
+}
+
+/// What the user chose to do with the new message for an existing commit.
+pub enum RewordAction {
+    Use(String),
+    Keep,
+    /// Asks for a new message; `draft` is the new message as last shown,
+    /// with any edits made before.
+    Regenerate {
+        draft: String,
+        feedback: String,
+    },
+    Abort,
+}
+
+const REWORD_CHOICES: &[&str] = &[
+    "Use the new message",
+    "Edit it in $EDITOR",
+    "Regenerate with feedback",
+    "Keep the old message",
+    "Abort without rewording anything",
+];
+
+/// Shows the old and new message of `commit` side by side and asks which
+/// one to keep. Editing loops back to the menu like [`review_message`].
+pub fn review_reword(commit: &str, old: &str, new: &str) -> io::Result<RewordAction> {
+    let theme = ColorfulTheme::default();
+    let mut new = new.to_string();
+    loop {
+        println!("\n{}", commit);
+        println!("{}", side_by_side(old, &new));
+        let choice = Select::with_theme(&theme)
+            .with_prompt("Reword this commit?")
+            .items(REWORD_CHOICES)
+            .default(0)
+            .interact_opt()
+            .map_err(io::Error::other)?;
+
+        match choice {
+            Some(0) => return Ok(RewordAction::Use(new)),
+            Some(1) => {
+                if let Some(edited) = Editor::new().edit(&new).map_err(io::Error::other)? {
+                    new = edited.trim().to_string();
+                }
+            }
+            Some(2) => {
+                let feedback: String = Input::with_theme(&theme)
+                    .with_prompt("Feedback for the model (e.g. \"mention the migration\")")
+                    .interact_text()
+                    .map_err(io::Error::other)?;
+                return Ok(RewordAction::Regenerate {
+                    draft: new,
+                    feedback,
+                });
+            }
+            Some(3) => return Ok(RewordAction::Keep),
+            _ => return Ok(RewordAction::Abort),
+        }
+    }
+}
+
+/// `old` and `new` in two columns, wrapped to fit the terminal.
+pub fn side_by_side(old: &str, new: &str) -> String {
+    let width = Term::stdout()
+        .size_checked()
+        .map_or(100, |(_, columns)| columns as usize);
+    let column = (width.saturating_sub(3) / 2).max(20);
+    let left = wrap(old.trim(), column);
+    let right = wrap(new.trim(), column);
+    let mut lines = vec![
+        format!("{:<column$} │ {}", "Old", "New"),
+        format!("{}─┼─{}", "─".repeat(column), "─".repeat(column)),
+    ];
+    for idx in 0..left.len().max(right.len()) {
+        let old = left.get(idx).map_or("", String::as_str);
+        let new = right.get(idx).map_or("", String::as_str);
+        lines.push(format!("{:<column$} │ {}", old, new).trim_end().to_string());
+    }
+    lines.join("\n")
+}
+
+/// Cuts every line of `text` into pieces of at most `width` characters.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut wrapped = Vec::new();
+    for line in text.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            wrapped.push(String::new());
+        }
+        for piece in chars.chunks(width) {
+            wrapped.push(piece.iter().collect());
+        }
+    }
+    wrapped
+}
+
+/// What to do when the staged changes span several scopes.
+pub enum MixedScopesAction {
+    Continue,
+    Split,
+    Abort,
+}
+
+/// Asks whether to go on with one message for changes that span several
+/// scopes, split them with `rcommit split` (when `can_split`) or stop.
+pub fn confirm_mixed_scopes(scopes: &[String], can_split: bool) -> io::Result<MixedScopesAction> {
+    let mut items = vec!["Write one message for all of them"];
+    if can_split {
+        items.push("Split them into several commits");
+    }
+    items.push("Abort");
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "The staged changes touch {} scopes ({}). Continue?",
+            scopes.len(),
+            scopes.join(", ")
+        ))
+        .items(&items)
+        .default(0)
+        .interact_opt()
+        .map_err(io::Error::other)?;
+    Ok(match choice.map(|idx| items[idx]) {
+        Some("Write one message for all of them") => MixedScopesAction::Continue,
+        Some("Split them into several commits") => MixedScopesAction::Split,
+        _ => MixedScopesAction::Abort,
+    })
+}
+
+/// What to stage when the index is empty.
+pub enum StageAction {
+    Tracked,
+    Pick,
+    Abort,
+}
+
+/// Offers to stage every tracked change (when there are any) or to pick
+/// files, since there is nothing staged to describe.
+pub fn choose_what_to_stage(has_tracked: bool) -> io::Result<StageAction> {
+    let mut items = Vec::new();
+    if has_tracked {
+        items.push("Stage all changes to tracked files");
+    }
+    items.push("Pick the files to stage");
+    items.push("Abort");
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Nothing is staged. What do you want to commit?")
+        .items(&items)
+        .default(0)
+        .interact_opt()
+        .map_err(io::Error::other)?;
+    Ok(match choice.map(|idx| items[idx]) {
+        Some("Stage all changes to tracked files") => StageAction::Tracked,
+        Some("Pick the files to stage") => StageAction::Pick,
+        _ => StageAction::Abort,
+    })
+}
+
+/// Lets the user tick files in `labels`; returns the chosen indices.
+pub fn pick_files(labels: &[String]) -> io::Result<Vec<usize>> {
+    let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Files to stage (space to select, enter to confirm)")
+        .items(labels)
+        .interact_opt()
+        .map_err(io::Error::other)?;
+    Ok(picked.unwrap_or_default())
+}
+
+/// Asks a yes/no question, defaulting to no.
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .map_err(io::Error::other)
+}
+
+fn print_message(message: &str) {
+    println!("\n{}", "-".repeat(60));
+    println!("{}", message.trim());
+    println!("{}\n", "-".repeat(60));
+}
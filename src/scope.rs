@@ -0,0 +1,447 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::git::FileChange;
+use crate::report;
+
+/// Maps staged paths to Conventional Commits scopes.
+///
+/// Rules from the configuration are tried first, then the members of any
+/// workspace found at the repository root. Within each group the longest
+/// matching path prefix wins. Scopes outside a non-empty `scopes` list are
+/// never inferred.
+#[derive(Debug, Clone, Default)]
+pub struct ScopeResolver {
+    configured: Vec<(PathBuf, String)>,
+    detected: Vec<(PathBuf, String)>,
+}
+
+impl ScopeResolver {
+    /// `rules` pairs a path prefix (relative to `root`) with its scope. With
+    /// `detect` set, workspace members are read from `Cargo.toml`,
+    /// `package.json`, `pnpm-workspace.yaml` and `lerna.json`, each becoming
+    /// a scope named after its directory. When `allowed` is not empty, rules
+    /// and members whose scope it does not list are left out; a rule is
+    /// reported, as it contradicts the configuration.
+    pub fn new(
+        root: &Path,
+        rules: &[(String, String)],
+        detect: bool,
+        allowed: &[String],
+    ) -> io::Result<Self> {
+        let is_allowed = |scope: &str| allowed.is_empty() || allowed.iter().any(|a| a == scope);
+        let mut configured = Vec::new();
+        for (prefix, scope) in rules {
+            if !is_allowed(scope) {
+                report::warning(format!(
+                    "scope-rules maps {} to `{}`, which is not in scopes, ignoring the rule",
+                    prefix, scope
+                ));
+                continue;
+            }
+            configured.push((PathBuf::from(prefix.trim_end_matches('/')), scope.clone()));
+        }
+        let mut detected = Vec::new();
+        if detect {
+            for member in workspace_members(root)? {
+                if let Some(name) = member.file_name() {
+                    let scope = name.to_string_lossy().to_string();
+                    if is_allowed(&scope) {
+                        detected.push((member, scope));
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            configured,
+            detected,
+        })
+    }
+
+    pub fn resolve(&self, path: &Path) -> Option<&str> {
+        longest_prefix(&self.configured, path).or_else(|| longest_prefix(&self.detected, path))
+    }
+
+    /// The distinct scopes touched by `changes`, sorted. A rename counts for
+    /// both its old and new location.
+    pub fn scopes_for(&self, changes: &[FileChange]) -> Vec<String> {
+        let mut scopes = BTreeSet::new();
+        for change in changes {
+            for path in std::iter::once(&change.path).chain(change.old_path.as_ref()) {
+                if let Some(scope) = self.resolve(path) {
+                    scopes.insert(scope.to_string());
+                }
+            }
+        }
+        scopes.into_iter().collect()
+    }
+}
+
+fn longest_prefix<'a>(rules: &'a [(PathBuf, String)], path: &Path) -> Option<&'a str> {
+    rules
+        .iter()
+        .filter(|(prefix, _)| path.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.components().count())
+        .map(|(_, scope)| scope.as_str())
+}
+
+/// Member directories of every workspace manifest at `root`, relative to it.
+fn workspace_members(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut patterns = Vec::new();
+    if let Some(text) = read_optional(&root.join("Cargo.toml"))? {
+        patterns.extend(cargo_members(&text));
+    }
+    if let Some(text) = read_optional(&root.join("package.json"))? {
+        patterns.extend(json_members(&text, &["workspaces"]));
+        patterns.extend(json_members(&text, &["workspaces", "packages"]));
+    }
+    if let Some(text) = read_optional(&root.join("lerna.json"))? {
+        patterns.extend(json_members(&text, &["packages"]));
+    }
+    if let Some(text) = read_optional(&root.join("pnpm-workspace.yaml"))? {
+        patterns.extend(pnpm_members(&text));
+    }
+
+    let mut members = BTreeSet::new();
+    let excluded: Vec<&String> = patterns.iter().filter(|p| p.starts_with('!')).collect();
+    for pattern in patterns.iter().filter(|p| !p.starts_with('!')) {
+        for member in expand(root, pattern.trim_start_matches("./"))? {
+            let negated = excluded.iter().any(|exclude| {
+                let exclude = exclude.trim_start_matches('!').trim_start_matches("./");
+                path_matches(exclude, &member)
+            });
+            if !negated && member.as_os_str() != "" {
+                members.insert(member);
+            }
+        }
+    }
+    Ok(members.into_iter().collect())
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// `[workspace] members`, minus `exclude`, written as `!` patterns.
+fn cargo_members(text: &str) -> Vec<String> {
+    let Ok(manifest) = text.parse::<toml::Table>() else {
+        report::warning("could not parse Cargo.toml, skipping its workspace members");
+        return Vec::new();
+    };
+    let Some(workspace) = manifest.get("workspace").and_then(|w| w.as_table()) else {
+        return Vec::new();
+    };
+    let strings = |key: &str| -> Vec<String> {
+        workspace
+            .get(key)
+            .and_then(|value| value.as_array())
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut members = strings("members");
+    members.extend(strings("exclude").into_iter().map(|e| format!("!{}", e)));
+    members
+}
+
+/// The string array found by following `keys` through a JSON document.
+fn json_members(text: &str, keys: &[&str]) -> Vec<String> {
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(text) else {
+        return Vec::new();
+    };
+    for key in keys {
+        value = match value.get(key) {
+            Some(inner) => inner.clone(),
+            None => return Vec::new(),
+        };
+    }
+    value
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The `packages:` list of `pnpm-workspace.yaml`. Only the block-list form
+/// pnpm documents is understood, which is all a workspace file contains.
+fn pnpm_members(text: &str) -> Vec<String> {
+    let mut members = Vec::new();
+    let mut in_packages = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with([' ', '\t', '-']) {
+            in_packages = trimmed == "packages:";
+            continue;
+        }
+        if let Some(item) = trimmed.strip_prefix('-').filter(|_| in_packages) {
+            members.push(item.trim().trim_matches(['\'', '"']).to_string());
+        }
+    }
+    members
+}
+
+/// Expands a member pattern such as `crates/*` into existing directories.
+/// Each path segment may use `*` and `?`; `**` matches one level like `*`.
+fn expand(root: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut found = vec![PathBuf::new()];
+    for segment in pattern.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        let mut next = Vec::new();
+        for base in &found {
+            if !segment.contains(['*', '?']) {
+                if root.join(base).join(segment).is_dir() {
+                    next.push(base.join(segment));
+                }
+                continue;
+            }
+            let entries = match fs::read_dir(root.join(base)) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if entry.file_type()?.is_dir()
+                    && !name.starts_with('.')
+                    && wildcard_match(segment, &name)
+                {
+                    next.push(base.join(name));
+                }
+            }
+        }
+        found = next;
+    }
+    Ok(found)
+}
+
+fn path_matches(pattern: &str, path: &Path) -> bool {
+    let segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let components: Vec<String> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    segments.len() == components.len()
+        && segments
+            .iter()
+            .zip(&components)
+            .all(|(segment, component)| wildcard_match(segment, component))
+}
+
+/// Matches `name` against a pattern where `*` is any run of characters and
+/// `?` a single one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                while pattern.get(p) == Some(&'*') {
+                    p += 1;
+                }
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test, removed first if a previous run left it.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rcommit-scope-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rules(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(prefix, scope)| (prefix.to_string(), scope.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("api-*", "api-server"));
+        assert!(wildcard_match("*-server", "api-server"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(wildcard_match("v?", "v1"));
+        assert!(wildcard_match("exact", "exact"));
+        assert!(!wildcard_match("v?", "v10"));
+        assert!(!wildcard_match("api-*", "web-server"));
+        assert!(!wildcard_match("a*c", "abd"));
+        assert!(!wildcard_match("", "x"));
+    }
+
+    #[test]
+    fn longest_prefix_wins_by_whole_components() {
+        let rules = vec![
+            (PathBuf::from("services"), "services".to_string()),
+            (PathBuf::from("services/api"), "api".to_string()),
+        ];
+        let resolve = |path: &str| longest_prefix(&rules, Path::new(path));
+        assert_eq!(resolve("services/api/src/main.rs"), Some("api"));
+        assert_eq!(resolve("services/web/index.ts"), Some("services"));
+        assert_eq!(resolve("services/api-gateway/main.go"), Some("services"));
+        assert_eq!(resolve("docs/readme.md"), None);
+    }
+
+    #[test]
+    fn reads_the_pnpm_packages_list() {
+        let yaml = "# workspace\npackages:\n  - 'packages/*'\n  - \"apps/web\"\n  - '!**/test/**'\n\ncatalog:\n  - not-a-member\n";
+        assert_eq!(
+            pnpm_members(yaml),
+            vec!["packages/*", "apps/web", "!**/test/**"]
+        );
+        assert!(pnpm_members("onlyBuiltDependencies:\n  - esbuild\n").is_empty());
+    }
+
+    #[test]
+    fn reads_cargo_members_and_excludes() {
+        let manifest =
+            "[workspace]\nmembers = [\"crates/*\", \"tools/cli\"]\nexclude = [\"crates/old\"]\n";
+        assert_eq!(
+            cargo_members(manifest),
+            vec!["crates/*", "tools/cli", "!crates/old"]
+        );
+        assert!(cargo_members("[package]\nname = \"x\"\n").is_empty());
+    }
+
+    #[test]
+    fn reads_json_workspaces_in_both_forms() {
+        let list = r#"{"workspaces": ["packages/*"]}"#;
+        let object = r#"{"workspaces": {"packages": ["apps/*"]}}"#;
+        assert_eq!(json_members(list, &["workspaces"]), vec!["packages/*"]);
+        assert!(json_members(object, &["workspaces"]).is_empty());
+        assert_eq!(
+            json_members(object, &["workspaces", "packages"]),
+            vec!["apps/*"]
+        );
+    }
+
+    #[test]
+    fn expands_workspace_members_on_disk() {
+        let root = scratch_dir("members");
+        for dir in ["crates/api", "crates/web", "crates/old", "crates/.hidden"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\", \"missing\"]\nexclude = [\"crates/old\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            workspace_members(&root).unwrap(),
+            vec![PathBuf::from("crates/api"), PathBuf::from("crates/web")]
+        );
+    }
+
+    #[test]
+    fn rules_win_over_workspace_members() {
+        let root = scratch_dir("resolver");
+        fs::create_dir_all(root.join("crates/api")).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .unwrap();
+        let resolver =
+            ScopeResolver::new(&root, &rules(&[("crates/api/", "backend")]), true, &[]).unwrap();
+        assert_eq!(
+            resolver.resolve(Path::new("crates/api/lib.rs")),
+            Some("backend")
+        );
+
+        let resolver = ScopeResolver::new(&root, &[], true, &[]).unwrap();
+        assert_eq!(
+            resolver.resolve(Path::new("crates/api/lib.rs")),
+            Some("api")
+        );
+        let resolver = ScopeResolver::new(&root, &[], false, &[]).unwrap();
+        assert_eq!(resolver.resolve(Path::new("crates/api/lib.rs")), None);
+    }
+
+    #[test]
+    fn only_allowed_scopes_are_inferred() {
+        let root = scratch_dir("allowed");
+        fs::create_dir_all(root.join("crates/api")).unwrap();
+        fs::create_dir_all(root.join("crates/web")).unwrap();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .unwrap();
+        let rules = rules(&[("services", "svc"), ("services/api", "server")]);
+        let allowed = vec!["api".to_string(), "svc".to_string()];
+        let resolver = ScopeResolver::new(&root, &rules, true, &allowed).unwrap();
+        assert_eq!(
+            resolver.resolve(Path::new("crates/api/lib.rs")),
+            Some("api")
+        );
+        assert_eq!(resolver.resolve(Path::new("crates/web/lib.rs")), None);
+        assert_eq!(
+            resolver.resolve(Path::new("services/api/main.rs")),
+            Some("svc")
+        );
+    }
+
+    #[test]
+    fn renames_count_for_both_locations() {
+        let resolver = ScopeResolver::new(
+            Path::new("."),
+            &rules(&[("api", "api"), ("web", "web")]),
+            false,
+            &[],
+        )
+        .unwrap();
+        let change = FileChange {
+            path: PathBuf::from("web/util.ts"),
+            old_path: Some(PathBuf::from("api/util.ts")),
+            status: crate::git::ChangeStatus::Renamed,
+            hunks: Vec::new(),
+            binary: false,
+            additions: 0,
+            deletions: 0,
+            mode_change: None,
+        };
+        assert_eq!(resolver.scopes_for(&[change]), vec!["api", "web"]);
+    }
+}
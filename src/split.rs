@@ -0,0 +1,687 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use git2::Repository;
+use serde::Deserialize;
+
+use crate::budget;
+use crate::exclude::ExcludeMatcher;
+use crate::git;
+use crate::scope::ScopeResolver;
+use crate::secrets;
+
+/// Hunks longer than this are cut short in the prompt; the full hunk is
+/// still committed.
+const MAX_PROMPT_HUNK_LINES: usize = 60;
+
+/// The instructions sent with the numbered hunks.
+const SPLIT_PROMPT: &str = r#"The staged changes below mix several unrelated edits. Group the numbered hunks into logical, atomic commits.
+Every hunk number must appear in exactly one group. Order the groups so each commit builds on the previous ones.
+For each group write a conventional commit message in {language}, using one of these types: {types}.
+Reply with only a JSON array and nothing else, for example:
+[{"message": "feat(api): add login endpoint", "hunks": [1, 3]}, {"message": "docs: describe login", "hunks": [2]}]
+
+Hunks:
+{hunks}"#;
+
+/// One staged file as a patch, cut into its header and hunks. The bytes are
+/// kept as git wrote them and only decoded for the prompt.
+#[derive(Debug, Clone)]
+struct FilePatch {
+    path: PathBuf,
+    header: Vec<u8>,
+    hunks: Vec<Vec<u8>>,
+    /// New, deleted, renamed, copied, binary and mode-changed files only
+    /// move as a whole.
+    whole: bool,
+}
+
+/// The smallest piece that can go into a commit: one hunk, or a whole file.
+#[derive(Debug, Clone)]
+struct Unit {
+    file: usize,
+    hunk: Option<usize>,
+    label: String,
+    text: String,
+}
+
+/// A proposed commit: its message and the (1-based) hunk numbers it takes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Group {
+    pub message: String,
+    pub hunks: Vec<usize>,
+}
+
+/// The staged change cut into numbered units for the model. Files hidden by
+/// the exclude patterns are kept aside and go into the last commit.
+#[derive(Debug, Clone)]
+pub struct StagedSplit {
+    files: Vec<FilePatch>,
+    units: Vec<Unit>,
+    excluded: Vec<usize>,
+}
+
+impl StagedSplit {
+    pub fn read(
+        repo: &Repository,
+        excludes: &ExcludeMatcher,
+        scopes: &ScopeResolver,
+    ) -> Result<Self, git2::Error> {
+        Ok(Self::from_patches(
+            git::staged_patches(repo)?,
+            excludes,
+            scopes,
+        ))
+    }
+
+    /// Cuts per-file patches, as returned by [`git::staged_patches`], into
+    /// units.
+    fn from_patches(
+        patches: Vec<(PathBuf, Vec<u8>)>,
+        excludes: &ExcludeMatcher,
+        scopes: &ScopeResolver,
+    ) -> Self {
+        let mut split = StagedSplit {
+            files: Vec::new(),
+            units: Vec::new(),
+            excluded: Vec::new(),
+        };
+        for (path, bytes) in patches {
+            let file = split_patch(path, &bytes);
+            let idx = split.files.len();
+            if excludes.is_excluded(&file.path) {
+                split.excluded.push(idx);
+                split.files.push(file);
+                continue;
+            }
+            let scope = scopes
+                .resolve(&file.path)
+                .map(|scope| format!(", scope {}", scope))
+                .unwrap_or_default();
+            if file.whole {
+                split.units.push(Unit {
+                    file: idx,
+                    hunk: None,
+                    label: format!("{} ({}{})", file.path.display(), file_kind(&file), scope),
+                    text: whole_file_text(&file),
+                });
+            } else {
+                for (hunk_idx, hunk) in file.hunks.iter().enumerate() {
+                    // The `@@` line goes into the label, the rest below it.
+                    let hunk = String::from_utf8_lossy(hunk);
+                    let (header, body) = hunk.split_once('\n').unwrap_or((&hunk, ""));
+                    split.units.push(Unit {
+                        file: idx,
+                        hunk: Some(hunk_idx),
+                        label: format!("{} {}{}", file.path.display(), header, scope),
+                        text: prompt_hunk(body),
+                    });
+                }
+            }
+            split.files.push(file);
+        }
+        split
+    }
+
+    /// Number of hunks the model has to place.
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    /// The prompt asking the model to group the numbered hunks, with the
+    /// number of hunks shortened to fit `max_tokens`. Every hunk keeps its
+    /// number and label so it can still be placed; the budget is shared out
+    /// smallest hunk first, like [`budget::plan_diff`] does for files.
+    pub fn prompt(&self, types: &str, language: &str, max_tokens: usize) -> (String, usize) {
+        let labels: Vec<String> = self
+            .units
+            .iter()
+            .enumerate()
+            .map(|(idx, unit)| format!("[{}] {}\n", idx + 1, unit.label))
+            .collect();
+        let fixed: usize = labels
+            .iter()
+            .map(|label| budget::estimate_tokens(label))
+            .sum();
+        let mut remaining = max_tokens.saturating_sub(fixed);
+
+        let mut order: Vec<usize> = (0..self.units.len()).collect();
+        order.sort_by_key(|&idx| budget::estimate_tokens(&self.units[idx].text));
+        let mut texts = vec![String::new(); self.units.len()];
+        let mut shortened = 0;
+        for (position, &idx) in order.iter().enumerate() {
+            let text = &self.units[idx].text;
+            let share = remaining / (order.len() - position);
+            texts[idx] = if budget::estimate_tokens(text) <= share {
+                text.clone()
+            } else {
+                shortened += 1;
+                format!("{}\n", budget::truncate_to(text, share))
+            };
+            remaining = remaining.saturating_sub(budget::estimate_tokens(&texts[idx]));
+        }
+
+        let hunks: Vec<String> = labels
+            .iter()
+            .zip(&texts)
+            .map(|(label, text)| format!("{}{}", label, text))
+            .collect();
+        let prompt = SPLIT_PROMPT
+            .replacen("{language}", language, 1)
+            .replacen("{types}", types, 1)
+            .replacen("{hunks}", &hunks.join("\n"), 1);
+        (prompt, shortened)
+    }
+
+    /// Redacts likely secrets from the text shown to the model, labels
+    /// included, as the function context of a hunk header can hold one too.
+    /// The patches that get committed are left as they are.
+    pub fn redact_secrets(&mut self) {
+        for unit in &mut self.units {
+            unit.label = secrets::redact(&unit.label);
+            let mut text = String::with_capacity(unit.text.len());
+            for line in unit.text.lines() {
+                let (origin, content) = line.split_at(line.len().min(1));
+                text.push_str(origin);
+                text.push_str(&secrets::redact(content));
+                text.push('\n');
+            }
+            unit.text = text;
+        }
+    }
+
+    /// Reads the model's JSON answer and checks that it places every hunk
+    /// exactly once.
+    pub fn parse_groups(&self, answer: &str) -> Result<Vec<Group>, SplitError> {
+        let groups: Vec<Group> =
+            serde_json::from_str(answer).map_err(|err| SplitError::InvalidJson(err.to_string()))?;
+        if groups.is_empty() {
+            return Err(SplitError::NoGroups);
+        }
+        let mut seen = vec![false; self.units.len()];
+        for group in &groups {
+            if group.hunks.is_empty() {
+                return Err(SplitError::EmptyGroup(group.message.clone()));
+            }
+            for &number in &group.hunks {
+                match seen.get_mut(number.wrapping_sub(1)) {
+                    None => return Err(SplitError::UnknownHunk(number)),
+                    Some(true) => return Err(SplitError::DuplicateHunk(number)),
+                    Some(placed) => *placed = true,
+                }
+            }
+        }
+        let missing: Vec<usize> = seen
+            .iter()
+            .enumerate()
+            .filter(|(_, placed)| !**placed)
+            .map(|(idx, _)| idx + 1)
+            .collect();
+        if !missing.is_empty() {
+            return Err(SplitError::MissingHunks(missing));
+        }
+        Ok(groups)
+    }
+
+    /// One line per hunk of `group`, for showing the plan. The last group
+    /// also lists the excluded files that ride along with it.
+    pub fn describe(&self, group: &Group, last: bool) -> Vec<String> {
+        let mut lines: Vec<String> = group
+            .hunks
+            .iter()
+            .map(|&number| self.units[number - 1].label.clone())
+            .collect();
+        if last {
+            lines.extend(self.excluded.iter().map(|&idx| {
+                format!(
+                    "{} (excluded from the prompt)",
+                    self.files[idx].path.display()
+                )
+            }));
+        }
+        lines
+    }
+
+    /// The patch staging exactly the hunks of `group`, plus the excluded
+    /// files when it is the last group.
+    pub fn patch_for(&self, group: &Group, last: bool) -> Vec<u8> {
+        let mut patch = Vec::new();
+        for (file_idx, file) in self.files.iter().enumerate() {
+            let whole = last && self.excluded.contains(&file_idx);
+            let mut hunks: Vec<usize> = Vec::new();
+            let mut take_file = whole;
+            for &number in &group.hunks {
+                let unit = &self.units[number - 1];
+                if unit.file != file_idx {
+                    continue;
+                }
+                match unit.hunk {
+                    Some(hunk) => hunks.push(hunk),
+                    None => take_file = true,
+                }
+            }
+            if take_file {
+                hunks = (0..file.hunks.len()).collect();
+            } else if hunks.is_empty() {
+                continue;
+            }
+            hunks.sort_unstable();
+            patch.extend_from_slice(&file.header);
+            for hunk in hunks {
+                patch.extend_from_slice(&file.hunks[hunk]);
+            }
+        }
+        patch
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    InvalidJson(String),
+    NoGroups,
+    EmptyGroup(String),
+    UnknownHunk(usize),
+    DuplicateHunk(usize),
+    MissingHunks(Vec<usize>),
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::InvalidJson(err) => {
+                write!(f, "the answer is not the requested JSON array: {}", err)
+            }
+            SplitError::NoGroups => write!(f, "the answer contains no groups"),
+            SplitError::EmptyGroup(message) => {
+                write!(f, "the group `{}` has no hunks", message)
+            }
+            SplitError::UnknownHunk(number) => write!(f, "there is no hunk {}", number),
+            SplitError::DuplicateHunk(number) => {
+                write!(f, "hunk {} appears in more than one group", number)
+            }
+            SplitError::MissingHunks(numbers) => {
+                let numbers: Vec<String> = numbers.iter().map(usize::to_string).collect();
+                match numbers.as_slice() {
+                    [number] => write!(f, "hunk {} is in no group", number),
+                    _ => write!(f, "hunks {} are in no group", numbers.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// Cuts one file's patch at its `@@` lines. Content lines always start
+/// with a space, `+`, `-` or `\`, so they never look like a hunk header.
+fn split_patch(path: PathBuf, bytes: &[u8]) -> FilePatch {
+    let mut header = Vec::new();
+    let mut hunks: Vec<Vec<u8>> = Vec::new();
+    for line in bytes.split_inclusive(|&byte| byte == b'\n') {
+        if line.starts_with(b"@@") {
+            hunks.push(Vec::new());
+        }
+        match hunks.last_mut() {
+            Some(hunk) => hunk.extend_from_slice(line),
+            None => header.extend_from_slice(line),
+        }
+    }
+    let header_text = String::from_utf8_lossy(&header);
+    let whole = hunks.len() < 2
+        || [
+            "new file mode",
+            "deleted file mode",
+            "rename from",
+            "copy from",
+            "old mode",
+            "GIT binary patch",
+            "Binary files",
+        ]
+        .iter()
+        .any(|marker| header_text.contains(marker));
+    FilePatch {
+        path,
+        header,
+        hunks,
+        whole,
+    }
+}
+
+fn file_kind(file: &FilePatch) -> &'static str {
+    let header = String::from_utf8_lossy(&file.header);
+    if header.contains("new file mode") {
+        "added"
+    } else if header.contains("deleted file mode") {
+        "deleted"
+    } else if header.contains("rename from") {
+        "renamed"
+    } else if header.contains("copy from") {
+        "copied"
+    } else if header.contains("GIT binary patch") || header.contains("Binary files") {
+        "binary"
+    } else if header.contains("old mode") {
+        "mode changed"
+    } else {
+        "modified"
+    }
+}
+
+/// Whole files are shown like in the commit prompt: deletions and binaries
+/// as one line, everything else with its hunks.
+fn whole_file_text(file: &FilePatch) -> String {
+    match file_kind(file) {
+        "deleted" => {
+            let removed: usize = file
+                .hunks
+                .iter()
+                .map(|hunk| {
+                    hunk.split(|&byte| byte == b'\n')
+                        .filter(|line| line.starts_with(b"-"))
+                        .count()
+                })
+                .sum();
+            format!("File removed ({} lines)\n", removed)
+        }
+        "binary" => "Binary file changed\n".to_string(),
+        _ => file
+            .hunks
+            .iter()
+            .map(|hunk| prompt_hunk(&String::from_utf8_lossy(hunk)))
+            .collect(),
+    }
+}
+
+fn prompt_hunk(hunk: &str) -> String {
+    let lines: Vec<&str> = hunk.lines().collect();
+    let mut text = String::new();
+    for line in lines.iter().take(MAX_PROMPT_HUNK_LINES) {
+        text.push_str(line);
+        text.push('\n');
+    }
+    if lines.len() > MAX_PROMPT_HUNK_LINES {
+        text.push_str(&format!(
+            "[... {} more lines]\n",
+            lines.len() - MAX_PROMPT_HUNK_LINES
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    const MODIFIED: &str = "diff --git a/src/a.rs b/src/a.rs
+index 1111111..2222222 100644
+--- a/src/a.rs
++++ b/src/a.rs
+@@ -1,3 +1,3 @@ fn one()
+ a
+-b
++B
+ c
+@@ -10,3 +10,3 @@ fn two()
+ x
+-y
++Y
+ z
+";
+
+    const ADDED: &str = "diff --git a/src/b.rs b/src/b.rs
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/src/b.rs
+@@ -0,0 +1,2 @@
++one
++two
+";
+
+    const LOCKFILE: &str = "diff --git a/Cargo.lock b/Cargo.lock
+index 4444444..5555555 100644
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -1,2 +1,2 @@
+-version = 1
++version = 2
+ [[package]]
+";
+
+    fn staged() -> StagedSplit {
+        let patches = vec![
+            (PathBuf::from("src/a.rs"), MODIFIED.as_bytes().to_vec()),
+            (PathBuf::from("src/b.rs"), ADDED.as_bytes().to_vec()),
+            (PathBuf::from("Cargo.lock"), LOCKFILE.as_bytes().to_vec()),
+        ];
+        let root = std::env::temp_dir();
+        let excludes = ExcludeMatcher::new(&root, &["*.lock"]).unwrap();
+        StagedSplit::from_patches(patches, &excludes, &ScopeResolver::default())
+    }
+
+    fn group(hunks: &[usize]) -> Group {
+        Group {
+            message: "fix: x".to_string(),
+            hunks: hunks.to_vec(),
+        }
+    }
+
+    #[test]
+    fn split_patch_cuts_at_hunk_headers() {
+        let file = split_patch(PathBuf::from("src/a.rs"), MODIFIED.as_bytes());
+        assert!(!file.whole);
+        assert!(file.header.ends_with(b"+++ b/src/a.rs\n"));
+        assert_eq!(file.hunks.len(), 2);
+        assert!(file.hunks[0].starts_with(b"@@ -1,3 +1,3 @@ fn one()\n"));
+        assert!(file.hunks[1].starts_with(b"@@ -10,3 +10,3 @@ fn two()\n"));
+        let rejoined: Vec<u8> = [file.header.clone(), file.hunks.concat()].concat();
+        assert_eq!(rejoined, MODIFIED.as_bytes());
+    }
+
+    #[test]
+    fn new_files_and_single_hunks_move_whole() {
+        assert!(split_patch(PathBuf::from("src/b.rs"), ADDED.as_bytes()).whole);
+        assert!(split_patch(PathBuf::from("Cargo.lock"), LOCKFILE.as_bytes()).whole);
+    }
+
+    #[test]
+    fn split_patch_keeps_bytes_that_are_not_utf8() {
+        let mut patch = b"diff --git a/l.txt b/l.txt\n--- a/l.txt\n+++ b/l.txt\n".to_vec();
+        patch.extend_from_slice(b"@@ -1 +1 @@\n-caf\xe9\r\n+CAF\xc9\r\n");
+        patch.extend_from_slice(b"@@ -9 +9 @@\n-na\xefve\n+NA\xcfVE\n");
+        let file = split_patch(PathBuf::from("l.txt"), &patch);
+        assert_eq!(file.hunks[0], b"@@ -1 +1 @@\n-caf\xe9\r\n+CAF\xc9\r\n");
+        assert_eq!([file.header, file.hunks.concat()].concat(), patch);
+    }
+
+    #[test]
+    fn numbers_hunks_and_keeps_excluded_files_aside() {
+        let staged = staged();
+        assert_eq!(staged.len(), 3);
+        let (prompt, shortened) = staged.prompt("feat, fix", "English", 12_000);
+        assert_eq!(shortened, 0);
+        assert!(prompt.contains("[1] src/a.rs @@ -1,3 +1,3 @@ fn one()\n a\n-b\n+B\n c\n"));
+        assert!(prompt.contains("[2] src/a.rs @@ -10,3 +10,3 @@ fn two()"));
+        assert!(prompt.contains("[3] src/b.rs (added)\n"));
+        assert!(!prompt.contains("Cargo.lock"));
+    }
+
+    #[test]
+    fn shortens_the_largest_hunks_to_fit_the_budget() {
+        let mut big =
+            String::from("diff --git a/src/c.rs b/src/c.rs\n--- a/src/c.rs\n+++ b/src/c.rs\n");
+        big.push_str("@@ -1,40 +1,40 @@ fn big()\n");
+        for line in 0..40 {
+            big.push_str(&format!(
+                "-let value_{} = compute_something_long({});\n",
+                line, line
+            ));
+        }
+        big.push_str("@@ -90 +90 @@ fn small()\n-a\n+b\n");
+        let excludes = ExcludeMatcher::new(&std::env::temp_dir(), &[]).unwrap();
+        let staged = StagedSplit::from_patches(
+            vec![(PathBuf::from("src/c.rs"), big.into_bytes())],
+            &excludes,
+            &ScopeResolver::default(),
+        );
+
+        let (full, shortened) = staged.prompt("fix", "English", 12_000);
+        assert_eq!(shortened, 0);
+        let (prompt, shortened) = staged.prompt("fix", "English", 200);
+        assert_eq!(shortened, 1);
+        assert!(prompt.len() < full.len());
+        assert!(prompt.contains("[1] src/c.rs @@ -1,40 +1,40 @@ fn big()\n"));
+        assert!(prompt.contains("more lines cut ...]"));
+        assert!(prompt.contains("[2] src/c.rs @@ -90 +90 @@ fn small()\n-a\n+b\n"));
+    }
+
+    #[test]
+    fn redaction_covers_labels_and_hunk_lines() {
+        let patch = "diff --git a/.env b/.env
+--- a/.env
++++ b/.env
+@@ -1,2 +1,2 @@ const KEY: &str = \"sk-abcdefghijklmnopqrstuvwx\";
+-OLD=1
++NEW=1
+@@ -8,2 +8,2 @@
+-API_TOKEN=abcdefgh12345678
++API_TOKEN=zyxwvuts87654321
+";
+        let excludes = ExcludeMatcher::new(&std::env::temp_dir(), &[]).unwrap();
+        let mut staged = StagedSplit::from_patches(
+            vec![(PathBuf::from(".env"), patch.as_bytes().to_vec())],
+            &excludes,
+            &ScopeResolver::default(),
+        );
+        staged.redact_secrets();
+        let (prompt, _) = staged.prompt("fix", "English", 12_000);
+        assert!(!prompt.contains("sk-abcdefghijklmnopqrstuvwx"));
+        assert!(!prompt.contains("abcdefgh12345678"));
+        assert!(!prompt.contains("zyxwvuts87654321"));
+        assert!(
+            prompt.contains("[1] .env @@ -1,2 +1,2 @@ const KEY: &str = \"[REDACTED API key]\";")
+        );
+        assert!(prompt.contains("-API_TOKEN=[REDACTED secret assignment]\n"));
+        // What gets committed keeps the real values.
+        let committed = staged.patch_for(&group(&[1, 2]), false);
+        assert_eq!(committed, patch.as_bytes());
+    }
+
+    #[test]
+    fn parse_groups_accepts_a_complete_grouping() {
+        let groups = staged()
+            .parse_groups(
+                r#"[{"message": "fix: b", "hunks": [1, 3]}, {"message": "fix: y", "hunks": [2]}]"#,
+            )
+            .unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].hunks, vec![1, 3]);
+    }
+
+    #[test]
+    fn parse_groups_rejects_incomplete_groupings() {
+        let staged = staged();
+        let parse = |answer: &str| staged.parse_groups(answer).unwrap_err();
+        assert!(matches!(parse("not json"), SplitError::InvalidJson(_)));
+        assert_eq!(parse("[]"), SplitError::NoGroups);
+        assert_eq!(
+            parse(r#"[{"message": "fix: x", "hunks": []}]"#),
+            SplitError::EmptyGroup("fix: x".to_string())
+        );
+        assert_eq!(
+            parse(r#"[{"message": "a", "hunks": [0, 1, 2, 3]}]"#),
+            SplitError::UnknownHunk(0)
+        );
+        assert_eq!(
+            parse(r#"[{"message": "a", "hunks": [1, 2, 3, 4]}]"#),
+            SplitError::UnknownHunk(4)
+        );
+        assert_eq!(
+            parse(r#"[{"message": "a", "hunks": [1, 2]}, {"message": "b", "hunks": [2, 3]}]"#),
+            SplitError::DuplicateHunk(2)
+        );
+        assert_eq!(
+            parse(r#"[{"message": "a", "hunks": [2]}]"#),
+            SplitError::MissingHunks(vec![1, 3])
+        );
+    }
+
+    #[test]
+    fn patch_for_takes_only_the_group_hunks() {
+        let staged = staged();
+        let patch = String::from_utf8(staged.patch_for(&group(&[2]), false)).unwrap();
+        let header = MODIFIED.split("@@ -1,3").next().unwrap();
+        let second = &MODIFIED[MODIFIED.find("@@ -10,3").unwrap()..];
+        assert_eq!(patch, format!("{}{}", header, second));
+    }
+
+    #[test]
+    fn patch_for_orders_hunks_and_adds_excluded_files_last() {
+        let staged = staged();
+        let patch = staged.patch_for(&group(&[3, 2, 1]), true);
+        assert_eq!(patch, [MODIFIED, ADDED, LOCKFILE].concat().into_bytes());
+        let not_last = staged.patch_for(&group(&[3]), false);
+        assert_eq!(not_last, ADDED.as_bytes());
+    }
+
+    /// A repository in a fresh temporary directory.
+    fn scratch_repo(name: &str) -> Repository {
+        let dir =
+            std::env::temp_dir().join(format!("rcommit-split-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        repo
+    }
+
+    #[test]
+    fn latin1_hunks_apply_to_the_index_unchanged() {
+        let repo = scratch_repo("latin1");
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        // "línea N" in Latin-1, with CRLF line ends.
+        let lines: Vec<Vec<u8>> = (0..20)
+            .map(|idx| {
+                [
+                    b"l\xednea ".to_vec(),
+                    idx.to_string().into_bytes(),
+                    b"\r\n".to_vec(),
+                ]
+                .concat()
+            })
+            .collect();
+        fs::write(workdir.join("old.txt"), lines.concat()).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("old.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+
+        let mut changed = lines.clone();
+        changed[1] = b"se\xf1al\r\n".to_vec();
+        changed[17] = b"a\xf1o\r\n".to_vec();
+        fs::write(workdir.join("old.txt"), changed.concat()).unwrap();
+        index.add_path(Path::new("old.txt")).unwrap();
+        index.write().unwrap();
+
+        let excludes = ExcludeMatcher::new(&workdir, &[]).unwrap();
+        let staged = StagedSplit::read(&repo, &excludes, &ScopeResolver::default()).unwrap();
+        assert_eq!(staged.len(), 2);
+        let patch = staged.patch_for(&group(&[1]), false);
+
+        index.read_tree(&tree).unwrap();
+        index.write().unwrap();
+        git::apply_to_index(&repo, &patch).unwrap();
+        index.read(true).unwrap();
+        let entry = index.get_path(Path::new("old.txt"), 0).unwrap();
+        let blob = repo.find_blob(entry.id).unwrap();
+        let mut expected = lines.clone();
+        expected[1] = changed[1].clone();
+        assert_eq!(blob.content(), expected.concat().as_slice());
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use git2::Repository;
+
+use crate::conventional;
+use crate::git;
+use crate::report;
+
+/// How many sampled messages are quoted verbatim as examples.
+const MAX_EXAMPLES: usize = 5;
+/// Longer example messages are cut to this many lines.
+const MAX_EXAMPLE_LINES: usize = 8;
+/// How many of the most used types and scopes are listed.
+const MAX_LISTED: usize = 8;
+
+const TICKET_FOOTERS: &[&str] = &[
+    "refs", "closes", "fixes", "resolves", "ticket", "issue", "jira",
+];
+
+/// Which commits `--style-from-history` samples.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SampleFilter {
+    /// Only commits whose author email matches `user.email`.
+    pub author_only: bool,
+    /// Walk the main branch instead of `HEAD`.
+    pub main_branch: bool,
+}
+
+/// Style features observed in a sample of commit messages, plus a few of
+/// the messages themselves to show the model.
+#[derive(Debug, Clone, Default)]
+pub struct StyleProfile {
+    sampled: usize,
+    conventional: usize,
+    types: Vec<(String, usize)>,
+    scopes: Vec<(String, usize)>,
+    lowercase: usize,
+    uppercase: usize,
+    trailing_period: usize,
+    emoji: usize,
+    ticket_in_subject: usize,
+    ticket_footer: usize,
+    with_body: usize,
+    subject_chars: usize,
+    examples: Vec<String>,
+}
+
+/// Samples up to `count` recent commit messages according to `filter`.
+pub fn sample(
+    repo: &Repository,
+    count: usize,
+    filter: SampleFilter,
+) -> Result<Vec<String>, git2::Error> {
+    let start = if filter.main_branch {
+        match git::main_branch(repo) {
+            Some(branch) => branch,
+            None => {
+                report::warning("no main branch found, sampling commit style from HEAD");
+                "HEAD".to_string()
+            }
+        }
+    } else {
+        "HEAD".to_string()
+    };
+    let author = if filter.author_only {
+        let email = git::user_email(repo);
+        if email.is_none() {
+            report::warning("user.email is not set, sampling commits by every author");
+        }
+        email
+    } else {
+        None
+    };
+    git::commit_messages(repo, &start, author.as_deref(), count)
+}
+
+/// Extracts the style features of `messages`, newest first.
+pub fn analyze(messages: &[String]) -> StyleProfile {
+    let mut profile = StyleProfile {
+        sampled: messages.len(),
+        ..StyleProfile::default()
+    };
+    let mut types: HashMap<String, usize> = HashMap::new();
+    let mut scopes: HashMap<String, usize> = HashMap::new();
+
+    for message in messages {
+        let header = message.lines().next().unwrap_or_default();
+        let subject = match conventional::parse(message) {
+            Ok(commit) => {
+                profile.conventional += 1;
+                *types.entry(commit.commit_type.clone()).or_default() += 1;
+                if let Some(scope) = &commit.scope {
+                    *scopes.entry(scope.clone()).or_default() += 1;
+                }
+                if commit
+                    .footers
+                    .iter()
+                    .any(|footer| is_ticket_footer(&footer.token))
+                {
+                    profile.ticket_footer += 1;
+                }
+                commit.subject
+            }
+            Err(_) => {
+                if message.lines().any(|line| {
+                    line.split_once(':')
+                        .is_some_and(|(token, _)| is_ticket_footer(token))
+                }) {
+                    profile.ticket_footer += 1;
+                }
+                header.to_string()
+            }
+        };
+
+        match subject.chars().find(|c| c.is_alphabetic()) {
+            Some(c) if c.is_lowercase() => profile.lowercase += 1,
+            Some(c) if c.is_uppercase() => profile.uppercase += 1,
+            _ => {}
+        }
+        if subject.ends_with('.') {
+            profile.trailing_period += 1;
+        }
+        if has_emoji(header) {
+            profile.emoji += 1;
+        }
+        if mentions_ticket(header) {
+            profile.ticket_in_subject += 1;
+        }
+        if message.lines().skip(1).any(|line| !line.trim().is_empty()) {
+            profile.with_body += 1;
+        }
+        profile.subject_chars += header.chars().count();
+    }
+
+    profile.types = most_used(types);
+    profile.scopes = most_used(scopes);
+    profile.examples = pick_examples(messages);
+    profile
+}
+
+impl fmt::Display for StyleProfile {
+    /// Describes the style as prompt text: one line per feature, then the
+    /// example messages separated by `---`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sampled == 0 {
+            return Ok(());
+        }
+        let total = self.sampled;
+        writeln!(
+            f,
+            "Match the commit style of this repository, learned from its last {} commits:",
+            total
+        )?;
+        writeln!(
+            f,
+            "- {} of {} follow Conventional Commits",
+            self.conventional, total
+        )?;
+        if !self.types.is_empty() {
+            writeln!(f, "- types used: {}", counted(&self.types))?;
+        }
+        if self.scopes.is_empty() {
+            writeln!(f, "- no scopes are used")?;
+        } else {
+            writeln!(f, "- scopes used: {}", counted(&self.scopes))?;
+        }
+        writeln!(
+            f,
+            "- subjects start with a lower-case letter in {} and an upper-case letter in {} of {}",
+            self.lowercase, self.uppercase, total
+        )?;
+        writeln!(
+            f,
+            "- {} of {} subjects end with a period",
+            self.trailing_period, total
+        )?;
+        writeln!(f, "- {} of {} subjects use emoji", self.emoji, total)?;
+        writeln!(
+            f,
+            "- ticket ids appear in {} subjects and {} footers of {}",
+            self.ticket_in_subject, self.ticket_footer, total
+        )?;
+        writeln!(
+            f,
+            "- {} of {} have a body; the first line averages {} characters",
+            self.with_body,
+            total,
+            self.subject_chars / total
+        )?;
+        writeln!(f, "Example messages:")?;
+        for example in &self.examples {
+            writeln!(f, "---\n{}", example)?;
+        }
+        write!(f, "---")
+    }
+}
+
+/// Picks up to `MAX_EXAMPLES` messages, preferring ones that show a
+/// different type and scope from those already picked, then the most recent
+/// ones not yet shown.
+fn pick_examples(messages: &[String]) -> Vec<String> {
+    let kind = |message: &str| {
+        conventional::parse(message)
+            .ok()
+            .map(|commit| (commit.commit_type, commit.scope))
+    };
+    let mut seen = Vec::new();
+    let mut picked: Vec<usize> = Vec::new();
+    for (idx, message) in messages.iter().enumerate() {
+        if picked.len() >= MAX_EXAMPLES {
+            break;
+        }
+        let kind = kind(message);
+        if !seen.contains(&kind) {
+            seen.push(kind);
+            picked.push(idx);
+        }
+    }
+    for idx in 0..messages.len() {
+        if picked.len() >= MAX_EXAMPLES {
+            break;
+        }
+        // Skip repeats of a message already shown, such as re-applied commits.
+        if !picked.iter().any(|&other| messages[other] == messages[idx]) {
+            picked.push(idx);
+        }
+    }
+    picked.sort_unstable();
+
+    picked
+        .into_iter()
+        .map(|idx| {
+            let lines: Vec<&str> = messages[idx].lines().collect();
+            let mut example = lines[..lines.len().min(MAX_EXAMPLE_LINES)].join("\n");
+            if lines.len() > MAX_EXAMPLE_LINES {
+                example.push_str("\n[...]");
+            }
+            example
+        })
+        .collect()
+}
+
+fn most_used(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(MAX_LISTED);
+    counts
+}
+
+fn counted(items: &[(String, usize)]) -> String {
+    items
+        .iter()
+        .map(|(name, count)| format!("{} ({})", name, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn is_ticket_footer(token: &str) -> bool {
+    TICKET_FOOTERS
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(token.trim()))
+}
+
+/// Emoji characters or gitmoji shortcodes such as `:sparkles:`.
+fn has_emoji(text: &str) -> bool {
+    let emoji_char = text
+        .chars()
+        .any(|c| matches!(c as u32, 0x2600..=0x27BF | 0x1F300..=0x1FAFF));
+    // Every part but the first and last sits between two colons.
+    let parts: Vec<&str> = text.split(':').collect();
+    let shortcode = parts.len() > 2
+        && parts[1..parts.len() - 1].iter().any(|inner| {
+            !inner.is_empty()
+                && inner
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c == '_' || c == '+' || c == '-')
+        });
+    emoji_char || shortcode
+}
+
+/// A Jira-style key (`ABC-123`) or an issue number (`#123`) anywhere in `text`.
+fn mentions_ticket(text: &str) -> bool {
+    text.split(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '#')
+        .any(|word| {
+            if let Some(number) = word.strip_prefix('#') {
+                return !number.is_empty() && number.chars().all(|c| c.is_ascii_digit());
+            }
+            match word.split_once('-') {
+                Some((key, number)) => {
+                    key.len() >= 2
+                        && key.starts_with(|c: char| c.is_ascii_uppercase())
+                        && key
+                            .chars()
+                            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                        && !number.is_empty()
+                        && number.chars().all(|c| c.is_ascii_digit())
+                }
+                None => false,
+            }
+        })
+}
@@ -0,0 +1,383 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+/// Where `rcommit template init` writes, and where a template is picked up
+/// from when none is configured: first in the repository, then per user.
+pub const REPO_TEMPLATE_PATH: &str = ".rcommit/template.txt";
+
+/// Prompt used when no template file is configured or found.
+pub const DEFAULT_TEMPLATE: &str = r#"Create a conventional commit message for the following changes.
+Use one of these types: {{types}}.
+Allowed scopes (leave the scope out if none fits or the list is empty): {{scopes}}
+Scopes of the changed paths (use it as the scope when there is exactly one): {{inferred_scopes}}
+Write the message in {{language}}.
+Current branch: {{branch}}
+Ticket (add it as a `Refs:` footer when present): {{ticket}}
+Recent commit subjects, for style:
+{{recent_commits}}
+{{style}}
+Some context about the changes: {{context}}
+Changed files:
+{{files}}
+File changes:
+{{input}}
+"#;
+
+/// Every variable a template may reference, with a short description.
+pub const VARIABLES: &[(&str, &str)] = &[
+    (
+        "input",
+        "the staged diff (or per-file summaries for large diffs)",
+    ),
+    ("context", "the --context text"),
+    ("branch", "the current branch name"),
+    ("files", "the changed files, one per line with their status"),
+    ("recent_commits", "subjects of the latest commits on HEAD"),
+    (
+        "style",
+        "style features and example messages from --style-from-history (may be empty)",
+    ),
+    ("types", "allowed commit types, comma-separated"),
+    ("scopes", "allowed scopes, comma-separated (may be empty)"),
+    (
+        "inferred_scopes",
+        "scopes of the changed paths from scope rules or workspace members (may be empty)",
+    ),
+    (
+        "ticket",
+        "ticket id from --ticket or the branch name (may be empty)",
+    ),
+    ("language", "language the message should be written in"),
+];
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Io(PathBuf, io::Error),
+    UnknownVariable {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+    Unterminated {
+        path: PathBuf,
+        line: usize,
+    },
+    MissingInput(PathBuf),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Io(path, err) => {
+                write!(f, "could not read template {}: {}", path.display(), err)
+            }
+            TemplateError::UnknownVariable { path, line, name } => {
+                let known: Vec<&str> = VARIABLES.iter().map(|(name, _)| *name).collect();
+                write!(
+                    f,
+                    "{}:{}: unknown template variable `{{{{{}}}}}` (known variables: {})",
+                    path.display(),
+                    line,
+                    name,
+                    known.join(", ")
+                )
+            }
+            TemplateError::Unterminated { path, line } => {
+                write!(f, "{}:{}: `{{{{` is never closed", path.display(), line)
+            }
+            TemplateError::MissingInput(path) => write!(
+                f,
+                "{}: the template must include `{{{{input}}}}` for the diff",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Values substituted into the template.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars {
+    pub input: String,
+    pub context: String,
+    pub branch: String,
+    pub files: String,
+    pub recent_commits: String,
+    pub style: String,
+    pub types: String,
+    pub scopes: String,
+    pub inferred_scopes: String,
+    pub ticket: String,
+    pub language: String,
+}
+
+impl TemplateVars {
+    fn get(&self, name: &str) -> &str {
+        match name {
+            "input" => &self.input,
+            "context" => &self.context,
+            "branch" => &self.branch,
+            "files" => &self.files,
+            "recent_commits" => &self.recent_commits,
+            "style" => &self.style,
+            "types" => &self.types,
+            "scopes" => &self.scopes,
+            "inferred_scopes" => &self.inferred_scopes,
+            "ticket" => &self.ticket,
+            "language" => &self.language,
+            _ => "",
+        }
+    }
+}
+
+/// A validated prompt template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    text: String,
+}
+
+impl Template {
+    /// Substitutes every `{{name}}` in a single pass, so text inside the
+    /// values (a diff that itself contains `{{branch}}`, say) is left alone.
+    pub fn render(&self, vars: &TemplateVars) -> String {
+        let mut output = String::with_capacity(self.text.len() + vars.input.len());
+        let mut rest = self.text.as_str();
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            // `load` has already checked that every placeholder is closed.
+            let end = after.find("}}").unwrap_or(after.len());
+            output.push_str(vars.get(&after[..end]));
+            rest = after.get(end + 2..).unwrap_or("");
+        }
+        output.push_str(rest);
+        output
+    }
+}
+
+/// Prefixes that mark an issue number in a branch name, as in `fix/gh-42`.
+const ISSUE_PREFIXES: &[&str] = &["issue-", "issues-", "gh-"];
+
+/// Finds a ticket id in a branch name: a Jira-style key such as `ABC-123`
+/// at the start of a path segment, or an issue number after `issue-` or
+/// `gh-`, as in `fix/issue-123-crash`, which becomes `#123`. Keys need two
+/// letters and must open a segment, so `X11-crash` or `decode-UTF-8` are
+/// not tickets. Bare numbers are left alone, as in `release/2024` they are
+/// rarely issues.
+pub fn ticket_from_branch(branch: &str) -> Option<String> {
+    for segment in branch.split('/') {
+        let Some((key, rest)) = segment.split_once('-') else {
+            continue;
+        };
+        let key_ok = key.starts_with(|c: char| c.is_ascii_uppercase())
+            && key.chars().filter(char::is_ascii_uppercase).count() >= 2
+            && key
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        let number: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let ends_word = !rest[number.len()..].starts_with(|c: char| c.is_ascii_alphanumeric());
+        if key_ok && !number.is_empty() && ends_word {
+            return Some(format!("{}-{}", key, number));
+        }
+    }
+
+    branch.split(['/', '_']).find_map(|segment| {
+        let segment = segment.to_ascii_lowercase();
+        let rest = ISSUE_PREFIXES
+            .iter()
+            .find_map(|prefix| segment.strip_prefix(prefix))?;
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let ends_word = rest[digits.len()..].is_empty() || rest[digits.len()..].starts_with('-');
+        (!digits.is_empty() && ends_word).then(|| format!("#{}", digits))
+    })
+}
+
+/// Resolves which template file to use: the configured one, else
+/// `.rcommit/template.txt` in the repository, else `template.txt` next to
+/// the user config. `None` means the built-in template.
+pub fn find_template(configured: Option<&Path>, repo_root: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = configured {
+        return Some(path.to_path_buf());
+    }
+    repo_root
+        .map(|root| root.join(REPO_TEMPLATE_PATH))
+        .into_iter()
+        .chain(user_template_path())
+        .find(|path| path.is_file())
+}
+
+pub fn user_template_path() -> Option<PathBuf> {
+    config::user_config_path().map(|path| path.with_file_name("template.txt"))
+}
+
+/// Loads and validates a template, or returns the built-in one for `None`.
+pub fn load(path: Option<&Path>) -> Result<Template, TemplateError> {
+    let text = match path {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .map_err(|err| TemplateError::Io(path.to_path_buf(), err))?;
+            normalize(&text, path)?
+        }
+        None => DEFAULT_TEMPLATE.to_string(),
+    };
+    Ok(Template { text })
+}
+
+/// Checks every `{{ name }}` against [`VARIABLES`] and rewrites it as
+/// `{{name}}`, the spelling [`Template::render`] looks up.
+fn normalize(text: &str, path: &Path) -> Result<String, TemplateError> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut has_input = false;
+    while let Some(start) = rest.find("{{") {
+        let line = text[..text.len() - rest.len() + start]
+            .matches('\n')
+            .count()
+            + 1;
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| TemplateError::Unterminated {
+                path: path.to_path_buf(),
+                line,
+            })?;
+        let name = after[..end].trim();
+        if !VARIABLES.iter().any(|(known, _)| *known == name) {
+            return Err(TemplateError::UnknownVariable {
+                path: path.to_path_buf(),
+                line,
+                name: name.to_string(),
+            });
+        }
+        has_input |= name == "input";
+        output.push_str(&format!("{{{{{}}}}}", name));
+        rest = &after[end + 2..];
+    }
+    output.push_str(rest);
+
+    if !has_input {
+        return Err(TemplateError::MissingInput(path.to_path_buf()));
+    }
+    Ok(output)
+}
+
+/// Writes the built-in template to `path` so a team can start from it.
+pub fn init(path: &Path, force: bool) -> io::Result<()> {
+    if path.exists() && !force {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists (use --force to overwrite it)",
+                path.display()
+            ),
+        ));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut text = String::from(DEFAULT_TEMPLATE);
+    text.push('\n');
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> &'static Path {
+        Path::new("template.txt")
+    }
+
+    #[test]
+    fn finds_jira_keys_at_the_start_of_a_segment() {
+        let ticket = |branch| ticket_from_branch(branch);
+        assert_eq!(ticket("feature/ABC-123-login"), Some("ABC-123".to_string()));
+        assert_eq!(ticket("ABC-7"), Some("ABC-7".to_string()));
+        assert_eq!(ticket("fix/PROJ2-45_crash"), Some("PROJ2-45".to_string()));
+        assert_eq!(ticket("feature/abc-123-login"), None);
+        assert_eq!(ticket("feature/A-1"), None);
+        assert_eq!(ticket("fix/X11-crash"), None);
+        assert_eq!(ticket("fix/decode-UTF-8-names"), None);
+        assert_eq!(ticket("feat/use-SHA-256-digests"), None);
+        assert_eq!(ticket("feat/support-HTTP-2"), None);
+        assert_eq!(ticket("fix/parse-ISO-8601-dates"), None);
+        assert_eq!(ticket("feature/ABC-12abc"), None);
+    }
+
+    #[test]
+    fn issue_numbers_need_an_explicit_prefix() {
+        let ticket = |branch| ticket_from_branch(branch);
+        assert_eq!(ticket("fix/issue-123-crash"), Some("#123".to_string()));
+        assert_eq!(ticket("gh-42"), Some("#42".to_string()));
+        assert_eq!(ticket("fix/GH-42-typo"), Some("GH-42".to_string()));
+        assert_eq!(ticket("bugfix/issues-9_typo"), Some("#9".to_string()));
+        assert_eq!(ticket("release/2024"), None);
+        assert_eq!(ticket("hotfix/3-typo"), None);
+        assert_eq!(ticket("fix/issue-12abc"), None);
+        assert_eq!(ticket("fix/tissue-12"), None);
+        assert_eq!(ticket("main"), None);
+    }
+
+    #[test]
+    fn normalize_rewrites_placeholders_without_spaces() {
+        let text = "Diff:\n{{ input }}\nBranch: {{branch}}, ticket {{  ticket }}";
+        assert_eq!(
+            normalize(text, path()).unwrap(),
+            "Diff:\n{{input}}\nBranch: {{branch}}, ticket {{ticket}}"
+        );
+    }
+
+    #[test]
+    fn normalize_reports_unknown_variables_with_their_line() {
+        match normalize("{{input}}\n\n{{ author }}", path()) {
+            Err(TemplateError::UnknownVariable { line, name, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(name, "author");
+            }
+            other => panic!("expected an unknown variable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn normalize_reports_unterminated_placeholders() {
+        match normalize("{{input}}\n{{branch", path()) {
+            Err(TemplateError::Unterminated { line, .. }) => assert_eq!(line, 2),
+            other => panic!("expected an unterminated placeholder, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn normalize_requires_the_input() {
+        assert!(matches!(
+            normalize("Branch: {{branch}}", path()),
+            Err(TemplateError::MissingInput(_))
+        ));
+    }
+
+    #[test]
+    fn render_leaves_placeholders_inside_values_alone() {
+        let template = Template {
+            text: normalize("{{branch}}: {{input}}", path()).unwrap(),
+        };
+        let vars = TemplateVars {
+            input: "+ let x = \"{{branch}}\";".to_string(),
+            branch: "main".to_string(),
+            ..TemplateVars::default()
+        };
+        assert_eq!(template.render(&vars), "main: + let x = \"{{branch}}\";");
+    }
+
+    #[test]
+    fn the_built_in_template_is_valid() {
+        assert_eq!(
+            normalize(DEFAULT_TEMPLATE, path()).unwrap(),
+            DEFAULT_TEMPLATE
+        );
+    }
+}